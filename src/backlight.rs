@@ -15,11 +15,16 @@
 // with this program; if not, write to the Free Software Foundation, Inc.,
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 
+use std::time::Duration;
+
 use anyhow::Result;
+use async_std::future::timeout;
 use async_std::prelude::*;
 use async_std::sync::Arc;
 use async_std::task::spawn;
+use chrono::{Local, NaiveTime};
 use log::warn;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 mod demo_mode;
 
@@ -30,9 +35,39 @@ use demo_mode::{Backlight as SysBacklight, Brightness, SysClass};
 use sysfs_class::{Backlight as SysBacklight, Brightness, SysClass};
 
 use crate::broker::{BrokerBuilder, Topic};
+use crate::time_of_day::TimeOfDay;
+
+// Re-evaluate the night schedule this often, even if no setting changed.
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Use a different backlight brightness during a time span of the day
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct NightSchedule {
+    pub enabled: bool,
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+    pub brightness: f32,
+}
+
+fn valid_brightness(brightness: f32) -> bool {
+    (0.0..=1.0).contains(&brightness)
+}
+
+impl NightSchedule {
+    /// Is the schedule enabled and is the given time between start and end?
+    fn is_active(&self, now: NaiveTime) -> bool {
+        self.enabled && TimeOfDay::span_contains(self.start, self.end, now)
+    }
+}
 
 pub struct Backlight {
     pub brightness: Arc<Topic<f32>>,
+    pub default_brightness: Arc<Topic<f32>>,
+    pub dim_brightness: Arc<Topic<f32>>,
+    pub dim_timeout: Arc<Topic<u64>>,
+    pub night_schedule: Arc<Topic<NightSchedule>>,
+    pub idle: Arc<Topic<bool>>,
+    pub screensaver: Arc<Topic<bool>>,
 }
 
 impl Backlight {
@@ -62,6 +97,144 @@ impl Backlight {
             }
         });
 
-        Ok(Self { brightness })
+        let this = Self {
+            brightness,
+            default_brightness: bb.setting(
+                "/v1/tac/display/backlight/default_brightness",
+                1.0,
+                |b| valid_brightness(*b),
+            ),
+            dim_brightness: bb.setting("/v1/tac/display/backlight/dim_brightness", 0.1, |b| {
+                valid_brightness(*b)
+            }),
+            dim_timeout: bb.topic(
+                "/v1/tac/display/backlight/dim_timeout",
+                true,
+                true,
+                true,
+                Some(0),
+                1,
+            ),
+            night_schedule: bb.setting(
+                "/v1/tac/display/backlight/night_schedule",
+                NightSchedule {
+                    enabled: false,
+                    start: TimeOfDay::new(22, 0),
+                    end: TimeOfDay::new(6, 0),
+                    brightness: 0.1,
+                },
+                |schedule| valid_brightness(schedule.brightness),
+            ),
+            idle: bb.topic_ro("/v1/tac/display/backlight/idle", Some(false)),
+            screensaver: Topic::anonymous(Some(false)),
+        };
+
+        this.handle_policy();
+
+        Ok(this)
+    }
+
+    /// Select the backlight brightness based on the current policy settings
+    ///
+    /// The brightness is set to `dim_brightness` while the UI is idle or
+    /// shows the screensaver, to the brightness of the night schedule if
+    /// it is active and to `default_brightness` otherwise.
+    /// The brightness can still be overridden via the brightness topic.
+    /// The override lasts until the policy selects a different brightness.
+    fn handle_policy(&self) {
+        let brightness = self.brightness.clone();
+        let default_brightness = self.default_brightness.clone();
+        let dim_brightness = self.dim_brightness.clone();
+        let night_schedule = self.night_schedule.clone();
+        let idle = self.idle.clone();
+        let screensaver = self.screensaver.clone();
+
+        let (default_brightness_events, _) = default_brightness.clone().subscribe_unbounded();
+        let (dim_brightness_events, _) = dim_brightness.clone().subscribe_unbounded();
+        let (night_schedule_events, _) = night_schedule.clone().subscribe_unbounded();
+        let (idle_events, _) = idle.clone().subscribe_unbounded();
+        let (screensaver_events, _) = screensaver.clone().subscribe_unbounded();
+
+        let mut changes = default_brightness_events
+            .map(|_| ())
+            .merge(dim_brightness_events.map(|_| ()))
+            .merge(night_schedule_events.map(|_| ()))
+            .merge(idle_events.map(|_| ()))
+            .merge(screensaver_events.map(|_| ()));
+
+        spawn(async move {
+            let mut prev = None;
+
+            loop {
+                if let Ok(None) = timeout(SCHEDULE_INTERVAL, changes.next()).await {
+                    break;
+                }
+
+                let dimmed =
+                    idle.try_get().unwrap_or(false) || screensaver.try_get().unwrap_or(false);
+
+                let night = night_schedule
+                    .try_get()
+                    .filter(|schedule| schedule.is_active(Local::now().time()));
+
+                let target = if dimmed {
+                    dim_brightness.try_get()
+                } else if let Some(schedule) = night {
+                    Some(schedule.brightness)
+                } else {
+                    default_brightness.try_get()
+                };
+
+                // Only touch the brightness if the policy changed its mind,
+                // so that values set via the API are kept until then.
+                if let Some(target) = target.filter(|t| Some(*t) != prev) {
+                    brightness.set(target);
+                    prev = Some(target);
+                }
+            }
+        });
+    }
+
+    /// Mark the UI as idle if there was no event on `activity` for
+    /// `dim_timeout` seconds and as not idle once an event arrives
+    ///
+    /// A `dim_timeout` of zero disables dimming due to inactivity.
+    pub fn dim_on_inactivity<E>(&self, activity: &Arc<Topic<E>>)
+    where
+        E: Serialize + DeserializeOwned + Send + Sync + Clone + 'static,
+    {
+        let dim_timeout = self.dim_timeout.clone();
+        let idle = self.idle.clone();
+
+        let (activity_events, _) = activity.clone().subscribe_unbounded();
+        let (dim_timeout_events, _) = dim_timeout.clone().subscribe_unbounded();
+
+        // true for user input, false for changes of the timeout setting
+        let mut events = activity_events
+            .map(|_| true)
+            .merge(dim_timeout_events.map(|_| false));
+
+        spawn(async move {
+            loop {
+                let secs = dim_timeout.try_get().unwrap_or(0);
+
+                let ev = if secs == 0 {
+                    Ok(events.next().await)
+                } else {
+                    timeout(Duration::from_secs(secs), events.next()).await
+                };
+
+                match ev {
+                    Ok(None) => break,
+                    Ok(Some(true)) => idle.set_if_changed(false),
+                    Ok(Some(false)) => {
+                        if dim_timeout.try_get() == Some(0) {
+                            idle.set_if_changed(false)
+                        }
+                    }
+                    Err(_) => idle.set_if_changed(true),
+                }
+            }
+        });
     }
 }
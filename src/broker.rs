@@ -15,15 +15,23 @@
 // with this program; if not, write to the Free Software Foundation, Inc.,
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 
+use std::fmt::Debug;
+
+use async_std::prelude::*;
 use async_std::sync::Arc;
+use async_std::task::spawn;
+use log::warn;
 use serde::{de::DeserializeOwned, Serialize};
 
+use crate::config::PersistenceConfig;
+
 mod mqtt_conn;
 mod persistence;
 mod rest;
 mod topic;
 
 pub use mqtt_conn::TopicName;
+pub use persistence::Persistence;
 pub use topic::{AnySubscriptionHandle, AnyTopic, Native, SubscriptionHandle, Topic};
 
 pub struct BrokerBuilder {
@@ -110,14 +118,65 @@ impl BrokerBuilder {
         self.topic(path, false, true, false, initial, 1)
     }
 
+    /// Register a persistent setting that can only be set to valid values
+    ///
+    /// This uses a read only and a write only topic with the same path
+    /// (see `topic()`). Values written via the API end up in the write only
+    /// topic and are only forwarded to the persistent read only topic if
+    /// `is_valid` accepts them. Invalid values are already rejected when
+    /// they are written via the API (or imported via the settings).
+    pub fn setting<E>(
+        &mut self,
+        path: &'static str,
+        initial: E,
+        is_valid: fn(&E) -> bool,
+    ) -> Arc<Topic<E>>
+    where
+        E: Serialize + DeserializeOwned + Sync + Send + Clone + Debug + 'static,
+    {
+        let setting = self.topic(path, true, false, true, Some(initial), 1);
+        let request =
+            Arc::new(Topic::new(path, false, true, false, None, 1).with_validator(is_valid));
+        self.topics.push(request.clone());
+
+        let (mut requests, _) = request.subscribe_unbounded();
+        let setting_task = setting.clone();
+
+        spawn(async move {
+            while let Some(req) = requests.next().await {
+                if is_valid(&req) {
+                    setting_task.set(req);
+                } else {
+                    warn!("Ignoring invalid value {req:?} for {path}");
+                }
+            }
+        });
+
+        setting
+    }
+
     /// Finish building the broker
     ///
-    /// This consumes the builder so that no new topics can be registered
-    pub fn build(self, server: &mut tide::Server<()>) {
+    /// This consumes the builder so that no new topics can be registered.
+    /// Returns all registered topics, e.g. to take snapshots of them,
+    /// and the handle to write the persistent topics on shutdown.
+    pub fn build(
+        mut self,
+        server: &mut tide::Server<()>,
+        persistence_config: &PersistenceConfig,
+    ) -> (Arc<Vec<Arc<dyn AnyTopic>>>, Persistence) {
+        // Problems encountered while loading the persistent topics,
+        // like a broken state file or topics that no longer exist.
+        let warnings = self.topic_ro("/v1/tac/persistence/warnings", Some(Vec::new()));
+        let stats = self.topic_ro("/v1/tac/persistence/stats", Some(Default::default()));
+
         let topics = Arc::new(self.topics);
 
-        persistence::register(topics.clone());
+        let persistence =
+            persistence::register(topics.clone(), warnings, stats, persistence_config);
         rest::register(server, topics.clone());
-        mqtt_conn::register(server, topics);
+        mqtt_conn::register(server, topics.clone());
+
+        (topics, persistence)
     }
 }
@@ -15,25 +15,50 @@
 // with this program; if not, write to the Free Software Foundation, Inc.,
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 
-use std::fs::{create_dir, rename, File};
-use std::path::Path;
+use std::fs::{copy, create_dir_all, read, rename, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Result};
-use async_std::channel::{unbounded, Receiver};
+use async_std::channel::{unbounded, Receiver, Sender};
+use async_std::future::timeout;
 use async_std::prelude::*;
 use async_std::sync::Arc;
-use async_std::task::spawn;
-use log::{error, info};
+use async_std::task::{spawn, JoinHandle};
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::{from_reader, to_writer_pretty, Map, Value};
 
-use super::{AnyTopic, TopicName};
-
-#[cfg(feature = "demo_mode")]
-const PERSISTENCE_PATH: &str = "demo_files/srv/tacd/state.json";
+use super::{AnyTopic, Topic, TopicName};
+use crate::config::PersistenceConfig;
+use crate::measurement::Timestamp;
+
+/// Convert the topics in a state file from one format version to the next
+///
+/// `MIGRATIONS[0]` converts from version 1 to version 2, `MIGRATIONS[1]`
+/// from version 2 to version 3 and so on.
+/// Adding a migration bumps the format version written by the tacd.
+type Migration = fn(&mut Map<String, Value>);
+
+const MIGRATIONS: &[Migration] = &[];
+
+// The number of known-good state files to keep around
+const BACKUP_COUNT: usize = 3;
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct WriteStats {
+    /// The number of changes to persistent topics
+    pub changes: u64,
+    /// The number of times the state file was written
+    pub writes: u64,
+    pub failed_writes: u64,
+    pub last_write: Option<Timestamp>,
+}
 
-#[cfg(not(feature = "demo_mode"))]
-const PERSISTENCE_PATH: &str = "/srv/tacd/state.json";
+enum Event {
+    Changed(TopicName),
+    Shutdown,
+}
 
 #[derive(Serialize, Deserialize)]
 struct PersistenceFile {
@@ -41,46 +66,211 @@ struct PersistenceFile {
     persistent_topics: Map<String, Value>,
 }
 
-fn load(topics: &[Arc<dyn AnyTopic>]) -> Result<()> {
-    let path = Path::new(PERSISTENCE_PATH);
+fn format_version(migrations: &[Migration]) -> u64 {
+    migrations.len() as u64 + 1
+}
 
-    if !path.is_file() {
-        info!(
-            "State file at \"{}\" does not yet exist. Using defaults",
-            PERSISTENCE_PATH
-        );
-        return Ok(());
+fn backup_path(state_file: &str, num: usize) -> PathBuf {
+    PathBuf::from(format!("{state_file}.{num}"))
+}
+
+fn quarantine_path(state_file: &str) -> PathBuf {
+    Path::new(state_file).with_extension("quarantine.json")
+}
+
+/// Write a file atomically by writing to a temporary file first
+fn write_json<T: Serialize>(path: &Path, content: &T) -> Result<()> {
+    let path_tmp = {
+        let mut path_tmp = path.to_owned();
+        assert!(path_tmp.set_extension("tmp"));
+        path_tmp
+    };
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        create_dir_all(parent)?;
     }
 
+    {
+        let fd = File::create(&path_tmp)?;
+        to_writer_pretty(&fd, content)?;
+        fd.sync_all()?;
+    }
+
+    rename(path_tmp, path)?;
+
+    Ok(())
+}
+
+fn read_file(path: &Path) -> Result<PersistenceFile> {
     let file: PersistenceFile = from_reader(File::open(path)?)?;
 
-    if file.format_version != 1 {
-        bail!("Unkown state file version: {}", file.format_version);
+    if file.format_version == 0 {
+        bail!("Invalid state file version: 0");
     }
 
+    Ok(file)
+}
+
+/// Bring the topics in a state file to the current format version
+fn migrate(
+    file: PersistenceFile,
+    migrations: &[Migration],
+    warnings: &mut Vec<String>,
+) -> Map<String, Value> {
+    let current = format_version(migrations);
     let mut content = file.persistent_topics;
 
+    if file.format_version > current {
+        // This happens after a downgrade of the tacd. Topics that still
+        // exist in this version will likely still have a compatible format.
+        warnings.push(format!(
+            "State file has format version {}, but only version {current} is supported. Loading it anyways",
+            file.format_version
+        ));
+    } else {
+        for migration in &migrations[(file.format_version - 1) as usize..] {
+            migration(&mut content);
+        }
+    }
+
+    content
+}
+
+/// Set the persistent topics to the values in `content`
+///
+/// Returns the entries that do not belong to a (compatible) topic.
+fn apply(topics: &[Arc<dyn AnyTopic>], mut content: Map<String, Value>) -> Map<String, Value> {
+    let mut rejected = Map::new();
+
     for topic in topics.iter().filter(|t| t.persistent()) {
         let path: &str = topic.path();
 
         if let Some(value) = content.remove(path) {
-            topic.set_from_json_value(value)?;
+            if let Err(e) = topic.set_from_json_value(value.clone()) {
+                error!("Failed to restore persistent topic \"{path}\": {e}");
+                rejected.insert(path.to_string(), value);
+            }
         }
     }
 
-    if !content.is_empty() {
-        error!("State file contained extra keys:");
-        for topic_name in content.keys() {
-            error!(" - {topic_name}");
-        }
+    rejected.extend(content);
+    rejected
+}
+
+/// Move values that can not be restored to a side file instead of losing them
+fn quarantine(state_file: &str, entries: Map<String, Value>) -> Result<()> {
+    let path = quarantine_path(state_file);
+
+    let mut quarantined: Map<String, Value> = File::open(&path)
+        .ok()
+        .and_then(|fd| from_reader(fd).ok())
+        .unwrap_or_default();
+
+    quarantined.extend(entries);
+
+    write_json(&path, &quarantined)
+}
+
+/// Keep copies of the last few state files that could be loaded
+fn rotate_backups(state_file: &str) -> Result<()> {
+    let newest = backup_path(state_file, 1);
+
+    // Do not push out older backups if nothing changed since the last boot
+    if read(&newest).ok() == Some(read(state_file)?) {
+        return Ok(());
+    }
 
-        bail!("Left over topics in state file");
+    for num in (1..BACKUP_COUNT).rev() {
+        let src = backup_path(state_file, num);
+
+        if src.is_file() {
+            rename(src, backup_path(state_file, num + 1))?;
+        }
     }
 
+    copy(state_file, newest)?;
+
     Ok(())
 }
 
-fn save(topics: &Arc<Vec<Arc<dyn AnyTopic>>>) -> Result<()> {
+/// Load the state file (or one of its backups) into the persistent topics
+///
+/// Returns a list of problems encountered along the way.
+fn load(topics: &[Arc<dyn AnyTopic>], state_file: &str) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let path = Path::new(state_file);
+
+    if !path.is_file() {
+        info!(
+            "State file at \"{}\" does not yet exist. Using defaults",
+            state_file
+        );
+        return warnings;
+    }
+
+    let file = match read_file(path) {
+        Ok(file) => {
+            if let Err(e) = rotate_backups(state_file) {
+                warnings.push(format!("Failed to back up the state file: {e}"));
+            }
+
+            Some(file)
+        }
+        Err(e) => {
+            warnings.push(format!("Failed to read state file: {e}"));
+
+            // Keep the broken file around for inspection
+            let corrupt = path.with_extension("corrupt.json");
+            if let Err(e) = rename(path, &corrupt) {
+                warnings.push(format!("Failed to move the broken state file: {e}"));
+            }
+
+            (1..=BACKUP_COUNT)
+                .map(|num| backup_path(state_file, num))
+                .filter(|backup| backup.is_file())
+                .find_map(|backup| match read_file(&backup) {
+                    Ok(file) => {
+                        warnings.push(format!("Restored state from {}", backup.display()));
+                        Some(file)
+                    }
+                    Err(e) => {
+                        warnings.push(format!("Failed to read {}: {e}", backup.display()));
+                        None
+                    }
+                })
+        }
+    };
+
+    let file = match file {
+        Some(file) => file,
+        None => {
+            warnings.push("Using the default settings".to_string());
+            return warnings;
+        }
+    };
+
+    let content = migrate(file, MIGRATIONS, &mut warnings);
+    let rejected = apply(topics, content);
+
+    if !rejected.is_empty() {
+        let keys: Vec<&str> = rejected.keys().map(|k| k.as_str()).collect();
+        let quarantine_file = quarantine_path(state_file);
+
+        warnings.push(format!(
+            "Moved unknown or invalid topics to {}: {}",
+            quarantine_file.display(),
+            keys.join(", ")
+        ));
+
+        if let Err(e) = quarantine(state_file, rejected) {
+            warnings.push(format!("Failed to quarantine topics: {e}"));
+        }
+    }
+
+    warnings
+}
+
+fn save(topics: &Arc<Vec<Arc<dyn AnyTopic>>>, state_file: &str) -> Result<()> {
     let persistent_topics = {
         let mut map = Map::new();
 
@@ -101,60 +291,313 @@ fn save(topics: &Arc<Vec<Arc<dyn AnyTopic>>>) -> Result<()> {
     };
 
     let file_contents = PersistenceFile {
-        format_version: 1,
+        format_version: format_version(MIGRATIONS),
         persistent_topics,
     };
 
-    let path = Path::new(PERSISTENCE_PATH);
-    let parent = path.parent().unwrap();
+    write_json(Path::new(state_file), &file_contents)
+}
 
-    let path_tmp = {
-        let mut path_tmp = path.to_owned();
-        assert!(path_tmp.set_extension("tmp"));
-        path_tmp
+/// How long to wait for further changes before writing the state file
+///
+/// Every change restarts the `delay`, but the file is written at most
+/// `max_delay` after the first change.
+fn write_timeout(first_change: Instant, delay: Duration, max_delay: Duration) -> Duration {
+    delay.min(max_delay.saturating_sub(first_change.elapsed()))
+}
+
+/// Write the state file once the persistent topics settled down
+///
+/// Writing the whole file on every single change would unnecessarily wear
+/// the eMMC, so changes that happen in quick succession are coalesced.
+async fn save_on_change(
+    topics: Arc<Vec<Arc<dyn AnyTopic>>>,
+    stats: Arc<Topic<WriteStats>>,
+    config: PersistenceConfig,
+    change_ev: Receiver<(TopicName, Arc<[u8]>)>,
+    shutdown_ev: Receiver<()>,
+) {
+    let changes = change_ev.map(|(topic_name, _)| Event::Changed(topic_name));
+    let shutdown = shutdown_ev.map(|_| Event::Shutdown);
+    let mut events = changes.merge(shutdown);
+
+    let mut first_change: Option<Instant> = None;
+
+    let write = |first_change: &mut Option<Instant>| {
+        if first_change.take().is_none() {
+            return;
+        }
+
+        let res = save(&topics, &config.state_file);
+
+        stats.modify(|prev| {
+            let mut stats = prev.unwrap_or_default();
+
+            match &res {
+                Ok(()) => {
+                    stats.writes += 1;
+                    stats.last_write = Some(Timestamp::now());
+                }
+                Err(e) => {
+                    error!("Failed to save state file: {e}");
+                    stats.failed_writes += 1;
+                }
+            }
+
+            Some(stats)
+        });
     };
 
-    if !parent.exists() {
-        create_dir(parent)?;
-    }
+    loop {
+        let event = match first_change {
+            Some(first) => {
+                let wait = write_timeout(first, config.write_delay(), config.max_write_delay());
+
+                match timeout(wait, events.next()).await {
+                    Ok(event) => event,
+                    Err(_) => {
+                        write(&mut first_change);
+                        continue;
+                    }
+                }
+            }
+            None => events.next().await,
+        };
 
-    {
-        let fd = File::create(&path_tmp)?;
-        to_writer_pretty(&fd, &file_contents)?;
-        fd.sync_all()?;
+        match event {
+            Some(Event::Changed(topic_name)) => {
+                let topic_name = String::from_utf8_lossy(topic_name.as_bytes());
+
+                info!(
+                    "Persistent topic \"{}\" has changed. Saving to disk soon",
+                    topic_name
+                );
+
+                stats.modify(|prev| {
+                    let mut stats = prev.unwrap_or_default();
+                    stats.changes += 1;
+                    Some(stats)
+                });
+
+                first_change.get_or_insert_with(Instant::now);
+            }
+            Some(Event::Shutdown) | None => {
+                write(&mut first_change);
+                break;
+            }
+        }
     }
+}
 
-    rename(path_tmp, path)?;
+/// The task writing the state file, which has to be shut down before the
+/// tacd exits to not lose recent changes.
+pub struct Persistence {
+    shutdown: Sender<()>,
+    task: JoinHandle<()>,
+}
 
-    Ok(())
+impl Persistence {
+    /// Write pending changes to the state file and stop watching for new ones
+    pub async fn shutdown(self) {
+        // The task only exits on its own if all topic subscriptions went
+        // away, in which case there is nothing left to write anyways.
+        let _ = self.shutdown.send(()).await;
+        self.task.await;
+    }
 }
 
-async fn save_on_change(
+pub fn register(
     topics: Arc<Vec<Arc<dyn AnyTopic>>>,
-    mut change_ev: Receiver<(TopicName, Arc<[u8]>)>,
-) -> Result<()> {
-    while let Some((topic_name, _)) = change_ev.next().await {
-        let topic_name = String::from_utf8_lossy(topic_name.as_bytes());
+    warnings: Arc<Topic<Vec<String>>>,
+    stats: Arc<Topic<WriteStats>>,
+    config: &PersistenceConfig,
+) -> Persistence {
+    let config = config.clone();
+    let state_file = config.state_file.as_str();
+
+    let load_warnings = load(&topics, state_file);
+
+    if !load_warnings.is_empty() {
+        for warning in load_warnings.iter() {
+            warn!("{warning}");
+        }
 
-        info!(
-            "Persistent topic \"{}\" has changed. Saving to disk",
-            topic_name
-        );
+        // Write a clean state file, so that the same problems are not
+        // encountered again on the next start.
+        if let Err(e) = save(&topics, state_file) {
+            error!("Failed to save state file: {e}");
+        }
 
-        save(&topics)?;
+        warnings.set(load_warnings);
     }
 
-    Ok(())
-}
-
-pub fn register(topics: Arc<Vec<Arc<dyn AnyTopic>>>) {
-    load(&topics).unwrap();
-
     let (tx, rx) = unbounded();
 
     for topic in topics.iter().filter(|t| t.persistent()).cloned() {
         topic.subscribe_as_bytes(tx.clone(), false);
     }
 
-    spawn(async move { save_on_change(topics, rx).await.unwrap() });
+    let (shutdown, shutdown_rx) = unbounded();
+
+    let task = spawn(save_on_change(topics, stats, config, rx, shutdown_rx));
+
+    Persistence { shutdown, task }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::time::{Duration, Instant};
+
+    use async_std::sync::Arc;
+    use serde_json::{json, Map, Value};
+
+    use super::{apply, load, migrate, write_timeout, Migration, PersistenceFile};
+    use crate::broker::{AnyTopic, Topic};
+
+    struct TestTopics {
+        number: Arc<Topic<u32>>,
+        text: Arc<Topic<String>>,
+        list: Vec<Arc<dyn AnyTopic>>,
+    }
+
+    fn topics() -> TestTopics {
+        let number = Arc::new(Topic::new("/v1/number", true, true, true, None, 1));
+        let text = Arc::new(Topic::new("/v1/text", true, true, true, None, 1));
+        let list: Vec<Arc<dyn AnyTopic>> = vec![number.clone(), text.clone()];
+
+        TestTopics { number, text, list }
+    }
+
+    fn content(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn migrations() {
+        fn rename(content: &mut Map<String, Value>) {
+            if let Some(v) = content.remove("/v1/old") {
+                content.insert("/v1/number".to_string(), v);
+            }
+        }
+
+        fn double(content: &mut Map<String, Value>) {
+            if let Some(v) = content.get_mut("/v1/number") {
+                *v = json!(v.as_u64().unwrap() * 2);
+            }
+        }
+
+        let migrations: &[Migration] = &[rename, double];
+        let mut warnings = Vec::new();
+
+        // A version 1 file goes through both migrations
+        let file = PersistenceFile {
+            format_version: 1,
+            persistent_topics: content(json!({"/v1/old": 21})),
+        };
+        let migrated = migrate(file, migrations, &mut warnings);
+        assert_eq!(migrated, content(json!({"/v1/number": 42})));
+
+        // A version 2 file only through the second one
+        let file = PersistenceFile {
+            format_version: 2,
+            persistent_topics: content(json!({"/v1/number": 21})),
+        };
+        let migrated = migrate(file, migrations, &mut warnings);
+        assert_eq!(migrated, content(json!({"/v1/number": 42})));
+        assert!(warnings.is_empty());
+
+        // Files from the future are loaded as they are
+        let file = PersistenceFile {
+            format_version: 4,
+            persistent_topics: content(json!({"/v1/number": 21})),
+        };
+        let migrated = migrate(file, migrations, &mut warnings);
+        assert_eq!(migrated, content(json!({"/v1/number": 21})));
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn unknown_topics() {
+        let TestTopics { number, text, list } = topics();
+
+        let rejected = apply(
+            &list,
+            content(json!({
+                "/v1/number": "not a number",
+                "/v1/text": "hello",
+                "/v1/removed": true,
+            })),
+        );
+
+        assert_eq!(
+            rejected,
+            content(json!({
+                "/v1/number": "not a number",
+                "/v1/removed": true,
+            }))
+        );
+        assert_eq!(number.try_get(), None);
+        assert_eq!(text.try_get().as_deref(), Some("hello"));
+    }
+
+    #[test]
+    fn write_coalescing() {
+        let delay = Duration::from_secs(1);
+        let max_delay = Duration::from_secs(10);
+        let now = Instant::now();
+
+        // Wait for the full delay after recent changes ...
+        assert_eq!(write_timeout(now, delay, max_delay), delay);
+
+        // ... but not for longer than the maximum delay after the first one
+        let wait = write_timeout(now - Duration::from_millis(9500), delay, max_delay);
+        assert!(wait <= Duration::from_millis(500));
+
+        let wait = write_timeout(now - Duration::from_secs(20), delay, max_delay);
+        assert_eq!(wait, Duration::ZERO);
+    }
+
+    #[test]
+    fn backup_fallback() {
+        let dir = std::env::temp_dir().join(format!("tacd-persistence-{}", std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+
+        let state_file = dir.join("state.json");
+        let state_file = state_file.to_str().unwrap();
+
+        let good = r#"{"format_version": 1, "persistent_topics": {"/v1/number": 1}}"#;
+
+        // Loading a good file creates a backup
+        write(state_file, good).unwrap();
+        let TestTopics { number, list, .. } = topics();
+        assert!(load(&list, state_file).is_empty());
+        assert_eq!(number.try_get(), Some(1));
+        assert!(dir.join("state.json.1").is_file());
+
+        // A broken file is moved aside and the backup is used instead
+        write(state_file, "{\"format_version\": 1, \"persis").unwrap();
+        let TestTopics { number, list, .. } = topics();
+        let warnings = load(&list, state_file);
+        assert_eq!(number.try_get(), Some(1));
+        assert_eq!(warnings.len(), 2);
+        assert!(dir.join("state.corrupt.json").is_file());
+
+        // Unknown topics are moved to the quarantine file
+        write(
+            state_file,
+            r#"{"format_version": 1, "persistent_topics": {"/v1/number": 2, "/v1/gone": 3}}"#,
+        )
+        .unwrap();
+        let TestTopics { number, list, .. } = topics();
+        let warnings = load(&list, state_file);
+        assert_eq!(number.try_get(), Some(2));
+        assert_eq!(warnings.len(), 1);
+
+        let quarantined = std::fs::read_to_string(dir.join("state.quarantine.json")).unwrap();
+        assert!(quarantined.contains("/v1/gone"));
+
+        remove_dir_all(&dir).unwrap();
+    }
 }
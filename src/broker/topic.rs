@@ -23,7 +23,10 @@ use std::sync::{Arc, Mutex, Weak};
 use async_std::channel::{unbounded, Receiver, Sender, TrySendError};
 use async_std::prelude::*;
 
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{
+    de::{DeserializeOwned, Error},
+    Serialize,
+};
 
 use unique_token::Unique;
 
@@ -92,6 +95,9 @@ pub struct Topic<E> {
     web_writable: bool,
     persistent: bool,
     retained_length: usize,
+    /// Values written from the outside (e.g. via the API) are rejected
+    /// if this returns false
+    is_valid: Option<fn(&E) -> bool>,
     inner: Mutex<TopicInner<E>>,
 }
 
@@ -167,10 +173,27 @@ impl<E: Serialize + DeserializeOwned + Clone> Topic<E> {
             web_writable,
             persistent,
             retained_length,
+            is_valid: None,
             inner,
         }
     }
 
+    /// Only accept values written from the outside if `is_valid` returns true
+    pub(super) fn with_validator(mut self, is_valid: fn(&E) -> bool) -> Self {
+        self.is_valid = Some(is_valid);
+        self
+    }
+
+    /// Check a value written from the outside against the validator
+    fn validate(&self, msg: E) -> serde_json::Result<E> {
+        match self.is_valid {
+            Some(is_valid) if !is_valid(&msg) => {
+                Err(serde_json::Error::custom("value is out of the valid range"))
+            }
+            _ => Ok(msg),
+        }
+    }
+
     pub fn anonymous(initial: Option<E>) -> Arc<Self> {
         Arc::new(Self::new("/hidden", false, false, false, initial, 1))
     }
@@ -356,6 +379,8 @@ pub trait AnyTopic: Sync + Send {
     fn persistent(&self) -> bool;
     fn set_from_bytes(&self, msg: &[u8]) -> serde_json::Result<()>;
     fn set_from_json_value(&self, msg: serde_json::Value) -> serde_json::Result<()>;
+    /// Check if `set_from_json_value` would accept this value
+    fn check_json_value(&self, msg: &serde_json::Value) -> serde_json::Result<()>;
     fn subscribe_as_bytes(
         self: Arc<Self>,
         sender: Sender<(TopicName, Arc<[u8]>)>,
@@ -384,9 +409,9 @@ impl<E: Serialize + DeserializeOwned + Send + Sync + Clone + 'static> AnyTopic f
 
     /// De-Serialize a message and set the topic to the resulting value
     ///
-    /// Returns an Err if deserialization failed.
+    /// Returns an Err if deserialization or validation failed.
     fn set_from_bytes(&self, msg: &[u8]) -> serde_json::Result<()> {
-        let msg = serde_json::from_slice(msg)?;
+        let msg = self.validate(serde_json::from_slice(msg)?)?;
         self.set(msg);
         Ok(())
     }
@@ -395,13 +420,18 @@ impl<E: Serialize + DeserializeOwned + Send + Sync + Clone + 'static> AnyTopic f
     /// topic to it.
     ///
     /// Returns an Err if de-structuring the generic value into this specific
-    /// type failed.
+    /// type or validation failed.
     fn set_from_json_value(&self, msg: serde_json::Value) -> serde_json::Result<()> {
-        let msg = serde_json::from_value(msg)?;
+        let msg = self.validate(serde_json::from_value(msg)?)?;
         self.set(msg);
         Ok(())
     }
 
+    fn check_json_value(&self, msg: &serde_json::Value) -> serde_json::Result<()> {
+        self.validate(serde_json::from_value(msg.clone())?)
+            .map(|_| ())
+    }
+
     /// Add a queue to the list of subscribers for serialized values
     ///
     /// The Returned AnySubscriptionHandle can be used to remove the queue
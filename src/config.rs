@@ -0,0 +1,787 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::fmt;
+use std::fs::read_to_string;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[cfg(feature = "demo_mode")]
+mod defaults {
+    pub const CONFIG_FILE: &str = "demo_files/etc/tacd/tacd.yaml";
+    pub const LISTEN: &str = "[::]:8080";
+    pub const WEBUI_DIR: &str = "web/build";
+    pub const SRV_DIR: &str = "demo_files/srv/www";
+    pub const STATE_FILE: &str = "demo_files/srv/tacd/state.json";
+    pub const DIAGNOSTICS_DIR: &str = "demo_files/srv/diagnostics";
+    pub const BOOT_COUNT_FILE: &str = "demo_files/srv/tacd/boot_count.json";
+    pub const CHANNELS_DIR: &str = "demo_files/usr/share/tacd/update_channels";
+    pub const UPLOAD_DIR: &str = "demo_files/srv/tacd/upload";
+}
+
+#[cfg(not(feature = "demo_mode"))]
+mod defaults {
+    pub const CONFIG_FILE: &str = "/etc/tacd/tacd.yaml";
+    pub const LISTEN: &str = "[::]:80";
+    pub const WEBUI_DIR: &str = "/usr/share/tacd/webui";
+    pub const SRV_DIR: &str = "/srv/www";
+    pub const STATE_FILE: &str = "/srv/tacd/state.json";
+    pub const DIAGNOSTICS_DIR: &str = "/srv/diagnostics";
+    pub const BOOT_COUNT_FILE: &str = "/srv/tacd/boot_count.json";
+    pub const CHANNELS_DIR: &str = "/usr/share/tacd/update_channels";
+    pub const UPLOAD_DIR: &str = "/srv/tacd/upload";
+}
+
+use defaults::*;
+
+const USB_PORT_BASE: &str = "/sys/devices/platform/soc/5800d000.usb/usb1/1-1/1-1:1.0";
+
+// Polling more often than this would only waste CPU time
+const MIN_POLL_INTERVAL_MS: u64 = 100;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// The address and port to serve the web interface and API on
+    pub listen: String,
+    /// The directory containing the web interface
+    pub webui_dir: String,
+    /// The directory served (with directory listings) at /srv
+    pub srv_dir: String,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            listen: LISTEN.to_string(),
+            webui_dir: WEBUI_DIR.to_string(),
+            srv_dir: SRV_DIR.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersistenceConfig {
+    /// The file the values of persistent topics are stored in
+    pub state_file: String,
+    /// Wait for this long without further changes before writing the file
+    pub write_delay_ms: u64,
+    /// But do not wait longer than this after the first change
+    pub max_write_delay_ms: u64,
+    /// The directory diagnostic archives are saved to via the display
+    pub diagnostics_dir: String,
+    /// The file the number of boots of the TAC is counted in
+    pub boot_count_file: String,
+}
+
+impl PersistenceConfig {
+    pub fn write_delay(&self) -> Duration {
+        Duration::from_millis(self.write_delay_ms)
+    }
+
+    pub fn max_write_delay(&self) -> Duration {
+        Duration::from_millis(self.max_write_delay_ms)
+    }
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self {
+            state_file: STATE_FILE.to_string(),
+            write_delay_ms: 1000,
+            max_write_delay_ms: 10000,
+            diagnostics_dir: DIAGNOSTICS_DIR.to_string(),
+            boot_count_file: BOOT_COUNT_FILE.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpdateConfig {
+    /// The directory containing the update channel definitions
+    pub channels_dir: String,
+    /// The directory bundles uploaded via the web interface are stored in
+    pub upload_dir: String,
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        Self {
+            channels_dir: CHANNELS_DIR.to_string(),
+            upload_dir: UPLOAD_DIR.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct IoBusConfig {
+    /// The base URL of the lxa-iobus-server
+    pub server: String,
+    pub poll_interval_ms: u64,
+    /// Report a supply fault above this current (in A)
+    pub max_current: f32,
+    /// Report a supply fault below this voltage (in V) while powered
+    pub min_voltage: f32,
+}
+
+impl IoBusConfig {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+}
+
+impl Default for IoBusConfig {
+    fn default() -> Self {
+        Self {
+            server: "http://127.0.0.1:8080".to_string(),
+            poll_interval_ms: 1000,
+            max_current: 0.2,
+            min_voltage: 10.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct UsbHubConfig {
+    /// The sysfs directories of the three downstream ports of the hub
+    pub port1: String,
+    pub port2: String,
+    pub port3: String,
+    pub poll_interval_ms: u64,
+}
+
+impl UsbHubConfig {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+}
+
+impl Default for UsbHubConfig {
+    fn default() -> Self {
+        Self {
+            port1: format!("{USB_PORT_BASE}/1-1-port1"),
+            port2: format!("{USB_PORT_BASE}/1-1-port2"),
+            port3: format!("{USB_PORT_BASE}/1-1-port3"),
+            poll_interval_ms: 1000,
+        }
+    }
+}
+
+/// The services the tacd always knows about, as the web interface uses them
+pub const DEFAULT_SERVICES: &[(&str, &str)] = &[
+    ("network-manager", "NetworkManager.service"),
+    ("labgrid-exporter", "labgrid-exporter.service"),
+    ("lxa-iobus", "lxa-iobus.service"),
+];
+
+/// An additional systemd service that may be controlled via the API
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceConfig {
+    /// The name used in the topic path, e.g. "ser2net"
+    pub name: String,
+    /// The name of the systemd service unit, e.g. "ser2net.service"
+    pub unit: String,
+}
+
+impl ServiceConfig {
+    fn validate(&self, index: usize) -> Result<(), ConfigError> {
+        let setting = |name: &str| format!("services[{index}].{name}");
+
+        let valid_name = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+
+        if !valid_name {
+            return Err(ConfigError::invalid(
+                &setting("name"),
+                "May only contain a-z, 0-9, - and _".into(),
+            ));
+        }
+
+        // Only the Service interface of the units is used
+        let valid_unit = self
+            .unit
+            .strip_suffix(".service")
+            .is_some_and(|stem| !stem.is_empty() && !stem.contains('/'));
+
+        if !valid_unit {
+            let detail = format!("{} is not a systemd service unit", self.unit);
+            return Err(ConfigError::invalid(&setting("unit"), detail));
+        }
+
+        Ok(())
+    }
+}
+
+/// What a rule compares the value of the watched topic against
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleCondition {
+    Equals(Value),
+    NotEquals(Value),
+    /// The value is a number larger than the threshold
+    Above(f64),
+    /// The value is a number smaller than the threshold
+    Below(f64),
+    /// The value changed (not counting the initial value)
+    Changed,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum RuleAction {
+    /// Set a web-writable topic like an API user would
+    Set { topic: String, value: Value },
+    /// POST a JSON description of the event to the URL
+    Webhook { url: String },
+}
+
+/// Do something when the value of a topic meets a condition
+///
+/// The actions run once when the condition becomes true (and stayed true
+/// for for_ms milliseconds), the release actions run once when it is no
+/// longer true afterwards.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    pub name: String,
+    /// The path of the topic to watch
+    pub topic: String,
+    /// A JSON pointer to the part of the value to look at,
+    /// e.g. "/value" for measurements
+    #[serde(default)]
+    pub field: Option<String>,
+    // Allow writing "equals: On" instead of YAML tags like "!equals On"
+    #[serde(with = "serde_yaml::with::singleton_map")]
+    pub condition: RuleCondition,
+    #[serde(default)]
+    pub for_ms: u64,
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    pub actions: Vec<RuleAction>,
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    pub release_actions: Vec<RuleAction>,
+}
+
+impl RuleConfig {
+    pub fn hold_time(&self) -> Duration {
+        Duration::from_millis(self.for_ms)
+    }
+
+    fn validate(&self, index: usize) -> Result<(), ConfigError> {
+        let setting = |name: &str| format!("rules[{index}].{name}");
+
+        if self.name.is_empty() {
+            return Err(ConfigError::invalid(
+                &setting("name"),
+                "Must not be empty".into(),
+            ));
+        }
+
+        if !self.topic.starts_with('/') {
+            let detail = "Must be a topic path like /v1/dut/powered".to_string();
+            return Err(ConfigError::invalid(&setting("topic"), detail));
+        }
+
+        if let Some(field) = &self.field {
+            if !field.starts_with('/') {
+                let detail = "Must be a JSON pointer like /value".to_string();
+                return Err(ConfigError::invalid(&setting("field"), detail));
+            }
+        }
+
+        match self.condition {
+            RuleCondition::Above(t) | RuleCondition::Below(t) if !t.is_finite() => {
+                let detail = "Threshold must be a finite number".to_string();
+                return Err(ConfigError::invalid(&setting("condition"), detail));
+            }
+            RuleCondition::Changed if self.for_ms != 0 || !self.release_actions.is_empty() => {
+                // A change is a single event that can not hold or be released
+                let detail = "for_ms and release_actions can not be used with changed".to_string();
+                return Err(ConfigError::invalid(&setting("condition"), detail));
+            }
+            _ => {}
+        }
+
+        if self.actions.is_empty() && self.release_actions.is_empty() {
+            return Err(ConfigError::invalid(
+                &setting("actions"),
+                "Must not be empty".into(),
+            ));
+        }
+
+        for action in self.actions.iter().chain(self.release_actions.iter()) {
+            match action {
+                RuleAction::Set { topic, .. } if !topic.starts_with('/') => {
+                    let detail = format!("{topic} is not a topic path");
+                    return Err(ConfigError::invalid(&setting("actions"), detail));
+                }
+                RuleAction::Webhook { url } => {
+                    if let Err(detail) = check_http_url(url) {
+                        return Err(ConfigError::invalid(&setting("actions"), detail));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// How often to retry a failed delivery
+    pub retries: u32,
+    /// The delay before the first retry. It doubles for every further retry.
+    pub delay_ms: u64,
+}
+
+impl RetryConfig {
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms)
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            delay_ms: 1000,
+        }
+    }
+}
+
+/// POST the values of topics to an URL whenever they change
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub name: String,
+    pub url: String,
+    /// The paths of the topics to report changes of
+    pub topics: Vec<String>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+impl WebhookConfig {
+    fn validate(&self, index: usize) -> Result<(), ConfigError> {
+        let setting = |name: &str| format!("webhooks[{index}].{name}");
+
+        if self.name.is_empty() {
+            return Err(ConfigError::invalid(
+                &setting("name"),
+                "Must not be empty".into(),
+            ));
+        }
+
+        if let Err(detail) = check_http_url(&self.url) {
+            return Err(ConfigError::invalid(&setting("url"), detail));
+        }
+
+        if self.topics.is_empty() {
+            return Err(ConfigError::invalid(
+                &setting("topics"),
+                "Must not be empty".into(),
+            ));
+        }
+
+        if let Some(topic) = self.topics.iter().find(|t| !t.starts_with('/')) {
+            let detail = format!("{topic} is not a topic path");
+            return Err(ConfigError::invalid(&setting("topics"), detail));
+        }
+
+        if self.retry.delay_ms == 0 {
+            return Err(ConfigError::invalid(
+                &setting("retry.delay_ms"),
+                "Must be positive".into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Make sure the URL can be used with the HTTP client (which lacks TLS)
+fn check_http_url(url: &str) -> Result<(), String> {
+    match surf::Url::parse(url) {
+        Ok(url) if url.scheme() == "http" => Ok(()),
+        Ok(_) => Err("Only http:// URLs are supported".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// The tacd configuration as read from /etc/tacd/tacd.yaml
+///
+/// All settings are optional and fall back to the defaults for the LXA TAC.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub http: HttpConfig,
+    pub persistence: PersistenceConfig,
+    pub update: UpdateConfig,
+    pub iobus: IoBusConfig,
+    pub usb_hub: UsbHubConfig,
+    pub services: Vec<ServiceConfig>,
+    pub rules: Vec<RuleConfig>,
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    /// A short description of the problem that fits on the LCD
+    pub summary: String,
+    pub detail: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", CONFIG_FILE, self.detail)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ConfigError {
+    fn invalid(setting: &str, detail: String) -> Self {
+        Self {
+            summary: format!("Invalid value for\n{setting}"),
+            detail: format!("Invalid value for {setting}: {detail}"),
+        }
+    }
+}
+
+impl Config {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if let Err(e) = self.http.listen.parse::<SocketAddr>() {
+            return Err(ConfigError::invalid("http.listen", e.to_string()));
+        }
+
+        if let Err(detail) = check_http_url(&self.iobus.server) {
+            return Err(ConfigError::invalid("iobus.server", detail));
+        }
+
+        let paths = [
+            ("http.webui_dir", &self.http.webui_dir),
+            ("http.srv_dir", &self.http.srv_dir),
+            ("persistence.state_file", &self.persistence.state_file),
+            (
+                "persistence.diagnostics_dir",
+                &self.persistence.diagnostics_dir,
+            ),
+            (
+                "persistence.boot_count_file",
+                &self.persistence.boot_count_file,
+            ),
+            ("update.channels_dir", &self.update.channels_dir),
+            ("update.upload_dir", &self.update.upload_dir),
+            ("usb_hub.port1", &self.usb_hub.port1),
+            ("usb_hub.port2", &self.usb_hub.port2),
+            ("usb_hub.port3", &self.usb_hub.port3),
+        ];
+
+        for (setting, path) in paths.iter() {
+            if path.is_empty() {
+                return Err(ConfigError::invalid(setting, "Must not be empty".into()));
+            }
+        }
+
+        let intervals = [
+            ("iobus.poll_interval_ms", self.iobus.poll_interval_ms),
+            ("usb_hub.poll_interval_ms", self.usb_hub.poll_interval_ms),
+        ];
+
+        for (setting, interval) in intervals.iter() {
+            if *interval < MIN_POLL_INTERVAL_MS {
+                let detail = format!("Must be at least {MIN_POLL_INTERVAL_MS}");
+                return Err(ConfigError::invalid(setting, detail));
+            }
+        }
+
+        if self.persistence.max_write_delay_ms < self.persistence.write_delay_ms {
+            let detail = "Must not be smaller than persistence.write_delay_ms".to_string();
+            return Err(ConfigError::invalid(
+                "persistence.max_write_delay_ms",
+                detail,
+            ));
+        }
+
+        let limits = [
+            ("iobus.max_current", self.iobus.max_current),
+            ("iobus.min_voltage", self.iobus.min_voltage),
+        ];
+
+        for (setting, limit) in limits.iter() {
+            if !limit.is_finite() || *limit <= 0.0 {
+                return Err(ConfigError::invalid(setting, "Must be positive".into()));
+            }
+        }
+
+        for (index, service) in self.services.iter().enumerate() {
+            service.validate(index)?;
+
+            // The name is used in the topic path of the service
+            let duplicate = DEFAULT_SERVICES
+                .iter()
+                .any(|(name, _)| *name == service.name)
+                || self.services[..index]
+                    .iter()
+                    .any(|s| s.name == service.name);
+
+            if duplicate {
+                let detail = format!("Duplicate service name {}", service.name);
+                let setting = format!("services[{index}].name");
+                return Err(ConfigError::invalid(&setting, detail));
+            }
+        }
+
+        for (index, rule) in self.rules.iter().enumerate() {
+            rule.validate(index)?;
+
+            // The name is used to report the state of a rule
+            if self.rules[..index].iter().any(|r| r.name == rule.name) {
+                let detail = format!("Duplicate rule name {}", rule.name);
+                return Err(ConfigError::invalid(
+                    &format!("rules[{index}].name"),
+                    detail,
+                ));
+            }
+        }
+
+        for (index, webhook) in self.webhooks.iter().enumerate() {
+            webhook.validate(index)?;
+
+            if self.webhooks[..index]
+                .iter()
+                .any(|w| w.name == webhook.name)
+            {
+                let detail = format!("Duplicate webhook name {}", webhook.name);
+                let setting = format!("webhooks[{index}].name");
+                return Err(ConfigError::invalid(&setting, detail));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse(content: &str) -> Result<Self, ConfigError> {
+        // An empty file (or one that only contains comments) uses the defaults
+        let config: Option<Self> = serde_yaml::from_str(content).map_err(|e| ConfigError {
+            summary: match e.location() {
+                Some(loc) => format!("Error in line {}", loc.line()),
+                None => "Syntax error".to_string(),
+            },
+            detail: e.to_string(),
+        })?;
+
+        let config = config.unwrap_or_default();
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Read the config file or use the defaults if there is none
+    pub fn load() -> Result<Self, ConfigError> {
+        let content = match read_to_string(CONFIG_FILE) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("Config file {CONFIG_FILE} does not exist. Using defaults");
+                String::new()
+            }
+            Err(e) => {
+                return Err(ConfigError {
+                    summary: "Can not read file".to_string(),
+                    detail: e.to_string(),
+                })
+            }
+        };
+
+        Self::parse(&content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Config, RuleAction, RuleCondition};
+
+    #[test]
+    fn parse_config() {
+        // Missing files and sections use the defaults
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert_eq!(Config::parse("# Nothing\n").unwrap(), Config::default());
+
+        let config = Config::parse(
+            "http:
+  listen: \"0.0.0.0:8000\"
+iobus:
+  server: http://192.168.1.2:8080
+  poll_interval_ms: 5000
+",
+        )
+        .unwrap();
+
+        assert_eq!(config.http.listen, "0.0.0.0:8000");
+        assert_eq!(config.http.webui_dir, Config::default().http.webui_dir);
+        assert_eq!(config.iobus.server, "http://192.168.1.2:8080");
+        assert_eq!(config.iobus.poll_interval_ms, 5000);
+        assert_eq!(config.iobus.max_current, 0.2);
+
+        // Typos are reported with their location instead of being ignored
+        let err = Config::parse("http:\n  listn: \"[::]:80\"\n").unwrap_err();
+        assert_eq!(err.summary, "Error in line 2");
+
+        let err = Config::parse("http:\n  listen: port80\n").unwrap_err();
+        assert_eq!(err.summary, "Invalid value for\nhttp.listen");
+
+        let err = Config::parse("iobus:\n  server: ftp://localhost\n").unwrap_err();
+        assert_eq!(err.summary, "Invalid value for\niobus.server");
+
+        let err = Config::parse("persistence:\n  write_delay_ms: 20000\n").unwrap_err();
+        assert_eq!(
+            err.summary,
+            "Invalid value for\npersistence.max_write_delay_ms"
+        );
+
+        let err = Config::parse("usb_hub:\n  poll_interval_ms: 0\n").unwrap_err();
+        assert_eq!(err.summary, "Invalid value for\nusb_hub.poll_interval_ms");
+
+        let config = Config::parse(
+            "rules:
+  - name: usb1-off-with-dut
+    topic: /v1/dut/powered
+    condition:
+      equals: \"Off\"
+    actions:
+      - set:
+          topic: /v1/usb/host/port1/powered
+          value: false
+  - name: notify
+    topic: /v1/dut/powered
+    condition: changed
+    actions:
+      - webhook:
+          url: http://ci.example.com/tac
+",
+        )
+        .unwrap();
+
+        assert_eq!(config.rules.len(), 2);
+        assert_eq!(
+            config.rules[0].condition,
+            RuleCondition::Equals(json!("Off"))
+        );
+        assert_eq!(
+            config.rules[0].actions,
+            vec![RuleAction::Set {
+                topic: "/v1/usb/host/port1/powered".to_string(),
+                value: json!(false),
+            }]
+        );
+        assert_eq!(config.rules[1].condition, RuleCondition::Changed);
+
+        let err = Config::parse(
+            "rules:
+  - name: hold-change
+    topic: /v1/dut/powered
+    condition: changed
+    for_ms: 100
+    actions:
+      - webhook:
+          url: http://ci.example.com/tac
+",
+        )
+        .unwrap_err();
+        assert_eq!(err.summary, "Invalid value for\nrules[0].condition");
+
+        let config = Config::parse(
+            "webhooks:
+  - name: ci
+    url: http://ci.example.com/tac
+    topics: [/v1/dut/powered, /v1/usb/host/overload]
+    retry:
+      retries: 2
+",
+        )
+        .unwrap();
+
+        assert_eq!(config.webhooks[0].topics.len(), 2);
+        assert_eq!(config.webhooks[0].retry.retries, 2);
+        assert_eq!(config.webhooks[0].retry.delay_ms, 1000);
+
+        let err = Config::parse(
+            "webhooks:
+  - name: ci
+    url: https://ci.example.com/tac
+    topics: [/v1/dut/powered]
+",
+        )
+        .unwrap_err();
+        assert_eq!(err.summary, "Invalid value for\nwebhooks[0].url");
+
+        let config = Config::parse(
+            "services:
+  - name: ser2net
+    unit: ser2net.service
+  - name: dhcp_server
+    unit: dnsmasq@dut.service
+",
+        )
+        .unwrap();
+
+        assert_eq!(config.services.len(), 2);
+        assert_eq!(config.services[1].unit, "dnsmasq@dut.service");
+
+        // Names end up in topic paths and units must be services
+        let service_err = |service: &str| Config::parse(&format!("services: [{service}]"));
+
+        for (service, setting) in [
+            ("{name: Ser2Net, unit: ser2net.service}", "name"),
+            ("{name: ser/net, unit: ser2net.service}", "name"),
+            ("{name: ser2net, unit: ser2net}", "unit"),
+            ("{name: ser2net, unit: ser2net.socket}", "unit"),
+            ("{name: ser2net, unit: ../ser2net.service}", "unit"),
+            // The default services can not be overridden
+            ("{name: lxa-iobus, unit: evil.service}", "name"),
+        ]
+        .iter()
+        {
+            let err = service_err(service).unwrap_err();
+            assert_eq!(
+                err.summary,
+                format!("Invalid value for\nservices[0].{setting}")
+            );
+        }
+
+        let err = service_err("{name: ser2net, unit: ser2net.service, user: root}").unwrap_err();
+        assert_eq!(err.summary, "Error in line 1");
+    }
+}
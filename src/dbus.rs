@@ -18,6 +18,7 @@
 use async_std::sync::Arc;
 
 use crate::broker::{BrokerBuilder, Topic};
+use crate::config::{ServiceConfig, UpdateConfig};
 use crate::led::BlinkPattern;
 
 #[cfg(feature = "demo_mode")]
@@ -76,6 +77,9 @@ impl DbusSession {
         bb: &mut BrokerBuilder,
         led_dut: Arc<Topic<BlinkPattern>>,
         led_uplink: Arc<Topic<BlinkPattern>>,
+        setup_mode: Arc<Topic<bool>>,
+        update_config: &UpdateConfig,
+        services: &[ServiceConfig],
     ) -> Self {
         let tacd = Tacd::new();
 
@@ -87,9 +91,9 @@ impl DbusSession {
         let conn = Arc::new(tacd.serve(conn_builder).build().await.unwrap());
 
         Self {
-            network: Network::new(bb, &conn, led_dut, led_uplink),
-            rauc: Rauc::new(bb, &conn),
-            systemd: Systemd::new(bb, &conn).await,
+            network: Network::new(bb, &conn, led_dut, led_uplink, setup_mode),
+            rauc: Rauc::new(bb, &conn, update_config),
+            systemd: Systemd::new(bb, &conn, services).await,
         }
     }
 }
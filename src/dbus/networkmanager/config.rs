@@ -0,0 +1,737 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use async_std::future::timeout;
+use async_std::prelude::*;
+use async_std::sync::Arc;
+use async_std::task::{sleep, spawn};
+use futures::stream::select_all;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::broker::{BrokerBuilder, Topic};
+
+/// Time a client has to confirm a new network configuration before it is
+/// rolled back
+const ROLLBACK_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The interfaces whose connection profiles can be edited via the API
+const INTERFACES: &[&str] = &["uplink", "tac-bridge"];
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IpMethod {
+    /// No IPv4 configuration, e.g. because the interface is a bridge port
+    Disabled,
+    Dhcp,
+    Static,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectionConfig {
+    pub method: IpMethod,
+    /// IPv4 addresses in CIDR notation, e.g. "192.168.1.10/24".
+    /// Only used with IpMethod::Static.
+    pub addresses: Vec<String>,
+    pub gateway: Option<String>,
+    pub dns: Vec<String>,
+    /// Use a tagged VLAN on top of the interface instead of the untagged
+    /// traffic
+    pub vlan_id: Option<u16>,
+}
+
+fn parse_cidr(address: &str) -> Result<(Ipv4Addr, u32)> {
+    let (addr, prefix) = address
+        .split_once('/')
+        .ok_or_else(|| anyhow!("Address {address} is missing a prefix length"))?;
+
+    let addr: Ipv4Addr = addr.parse()?;
+    let prefix: u32 = prefix.parse()?;
+
+    if prefix > 32 {
+        bail!("Prefix length of {address} is out of range");
+    }
+
+    Ok((addr, prefix))
+}
+
+impl ConnectionConfig {
+    fn validate(&self) -> Result<()> {
+        match self.method {
+            IpMethod::Static if self.addresses.is_empty() => {
+                bail!("A static configuration needs at least one address")
+            }
+            IpMethod::Disabled | IpMethod::Dhcp if !self.addresses.is_empty() => {
+                bail!("Addresses can only be set for a static configuration")
+            }
+            _ => {}
+        }
+
+        for address in &self.addresses {
+            parse_cidr(address)?;
+        }
+
+        if let Some(gateway) = &self.gateway {
+            gateway.parse::<Ipv4Addr>()?;
+        }
+
+        for dns in &self.dns {
+            dns.parse::<Ipv4Addr>()?;
+        }
+
+        if let Some(id) = self.vlan_id {
+            if !(1..=4094).contains(&id) {
+                bail!("VLAN ID {id} is out of range");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "demo_mode")]
+mod backend {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use anyhow::{anyhow, Result};
+
+    use super::{ConnectionConfig, IpMethod};
+
+    pub struct Checkpoint(HashMap<String, ConnectionConfig>);
+
+    pub struct Backend {
+        profiles: Mutex<HashMap<String, ConnectionConfig>>,
+    }
+
+    impl Backend {
+        pub fn new() -> Self {
+            let uplink = ConnectionConfig {
+                method: IpMethod::Disabled,
+                addresses: Vec::new(),
+                gateway: None,
+                dns: Vec::new(),
+                vlan_id: None,
+            };
+
+            let bridge = ConnectionConfig {
+                method: IpMethod::Dhcp,
+                addresses: Vec::new(),
+                gateway: None,
+                dns: Vec::new(),
+                vlan_id: None,
+            };
+
+            let profiles = HashMap::from([
+                ("uplink".to_string(), uplink),
+                ("tac-bridge".to_string(), bridge),
+            ]);
+
+            Self {
+                profiles: Mutex::new(profiles),
+            }
+        }
+
+        pub async fn read(&self, interface: &str) -> Result<ConnectionConfig> {
+            self.profiles
+                .lock()
+                .unwrap()
+                .get(interface)
+                .cloned()
+                .ok_or_else(|| anyhow!("No connection profile for {interface}"))
+        }
+
+        pub async fn checkpoint(&self) -> Result<Checkpoint> {
+            Ok(Checkpoint(self.profiles.lock().unwrap().clone()))
+        }
+
+        pub async fn apply(&self, interface: &str, config: &ConnectionConfig) -> Result<()> {
+            self.profiles
+                .lock()
+                .unwrap()
+                .insert(interface.to_string(), config.clone());
+
+            Ok(())
+        }
+
+        pub async fn confirm(&self, _checkpoint: Checkpoint) -> Result<()> {
+            Ok(())
+        }
+
+        pub async fn rollback(&self, checkpoint: Checkpoint) -> Result<()> {
+            *self.profiles.lock().unwrap() = checkpoint.0;
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(feature = "demo_mode"))]
+mod backend {
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+    use std::net::Ipv4Addr;
+
+    use anyhow::{anyhow, Result};
+    use async_std::sync::Arc;
+    use zbus::Connection;
+    use zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+
+    use super::super::networkmanager::NetworkManagerProxy;
+    use super::super::settings::{ConnectionProxy, SettingsProxy};
+    use super::{parse_cidr, ConnectionConfig, IpMethod, ROLLBACK_TIMEOUT};
+
+    // See NMCheckpointCreateFlags in the NetworkManager documentation
+    const CHECKPOINT_DESTROY_ALL: u32 = 0x01;
+    const CHECKPOINT_DELETE_NEW_CONNECTIONS: u32 = 0x02;
+    const CHECKPOINT_DISCONNECT_NEW_DEVICES: u32 = 0x04;
+
+    // NetworkManager rolls back on its own if the tacd does not get around
+    // to do it, e.g. because it crashed.
+    const CHECKPOINT_TIMEOUT_MARGIN: u32 = 60;
+
+    type Settings = HashMap<String, HashMap<String, OwnedValue>>;
+
+    pub struct Checkpoint(OwnedObjectPath);
+
+    struct Profiles {
+        base: (OwnedObjectPath, Settings),
+        vlan: Option<(OwnedObjectPath, Settings)>,
+    }
+
+    fn get_str<'a>(settings: &'a Settings, section: &str, key: &str) -> Option<&'a str> {
+        settings
+            .get(section)
+            .and_then(|s| s.get(key))
+            .and_then(|v| v.downcast_ref::<zvariant::Str>())
+            .map(|s| s.as_str())
+    }
+
+    fn get_u32(settings: &Settings, section: &str, key: &str) -> Option<u32> {
+        settings
+            .get(section)
+            .and_then(|s| s.get(key))
+            .and_then(|v| v.downcast_ref::<u32>())
+            .copied()
+    }
+
+    /// The name of the VLAN connection profile managed by the tacd
+    fn vlan_profile_name(interface: &str) -> String {
+        format!("tacd-{interface}-vlan")
+    }
+
+    /// Build the "ipv4" section of a connection profile
+    fn ipv4_section(config: &ConnectionConfig) -> Result<HashMap<String, Value<'static>>> {
+        let mut section = HashMap::new();
+
+        let method = match config.method {
+            IpMethod::Disabled => "disabled",
+            IpMethod::Dhcp => "auto",
+            IpMethod::Static => "manual",
+        };
+
+        section.insert("method".to_string(), Value::from(method.to_string()));
+
+        let mut address_data = Vec::new();
+
+        for address in &config.addresses {
+            let (addr, prefix) = parse_cidr(address)?;
+
+            let mut entry: HashMap<String, Value<'static>> = HashMap::new();
+            entry.insert("address".to_string(), Value::from(addr.to_string()));
+            entry.insert("prefix".to_string(), Value::from(prefix));
+
+            address_data.push(entry);
+        }
+
+        section.insert("address-data".to_string(), Value::from(address_data));
+
+        if let Some(gateway) = &config.gateway {
+            section.insert("gateway".to_string(), Value::from(gateway.clone()));
+        }
+
+        // NetworkManager expects the DNS servers as u32 in network byte order
+        let dns = config
+            .dns
+            .iter()
+            .map(|d| Ok(u32::from_ne_bytes(d.parse::<Ipv4Addr>()?.octets())))
+            .collect::<Result<Vec<u32>>>()?;
+
+        section.insert("dns".to_string(), Value::from(dns));
+
+        Ok(section)
+    }
+
+    /// Convert the settings returned by GetSettings into the form expected
+    /// by Update, replacing the "ipv4" section on the way
+    fn with_ipv4(
+        settings: &Settings,
+        ipv4: HashMap<String, Value<'static>>,
+    ) -> HashMap<String, HashMap<String, Value<'static>>> {
+        let mut res: HashMap<String, HashMap<String, Value<'static>>> = settings
+            .iter()
+            .map(|(section, values)| {
+                let values = values
+                    .iter()
+                    .map(|(k, v)| (k.clone(), Value::from(v.clone())))
+                    .collect();
+
+                (section.clone(), values)
+            })
+            .collect();
+
+        res.insert("ipv4".to_string(), ipv4);
+
+        res
+    }
+
+    fn as_refs<'a>(
+        settings: &'a HashMap<String, HashMap<String, Value<'static>>>,
+    ) -> HashMap<&'a str, HashMap<&'a str, Value<'a>>> {
+        settings
+            .iter()
+            .map(|(section, values)| {
+                let values = values
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.clone()))
+                    .collect();
+
+                (section.as_str(), values)
+            })
+            .collect()
+    }
+
+    pub struct Backend {
+        con: Arc<Connection>,
+    }
+
+    impl Backend {
+        pub fn new(con: Arc<Connection>) -> Self {
+            Self { con }
+        }
+
+        async fn profiles(&self, interface: &str) -> Result<Profiles> {
+            let settings_proxy = SettingsProxy::new(&self.con).await?;
+            let vlan_name = vlan_profile_name(interface);
+
+            let mut base = None;
+            let mut vlan = None;
+
+            for path in settings_proxy.list_connections().await? {
+                let proxy = ConnectionProxy::builder(&self.con)
+                    .path(&path)?
+                    .build()
+                    .await?;
+
+                let settings = proxy.get_settings().await?;
+
+                let id = get_str(&settings, "connection", "id");
+                let iface = get_str(&settings, "connection", "interface-name");
+                let kind = get_str(&settings, "connection", "type");
+
+                if kind == Some("vlan") {
+                    if id == Some(vlan_name.as_str()) {
+                        vlan = Some((path, settings));
+                    }
+                } else if iface == Some(interface) && base.is_none() {
+                    base = Some((path, settings));
+                }
+            }
+
+            let base = base.ok_or_else(|| anyhow!("No connection profile for {interface}"))?;
+
+            Ok(Profiles { base, vlan })
+        }
+
+        pub async fn read(&self, interface: &str) -> Result<ConnectionConfig> {
+            let profiles = self.profiles(interface).await?;
+
+            let vlan_id = profiles
+                .vlan
+                .as_ref()
+                .and_then(|(_, s)| get_u32(s, "vlan", "id"))
+                .and_then(|id| u16::try_from(id).ok());
+
+            // The IP configuration lives on the VLAN profile if there is one
+            let settings = match &profiles.vlan {
+                Some((_, settings)) => settings,
+                None => &profiles.base.1,
+            };
+
+            let method = match get_str(settings, "ipv4", "method") {
+                Some("auto") => IpMethod::Dhcp,
+                Some("manual") => IpMethod::Static,
+                _ => IpMethod::Disabled,
+            };
+
+            let addresses = settings
+                .get("ipv4")
+                .and_then(|s| s.get("address-data"))
+                .and_then(|v| {
+                    Vec::<HashMap<String, OwnedValue>>::try_from(Value::from(v.clone())).ok()
+                })
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|entry| {
+                    let address = entry.get("address")?.downcast_ref::<zvariant::Str>()?;
+                    let prefix = entry.get("prefix")?.downcast_ref::<u32>()?;
+
+                    Some(format!("{}/{}", address.as_str(), prefix))
+                })
+                .collect();
+
+            let gateway = get_str(settings, "ipv4", "gateway").map(|g| g.to_string());
+
+            let dns = settings
+                .get("ipv4")
+                .and_then(|s| s.get("dns"))
+                .and_then(|v| Vec::<u32>::try_from(Value::from(v.clone())).ok())
+                .unwrap_or_default()
+                .iter()
+                .map(|d| Ipv4Addr::from(d.to_ne_bytes()).to_string())
+                .collect();
+
+            Ok(ConnectionConfig {
+                method,
+                addresses,
+                gateway,
+                dns,
+                vlan_id,
+            })
+        }
+
+        pub async fn checkpoint(&self) -> Result<Checkpoint> {
+            let nm = NetworkManagerProxy::new(&self.con).await?;
+
+            // An empty list of devices means all devices
+            let path = nm
+                .checkpoint_create(
+                    &[],
+                    ROLLBACK_TIMEOUT.as_secs() as u32 + CHECKPOINT_TIMEOUT_MARGIN,
+                    CHECKPOINT_DESTROY_ALL
+                        | CHECKPOINT_DELETE_NEW_CONNECTIONS
+                        | CHECKPOINT_DISCONNECT_NEW_DEVICES,
+                )
+                .await?;
+
+            Ok(Checkpoint(path))
+        }
+
+        pub async fn apply(&self, interface: &str, config: &ConnectionConfig) -> Result<()> {
+            let settings_proxy = SettingsProxy::new(&self.con).await?;
+            let nm = NetworkManagerProxy::new(&self.con).await?;
+            let profiles = self.profiles(interface).await?;
+            let root = ObjectPath::try_from("/")?;
+
+            let ipv4 = ipv4_section(config)?;
+
+            let (base_ipv4, vlan_ipv4) = match config.vlan_id {
+                Some(_) => {
+                    let disabled = ConnectionConfig {
+                        method: IpMethod::Disabled,
+                        addresses: Vec::new(),
+                        gateway: None,
+                        dns: Vec::new(),
+                        vlan_id: None,
+                    };
+
+                    (ipv4_section(&disabled)?, Some(ipv4))
+                }
+                None => (ipv4, None),
+            };
+
+            let (base_path, base_settings) = &profiles.base;
+            let base_proxy = ConnectionProxy::builder(&self.con)
+                .path(base_path)?
+                .build()
+                .await?;
+
+            let base_settings = with_ipv4(base_settings, base_ipv4);
+            base_proxy.update(as_refs(&base_settings)).await?;
+            nm.activate_connection(base_path, &root, &root).await?;
+
+            match (config.vlan_id, vlan_ipv4, profiles.vlan) {
+                (Some(id), Some(ipv4), Some((vlan_path, vlan_settings))) => {
+                    let vlan_proxy = ConnectionProxy::builder(&self.con)
+                        .path(&vlan_path)?
+                        .build()
+                        .await?;
+
+                    let mut vlan_settings = with_ipv4(&vlan_settings, ipv4);
+                    vlan_settings
+                        .entry("vlan".to_string())
+                        .or_default()
+                        .insert("id".to_string(), Value::from(id as u32));
+
+                    vlan_proxy.update(as_refs(&vlan_settings)).await?;
+                    nm.activate_connection(&vlan_path, &root, &root).await?;
+                }
+                (Some(id), Some(ipv4), None) => {
+                    let mut connection = HashMap::new();
+                    connection.insert("id".to_string(), Value::from(vlan_profile_name(interface)));
+                    connection.insert("type".to_string(), Value::from("vlan".to_string()));
+                    connection.insert("autoconnect".to_string(), Value::from(true));
+
+                    let mut vlan = HashMap::new();
+                    vlan.insert("parent".to_string(), Value::from(interface.to_string()));
+                    vlan.insert("id".to_string(), Value::from(id as u32));
+
+                    let mut vlan_settings = HashMap::new();
+                    vlan_settings.insert("connection".to_string(), connection);
+                    vlan_settings.insert("vlan".to_string(), vlan);
+                    vlan_settings.insert("ipv4".to_string(), ipv4);
+
+                    let vlan_path = settings_proxy
+                        .add_connection(as_refs(&vlan_settings))
+                        .await?;
+
+                    nm.activate_connection(&vlan_path, &root, &root).await?;
+                }
+                (None, _, Some((vlan_path, _))) => {
+                    let vlan_proxy = ConnectionProxy::builder(&self.con)
+                        .path(&vlan_path)?
+                        .build()
+                        .await?;
+
+                    vlan_proxy.delete().await?;
+                }
+                _ => {}
+            }
+
+            Ok(())
+        }
+
+        pub async fn confirm(&self, checkpoint: Checkpoint) -> Result<()> {
+            let nm = NetworkManagerProxy::new(&self.con).await?;
+            nm.checkpoint_destroy(&checkpoint.0).await?;
+
+            Ok(())
+        }
+
+        pub async fn rollback(&self, checkpoint: Checkpoint) -> Result<()> {
+            let nm = NetworkManagerProxy::new(&self.con).await?;
+            nm.checkpoint_rollback(&checkpoint.0).await?;
+
+            Ok(())
+        }
+    }
+}
+
+pub use backend::Backend;
+
+struct Interface {
+    name: &'static str,
+    config: Arc<Topic<ConnectionConfig>>,
+}
+
+async fn refresh(backend: &Backend, interfaces: &[Interface]) {
+    for interface in interfaces {
+        match backend.read(interface.name).await {
+            Ok(config) => interface.config.set_if_changed(config),
+            Err(e) => warn!("Failed to read network config of {}: {e}", interface.name),
+        }
+    }
+}
+
+/// Wait until the pending change was confirmed by a client or the
+/// ROLLBACK_TIMEOUT has passed.
+/// Returns true if the change was confirmed.
+async fn wait_for_confirmation(pending: &Arc<Topic<bool>>) -> bool {
+    let (mut pending_events, pending_handle) = pending.clone().subscribe_unbounded();
+
+    let confirmed = timeout(ROLLBACK_TIMEOUT, async {
+        while let Some(pending) = pending_events.next().await {
+            if !pending {
+                break;
+            }
+        }
+    })
+    .await
+    .is_ok();
+
+    pending_handle.unsubscribe();
+
+    confirmed
+}
+
+/// Expose the connection profiles of the uplink and bridge interfaces
+///
+/// Changes are only accepted in setup mode and have to be confirmed by
+/// writing `false` to `/v1/tac/network/config/pending` within
+/// ROLLBACK_TIMEOUT. Otherwise they are rolled back, so that a TAC that is
+/// no longer reachable after a change fixes itself.
+pub fn setup(bb: &mut BrokerBuilder, backend: Backend, setup_mode: Arc<Topic<bool>>) {
+    let pending = bb.topic_ro("/v1/tac/network/config/pending", Some(false));
+    let error = bb.topic_ro::<Option<String>>("/v1/tac/network/config/error", Some(None));
+
+    // Use the "register a read-only and a write-only topic with the same name
+    // to perform validation" trick, so that a client can only ever confirm
+    // a pending change but not set one pending.
+    let (mut confirm_requests, _) = bb
+        .topic_wo::<bool>("/v1/tac/network/config/pending", None)
+        .subscribe_unbounded();
+
+    let pending_task = pending.clone();
+
+    spawn(async move {
+        while let Some(confirm) = confirm_requests.next().await {
+            if !confirm {
+                pending_task.set_if_changed(false);
+            }
+        }
+    });
+
+    let mut interfaces = Vec::new();
+    let mut requests = Vec::new();
+
+    for &name in INTERFACES {
+        let path = format!("/v1/tac/network/config/{name}");
+
+        let config = bb.topic_ro(&path, None);
+        let (reqs, _) = bb
+            .topic_wo::<ConnectionConfig>(&path, None)
+            .subscribe_unbounded();
+
+        interfaces.push(Interface { name, config });
+        requests.push(Box::pin(reqs.map(move |req| (name, req))));
+    }
+
+    let mut requests = select_all(requests);
+
+    spawn(async move {
+        refresh(&backend, &interfaces).await;
+
+        while let Some((name, req)) = requests.next().await {
+            if !setup_mode.try_get().unwrap_or(false) {
+                warn!("Ignoring network config change of {name} outside of setup mode");
+                error.set(Some(
+                    "The network config can only be changed in setup mode".into(),
+                ));
+                continue;
+            }
+
+            if let Err(e) = req.validate() {
+                warn!("Ignoring invalid network config for {name}: {e}");
+                error.set(Some(format!("Invalid network config for {name}: {e}")));
+                continue;
+            }
+
+            let checkpoint = match backend.checkpoint().await {
+                Ok(cp) => cp,
+                Err(e) => {
+                    warn!("Failed to create network config checkpoint: {e}");
+                    error.set(Some(format!("Failed to create checkpoint: {e}")));
+                    continue;
+                }
+            };
+
+            info!("Applying new network config for {name}: {req:?}");
+
+            let res = backend.apply(name, &req).await;
+
+            // Give NetworkManager some time to reflect the changes
+            sleep(Duration::from_secs(1)).await;
+            refresh(&backend, &interfaces).await;
+
+            if let Err(e) = res {
+                warn!("Failed to apply network config for {name}: {e}. Rolling back");
+                error.set(Some(format!(
+                    "Failed to apply network config for {name}: {e}"
+                )));
+
+                if let Err(e) = backend.rollback(checkpoint).await {
+                    warn!("Failed to roll back network config: {e}");
+                }
+
+                refresh(&backend, &interfaces).await;
+                continue;
+            }
+
+            error.set(None);
+            pending.set(true);
+
+            if wait_for_confirmation(&pending).await {
+                info!("New network config for {name} was confirmed");
+
+                if let Err(e) = backend.confirm(checkpoint).await {
+                    warn!("Failed to remove network config checkpoint: {e}");
+                }
+            } else {
+                warn!("New network config for {name} was not confirmed. Rolling back");
+                error.set(Some(format!(
+                    "The network config for {name} was not confirmed and was rolled back"
+                )));
+
+                if let Err(e) = backend.rollback(checkpoint).await {
+                    warn!("Failed to roll back network config: {e}");
+                }
+
+                pending.set(false);
+
+                sleep(Duration::from_secs(1)).await;
+                refresh(&backend, &interfaces).await;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionConfig, IpMethod};
+
+    #[test]
+    fn validate() {
+        let dhcp = ConnectionConfig {
+            method: IpMethod::Dhcp,
+            addresses: Vec::new(),
+            gateway: None,
+            dns: Vec::new(),
+            vlan_id: None,
+        };
+
+        let fixed = ConnectionConfig {
+            method: IpMethod::Static,
+            addresses: vec!["192.168.1.10/24".into(), "10.0.0.1/8".into()],
+            gateway: Some("192.168.1.1".into()),
+            dns: vec!["192.168.1.1".into()],
+            vlan_id: Some(42),
+        };
+
+        assert!(dhcp.validate().is_ok());
+        assert!(fixed.validate().is_ok());
+
+        let check = |cb: &dyn Fn(&mut ConnectionConfig)| {
+            let mut config = fixed.clone();
+            cb(&mut config);
+            config.validate()
+        };
+
+        assert!(check(&|c| c.addresses.clear()).is_err());
+        assert!(check(&|c| c.method = IpMethod::Dhcp).is_err());
+        assert!(check(&|c| c.addresses[0] = "192.168.1.10".into()).is_err());
+        assert!(check(&|c| c.addresses[0] = "192.168.1.10/33".into()).is_err());
+        assert!(check(&|c| c.addresses[0] = "192.168.1.300/24".into()).is_err());
+        assert!(check(&|c| c.gateway = Some("gateway".into())).is_err());
+        assert!(check(&|c| c.dns.push("::1".into())).is_err());
+        assert!(check(&|c| c.vlan_id = Some(0)).is_err());
+        assert!(check(&|c| c.vlan_id = Some(4095)).is_err());
+    }
+}
@@ -413,6 +413,40 @@ pub mod ip4 {
         fn wins_servers(&self) -> zbus::Result<Vec<u32>>;
     }
 }
+pub mod ip6 {
+    use zbus::dbus_proxy;
+
+    #[dbus_proxy(
+        interface = "org.freedesktop.NetworkManager.IP6Config",
+        default_service = "org.freedesktop.NetworkManager"
+    )]
+    pub trait IP6Config {
+        /// AddressData property
+        #[dbus_proxy(property)]
+        fn address_data(
+            &self,
+        ) -> zbus::Result<Vec<std::collections::HashMap<String, zbus::zvariant::OwnedValue>>>;
+
+        /// Domains property
+        #[dbus_proxy(property)]
+        fn domains(&self) -> zbus::Result<Vec<String>>;
+
+        /// Gateway property
+        #[dbus_proxy(property)]
+        fn gateway(&self) -> zbus::Result<String>;
+
+        /// Nameservers property
+        #[dbus_proxy(property)]
+        fn nameservers(&self) -> zbus::Result<Vec<Vec<u8>>>;
+
+        /// RouteData property
+        #[dbus_proxy(property)]
+        fn route_data(
+            &self,
+        ) -> zbus::Result<Vec<std::collections::HashMap<String, zbus::zvariant::OwnedValue>>>;
+    }
+}
+
 pub use device::DeviceProxy;
 pub use statistics::StatisticsProxy;
 pub use wired::WiredProxy;
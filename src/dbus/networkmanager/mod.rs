@@ -16,34 +16,44 @@
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 
 use async_std;
+use async_std::stream::StreamExt;
 use async_std::sync::Arc;
-
+use log::warn;
 use serde::{Deserialize, Serialize};
 
 use crate::broker::{BrokerBuilder, Topic};
 use crate::led::BlinkPattern;
 
+mod config;
 mod devices;
 mod hostname;
 
+#[cfg(not(feature = "demo_mode"))]
+mod settings;
+
 // All of the following includes are not used in demo_mode.
 // Put them inside a mod so we do not have to decorate each one with
 // a #[cfg(not(feature = "demo_mode"))].
 mod optional_includes {
     pub use anyhow::{anyhow, Result};
-    pub use async_std::stream::StreamExt;
     pub use async_std::task::sleep;
     pub use futures::{future::FutureExt, pin_mut, select};
     pub use log::trace;
-    pub use std::convert::TryInto;
+    pub use std::collections::HashMap;
+    pub use std::convert::TryFrom;
+    pub use std::net::Ipv6Addr;
     pub use std::time::Duration;
     pub use zbus::{Connection, PropertyStream};
-    pub use zvariant::{ObjectPath, OwnedObjectPath};
+    pub use zvariant::{OwnedObjectPath, OwnedValue};
 }
 
 #[cfg(not(feature = "demo_mode"))]
 use optional_includes::*;
 
+// Addresses may change without a change notification, see handle_ip_info
+#[cfg(not(feature = "demo_mode"))]
+const IP_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
 #[allow(clippy::module_inception)]
 mod networkmanager;
 
@@ -53,6 +63,48 @@ pub struct LinkInfo {
     pub carrier: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IpAddress {
+    pub address: String,
+    pub prefix: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct IpInfo {
+    pub ipv4: Vec<IpAddress>,
+    pub ipv6: Vec<IpAddress>,
+    /// The IPv4 default gateway
+    pub gateway4: Option<String>,
+    /// The IPv6 default gateway
+    pub gateway6: Option<String>,
+    /// IPv4 and IPv6 DNS servers
+    pub dns: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LldpNeighbor {
+    pub chassis_id: Option<String>,
+    pub port_id: Option<String>,
+    pub port_description: Option<String>,
+    pub system_name: Option<String>,
+    /// The port VLAN ID announced by the neighbor
+    pub vlan_id: Option<u32>,
+}
+
+impl IpInfo {
+    /// The IPv6 address that is most useful to show to a user.
+    /// A global address if there is one or a link-local address otherwise.
+    pub fn preferred_ipv6(&self) -> Option<&str> {
+        let is_link_local = |a: &&IpAddress| a.address.to_lowercase().starts_with("fe80:");
+
+        self.ipv6
+            .iter()
+            .find(|a| !is_link_local(a))
+            .or_else(|| self.ipv6.iter().find(is_link_local))
+            .map(|a| a.address.as_str())
+    }
+}
+
 #[cfg(not(feature = "demo_mode"))]
 async fn path_from_interface(con: &Connection, interface: &str) -> Result<OwnedObjectPath> {
     let proxy = networkmanager::NetworkManagerProxy::new(con).await?;
@@ -89,28 +141,6 @@ async fn get_link_info(con: &Connection, path: &str) -> Result<LinkInfo> {
     Ok(info)
 }
 
-#[cfg(not(feature = "demo_mode"))]
-pub async fn get_ip4_address<'a, P>(con: &Connection, path: P) -> Result<Vec<String>>
-where
-    P: TryInto<ObjectPath<'a>>,
-    P::Error: Into<zbus::Error>,
-{
-    let ip_4_proxy = devices::ip4::IP4ConfigProxy::builder(con)
-        .path(path)?
-        .build()
-        .await?;
-
-    let ip_address = ip_4_proxy.address_data2().await?;
-    trace!("get IPv4: {:?}", ip_address);
-    let ip_address = ip_address
-        .get(0)
-        .and_then(|e| e.get("address"))
-        .and_then(|e| e.downcast_ref::<zvariant::Str>())
-        .map(|e| e.as_str())
-        .ok_or(anyhow!("IP not found"))?;
-    Ok(Vec::from([ip_address.to_string()]))
-}
-
 #[cfg(not(feature = "demo_mode"))]
 pub struct LinkStream<'a> {
     pub interface: String,
@@ -177,70 +207,218 @@ impl<'a> LinkStream<'a> {
 }
 
 #[cfg(not(feature = "demo_mode"))]
-pub struct IpStream<'a> {
-    pub interface: String,
-    _con: Arc<Connection>,
-    ip_4_config: PropertyStream<'a, OwnedObjectPath>,
-    path: String,
+fn address_list(data: Vec<HashMap<String, OwnedValue>>) -> Vec<IpAddress> {
+    data.iter()
+        .filter_map(|entry| {
+            let address = entry.get("address")?.downcast_ref::<zvariant::Str>()?;
+            let prefix = entry.get("prefix")?.downcast_ref::<u32>()?;
+
+            Some(IpAddress {
+                address: address.as_str().to_string(),
+                prefix: *prefix,
+            })
+        })
+        .collect()
 }
 
 #[cfg(not(feature = "demo_mode"))]
-impl<'a> IpStream<'a> {
-    pub async fn new(con: Arc<Connection>, interface: &str) -> Result<IpStream<'a>> {
-        let path = path_from_interface(&con, interface)
-            .await?
-            .as_str()
-            .to_string();
+pub async fn get_ip_info(con: &Connection, path: &str) -> Result<IpInfo> {
+    let device_proxy = devices::DeviceProxy::builder(con)
+        .path(path)?
+        .build()
+        .await?;
 
-        let device_proxy = devices::DeviceProxy::builder(&con)
-            .path(path.clone())?
+    let mut info = IpInfo::default();
+
+    // Interfaces without an IP configuration use "/" as path
+    let ip_4_config = device_proxy.ip4_config().await?;
+
+    if ip_4_config.as_str() != "/" {
+        let ip_4_proxy = devices::ip4::IP4ConfigProxy::builder(con)
+            .path(&ip_4_config)?
             .build()
             .await?;
 
-        let ip_4_config = device_proxy.receive_ip4_config_changed().await;
+        info.ipv4 = address_list(ip_4_proxy.address_data2().await?);
+        info.gateway4 = Some(ip_4_proxy.gateway().await?).filter(|g| !g.is_empty());
 
-        Ok(Self {
-            interface: interface.to_string(),
-            _con: con,
-            ip_4_config,
-            path: path.to_string(),
-        })
+        for nameserver in ip_4_proxy.nameserver_data().await? {
+            if let Some(address) = nameserver
+                .get("address")
+                .and_then(|a| a.downcast_ref::<zvariant::Str>())
+            {
+                info.dns.push(address.as_str().to_string());
+            }
+        }
     }
 
-    pub async fn now(&mut self, con: &Connection) -> Result<Vec<String>> {
-        let device_proxy = devices::DeviceProxy::builder(con)
-            .path(self.path.as_str())?
+    let ip_6_config = device_proxy.ip6_config().await?;
+
+    if ip_6_config.as_str() != "/" {
+        let ip_6_proxy = devices::ip6::IP6ConfigProxy::builder(con)
+            .path(&ip_6_config)?
             .build()
             .await?;
 
-        let ip_4_config = device_proxy.ip4_config().await?;
+        info.ipv6 = address_list(ip_6_proxy.address_data().await?);
+        info.gateway6 = Some(ip_6_proxy.gateway().await?).filter(|g| !g.is_empty());
 
-        Ok(get_ip4_address(con, ip_4_config)
-            .await
-            .unwrap_or_else(|_e| Vec::new()))
+        for nameserver in ip_6_proxy.nameservers().await? {
+            if let Ok(octets) = <[u8; 16]>::try_from(nameserver.as_slice()) {
+                info.dns.push(Ipv6Addr::from(octets).to_string());
+            }
+        }
     }
 
-    pub async fn next(&mut self, con: &Connection) -> Result<Vec<String>> {
-        let ip_4_config = StreamExt::next(&mut self.ip_4_config).await;
+    trace!("get IP info: {} {:?}", path, info);
+
+    Ok(info)
+}
+
+#[cfg(not(feature = "demo_mode"))]
+impl LldpNeighbor {
+    fn from_dbus(neighbor: &HashMap<String, OwnedValue>) -> Self {
+        let get_str = |key: &str| {
+            neighbor
+                .get(key)
+                .and_then(|v| v.downcast_ref::<zvariant::Str>())
+                .map(|v| v.as_str().to_string())
+        };
+
+        let get_u32 = |key: &str| {
+            neighbor
+                .get(key)
+                .and_then(|v| v.downcast_ref::<u32>())
+                .copied()
+        };
 
-        if let Some(path) = ip_4_config {
-            let path = path.get().await?;
-            if let Ok(ips) = get_ip4_address(con, &path).await {
-                trace!("updata ip: {} {:?}", self.interface, ips);
-                return Ok(ips);
-            } else {
-                return Ok(Vec::new());
+        Self {
+            chassis_id: get_str("chassis-id"),
+            port_id: get_str("port-id"),
+            port_description: get_str("port-description"),
+            system_name: get_str("system-name"),
+            vlan_id: get_u32("ieee-802-1-pvid").or_else(|| get_u32("ieee-802-1-vid")),
+        }
+    }
+}
+
+/// Keep the list of LLDP neighbors seen on an interface up to date
+///
+/// Please note that NetworkManager only collects this information if LLDP
+/// is enabled in the connection profile of the interface.
+#[cfg(not(feature = "demo_mode"))]
+async fn handle_lldp(con: Arc<Connection>, interface: &str, topic: Arc<Topic<Vec<LldpNeighbor>>>) {
+    let device_proxy = loop {
+        if let Ok(path) = path_from_interface(&con, interface).await {
+            let proxy = devices::DeviceProxy::builder(&con)
+                .path(path)
+                .unwrap()
+                .build()
+                .await;
+
+            if let Ok(proxy) = proxy {
+                break proxy;
             }
         }
-        Err(anyhow!("No IP found"))
+
+        sleep(Duration::from_secs(1)).await;
+    };
+
+    let mut changes = device_proxy.receive_lldp_neighbors_changed().await;
+
+    if let Ok(neighbors) = device_proxy.lldp_neighbors().await {
+        topic.set(neighbors.iter().map(LldpNeighbor::from_dbus).collect());
+    }
+
+    while let Some(change) = StreamExt::next(&mut changes).await {
+        if let Ok(neighbors) = change.get().await {
+            trace!("update LLDP neighbors: {} {:?}", interface, neighbors);
+            topic.set_if_changed(neighbors.iter().map(LldpNeighbor::from_dbus).collect());
+        }
     }
 }
 
+/// Keep the IP address information of an interface up to date
+#[cfg(not(feature = "demo_mode"))]
+async fn handle_ip_info(con: Arc<Connection>, interface: &str, topic: Arc<Topic<IpInfo>>) {
+    let (path, device_proxy) = loop {
+        if let Ok(path) = path_from_interface(&con, interface).await {
+            let proxy = devices::DeviceProxy::builder(&con)
+                .path(path.clone())
+                .unwrap()
+                .build()
+                .await;
+
+            if let Ok(proxy) = proxy {
+                break (path, proxy);
+            }
+        }
+
+        sleep(Duration::from_secs(1)).await;
+    };
+
+    let mut ip_4_config = device_proxy.receive_ip4_config_changed().await;
+    let mut ip_6_config = device_proxy.receive_ip6_config_changed().await;
+
+    loop {
+        match get_ip_info(&con, path.as_str()).await {
+            Ok(info) => topic.set_if_changed(info),
+            Err(e) => trace!("Failed to get IP info for {}: {}", interface, e),
+        }
+
+        // The addresses can also change without a new IP config object
+        // being created, so poll them in addition to waiting for changes.
+        let ip_4 = StreamExt::next(&mut ip_4_config).fuse();
+        let ip_6 = StreamExt::next(&mut ip_6_config).fuse();
+        let poll = sleep(IP_POLL_INTERVAL).fuse();
+
+        pin_mut!(ip_4, ip_6, poll);
+        select! {
+            _ = ip_4 => {},
+            _ = ip_6 => {},
+            _ = poll => {},
+        };
+    }
+}
+
+/// Check if a hostname is acceptable as static system hostname
+///
+/// Only the characters and lengths allowed by RFC 1123 are accepted,
+/// limited to the 64 characters the kernel can store.
+fn validate_hostname(hostname: &str) -> std::result::Result<(), String> {
+    if hostname.is_empty() || hostname.len() > 64 {
+        return Err("The hostname must be between 1 and 64 characters long".into());
+    }
+
+    for label in hostname.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("Invalid hostname label length: \"{label}\""));
+        }
+
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!(
+                "Labels may not start or end with \"-\": \"{label}\""
+            ));
+        }
+
+        if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(format!("Invalid characters in hostname: \"{hostname}\""));
+        }
+    }
+
+    Ok(())
+}
+
 pub struct Network {
     pub hostname: Arc<Topic<String>>,
     pub bridge_interface: Arc<Topic<Vec<String>>>,
     pub dut_interface: Arc<Topic<LinkInfo>>,
     pub uplink_interface: Arc<Topic<LinkInfo>>,
+    pub bridge_ip: Arc<Topic<IpInfo>>,
+    pub dut_ip: Arc<Topic<IpInfo>>,
+    pub uplink_ip: Arc<Topic<IpInfo>>,
+    pub dut_lldp: Arc<Topic<Vec<LldpNeighbor>>>,
+    pub uplink_lldp: Arc<Topic<Vec<LldpNeighbor>>>,
 }
 
 impl Network {
@@ -250,6 +428,11 @@ impl Network {
             bridge_interface: bb.topic_ro("/v1/tac/network/interface/tac-bridge", None),
             dut_interface: bb.topic_ro("/v1/tac/network/interface/dut", None),
             uplink_interface: bb.topic_ro("/v1/tac/network/interface/uplink", None),
+            bridge_ip: bb.topic_ro("/v1/tac/network/ip/tac-bridge", None),
+            dut_ip: bb.topic_ro("/v1/tac/network/ip/dut", None),
+            uplink_ip: bb.topic_ro("/v1/tac/network/ip/uplink", None),
+            dut_lldp: bb.topic_ro("/v1/tac/network/lldp/dut", Some(Vec::new())),
+            uplink_lldp: bb.topic_ro("/v1/tac/network/lldp/uplink", Some(Vec::new())),
         }
     }
 
@@ -259,9 +442,38 @@ impl Network {
         _conn: C,
         _led_dut: Arc<Topic<BlinkPattern>>,
         _led_uplink: Arc<Topic<BlinkPattern>>,
+        setup_mode: Arc<Topic<bool>>,
     ) -> Self {
         let this = Self::setup_topics(bb);
 
+        // Use the "register a read-only and a write-only topic with the same
+        // name to perform validation" trick, so that only valid hostnames
+        // set in setup mode make it to the read-only topic.
+        let (mut hostname_requests, _) = bb
+            .topic_wo::<String>("/v1/tac/network/hostname", None)
+            .subscribe_unbounded();
+
+        {
+            let hostname_topic = this.hostname.clone();
+            let setup_mode = setup_mode.clone();
+
+            async_std::task::spawn(async move {
+                while let Some(hostname) = hostname_requests.next().await {
+                    if !setup_mode.try_get().unwrap_or(false) {
+                        warn!("Ignoring hostname change outside of setup mode");
+                        continue;
+                    }
+
+                    match validate_hostname(&hostname) {
+                        Ok(()) => hostname_topic.set(hostname),
+                        Err(e) => warn!("Ignoring invalid hostname: {e}"),
+                    }
+                }
+            });
+        }
+
+        config::setup(bb, config::Backend::new(), setup_mode);
+
         this.hostname.set("lxatac".to_string());
         this.bridge_interface.set(vec![String::from("192.168.1.1")]);
         this.dut_interface.set(LinkInfo {
@@ -272,6 +484,34 @@ impl Network {
             speed: 1000,
             carrier: true,
         });
+        this.bridge_ip.set(IpInfo {
+            ipv4: vec![IpAddress {
+                address: "192.168.1.1".to_string(),
+                prefix: 24,
+            }],
+            ipv6: vec![
+                IpAddress {
+                    address: "fe80::1".to_string(),
+                    prefix: 64,
+                },
+                IpAddress {
+                    address: "2001:db8::1".to_string(),
+                    prefix: 64,
+                },
+            ],
+            gateway4: Some("192.168.1.254".to_string()),
+            gateway6: Some("fe80::fe".to_string()),
+            dns: vec!["192.168.1.254".to_string()],
+        });
+        this.dut_ip.set(IpInfo::default());
+        this.uplink_ip.set(IpInfo::default());
+        this.uplink_lldp.set(vec![LldpNeighbor {
+            chassis_id: Some("00:00:5e:00:53:00".to_string()),
+            port_id: Some("gi1/0/17".to_string()),
+            port_description: Some("GigabitEthernet1/0/17".to_string()),
+            system_name: Some("lab-switch-3".to_string()),
+            vlan_id: Some(42),
+        }]);
 
         this
     }
@@ -282,9 +522,47 @@ impl Network {
         conn: &Arc<Connection>,
         led_dut: Arc<Topic<BlinkPattern>>,
         led_uplink: Arc<Topic<BlinkPattern>>,
+        setup_mode: Arc<Topic<bool>>,
     ) -> Self {
         let this = Self::setup_topics(bb);
 
+        // See the demo_mode implementation above on why there are two
+        // topics with the same name.
+        let (mut hostname_requests, _) = bb
+            .topic_wo::<String>("/v1/tac/network/hostname", None)
+            .subscribe_unbounded();
+
+        {
+            let conn = conn.clone();
+            let hostname_topic = this.hostname.clone();
+            let setup_mode = setup_mode.clone();
+
+            async_std::task::spawn(async move {
+                let proxy = settings::SettingsProxy::new(&conn).await.unwrap();
+
+                while let Some(hostname) = hostname_requests.next().await {
+                    if !setup_mode.try_get().unwrap_or(false) {
+                        warn!("Ignoring hostname change outside of setup mode");
+                        continue;
+                    }
+
+                    if let Err(e) = validate_hostname(&hostname) {
+                        warn!("Ignoring invalid hostname: {e}");
+                        continue;
+                    }
+
+                    match proxy.save_hostname(&hostname).await {
+                        // hostnamed will also notify us about the change,
+                        // but there is no need to wait for it.
+                        Ok(()) => hostname_topic.set(hostname),
+                        Err(e) => warn!("Failed to set the hostname to {hostname}: {e}"),
+                    }
+                }
+            });
+        }
+
+        config::setup(bb, config::Backend::new(conn.clone()), setup_mode);
+
         {
             let conn = conn.clone();
             let hostname_topic = this.hostname.clone();
@@ -358,22 +636,27 @@ impl Network {
             });
         }
 
-        {
-            let conn = conn.clone();
-            let bridge_interface = this.bridge_interface.clone();
-            async_std::task::spawn(async move {
-                let mut ip_stream = loop {
-                    if let Ok(ips) = IpStream::new(conn.clone(), "tac-bridge").await {
-                        break ips;
-                    }
+        for (interface, topic) in [
+            ("tac-bridge", &this.bridge_ip),
+            ("dut", &this.dut_ip),
+            ("uplink", &this.uplink_ip),
+        ] {
+            async_std::task::spawn(handle_ip_info(conn.clone(), interface, topic.clone()));
+        }
 
-                    sleep(Duration::from_secs(1)).await;
-                };
+        for (interface, topic) in [("dut", &this.dut_lldp), ("uplink", &this.uplink_lldp)] {
+            async_std::task::spawn(handle_lldp(conn.clone(), interface, topic.clone()));
+        }
 
-                bridge_interface.set(ip_stream.now(&conn).await.unwrap());
+        {
+            let (mut bridge_ip, _) = this.bridge_ip.clone().subscribe_unbounded();
+            let bridge_interface = this.bridge_interface.clone();
 
-                while let Ok(info) = ip_stream.next(&conn).await {
-                    bridge_interface.set(info);
+            // Keep providing the plain list of IPv4 addresses for existing users
+            async_std::task::spawn(async move {
+                while let Some(info) = bridge_ip.next().await {
+                    let ips = info.ipv4.into_iter().map(|a| a.address).collect();
+                    bridge_interface.set_if_changed(ips);
                 }
             });
         }
@@ -381,3 +664,22 @@ impl Network {
         this
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::validate_hostname;
+
+    #[test]
+    fn hostnames() {
+        assert!(validate_hostname("lxatac-00011").is_ok());
+        assert!(validate_hostname("tac.lab.example.com").is_ok());
+
+        assert!(validate_hostname("").is_err());
+        assert!(validate_hostname("-lxatac").is_err());
+        assert!(validate_hostname("lxatac-").is_err());
+        assert!(validate_hostname("lxa_tac").is_err());
+        assert!(validate_hostname("lxa tac").is_err());
+        assert!(validate_hostname("tac..lab").is_err());
+        assert!(validate_hostname(&"a".repeat(65)).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+//! # DBus interface proxies for: `org.freedesktop.NetworkManager.Settings`, `org.freedesktop.NetworkManager.Settings.Connection`
+//!
+//! This code was generated by `zbus-xmlgen` `2.0.0` from DBus introspection data
+//! and trimmed down to the methods used by the tacd.
+//! Source: `Interface '/org/freedesktop/NetworkManager/Settings' from service 'org.freedesktop.NetworkManager' on system bus`.
+//!
+
+#![allow(clippy::all)]
+
+mod settings {
+    use zbus::dbus_proxy;
+
+    #[dbus_proxy(
+        interface = "org.freedesktop.NetworkManager.Settings",
+        default_service = "org.freedesktop.NetworkManager",
+        default_path = "/org/freedesktop/NetworkManager/Settings"
+    )]
+    trait Settings {
+        /// AddConnection method
+        fn add_connection(
+            &self,
+            connection: std::collections::HashMap<
+                &str,
+                std::collections::HashMap<&str, zbus::zvariant::Value<'_>>,
+            >,
+        ) -> zbus::Result<zbus::zvariant::OwnedObjectPath>;
+
+        /// ListConnections method
+        fn list_connections(&self) -> zbus::Result<Vec<zbus::zvariant::OwnedObjectPath>>;
+
+        /// SaveHostname method
+        fn save_hostname(&self, hostname: &str) -> zbus::Result<()>;
+    }
+}
+
+mod connection {
+    use zbus::dbus_proxy;
+
+    #[dbus_proxy(
+        interface = "org.freedesktop.NetworkManager.Settings.Connection",
+        default_service = "org.freedesktop.NetworkManager"
+    )]
+    trait Connection {
+        /// Delete method
+        fn delete(&self) -> zbus::Result<()>;
+
+        /// GetSettings method
+        fn get_settings(
+            &self,
+        ) -> zbus::Result<
+            std::collections::HashMap<
+                String,
+                std::collections::HashMap<String, zbus::zvariant::OwnedValue>,
+            >,
+        >;
+
+        /// Update method
+        fn update(
+            &self,
+            properties: std::collections::HashMap<
+                &str,
+                std::collections::HashMap<&str, zbus::zvariant::Value<'_>>,
+            >,
+        ) -> zbus::Result<()>;
+    }
+}
+
+pub use connection::ConnectionProxy;
+pub use settings::SettingsProxy;
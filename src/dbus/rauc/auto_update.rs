@@ -0,0 +1,425 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use async_std::future::timeout;
+use async_std::prelude::*;
+use async_std::sync::Arc;
+use async_std::task::spawn;
+use chrono::{DateTime, Local, NaiveTime};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use super::Rauc;
+use crate::broker::{BrokerBuilder, Topic};
+use crate::dut_power::OutputState;
+use crate::measurement::Timestamp;
+use crate::time_of_day::TimeOfDay;
+
+// Re-evaluate the policies this often, even if nothing changed.
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(60);
+
+// The number of decisions to keep in the history of the decisions topic
+const DECISION_HISTORY: usize = 64;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AutoUpdateMode {
+    /// Ignore new bundles on this channel
+    Never,
+    /// Show an "update available" notification
+    Notify,
+    /// Install new bundles during the maintenance window
+    Install,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct AutoUpdatePolicy {
+    pub mode: AutoUpdateMode,
+    pub window_start: TimeOfDay,
+    pub window_end: TimeOfDay,
+    /// Reboot into the new bundle at this time after an automatic installation
+    pub reboot_at: Option<TimeOfDay>,
+}
+
+/// The policies by channel name. Channels without a policy use `Notify`.
+pub type AutoUpdatePolicies = BTreeMap<String, AutoUpdatePolicy>;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AutoUpdateDecision {
+    pub ts: Timestamp,
+    pub channel: Option<String>,
+    pub message: String,
+}
+
+pub fn mode_of(policies: Option<&AutoUpdatePolicies>, channel: &str) -> AutoUpdateMode {
+    policies
+        .and_then(|p| p.get(channel))
+        .map(|p| p.mode)
+        .unwrap_or(AutoUpdateMode::Notify)
+}
+
+/// Get the next point in time that has the given time of day
+fn next_occurrence(time: TimeOfDay) -> Option<DateTime<Local>> {
+    let now = Local::now();
+    let today = now
+        .date_naive()
+        .and_time(time.time())
+        .and_local_timezone(Local)
+        .earliest()?;
+
+    if today > now {
+        Some(today)
+    } else {
+        Some(today + chrono::Duration::days(1))
+    }
+}
+
+/// Why automatic installations and reboots have to wait, if they have to
+///
+/// A DUT power switch that was turned off due to a fault (e.g. OverCurrent)
+/// does not block updates, as there can be no test running on the unpowered
+/// DUT and the state is kept until someone requests power again, which may
+/// well be never.
+fn update_blocker(dut_state: Option<OutputState>, place_locked: bool) -> Option<&'static str> {
+    let dut_powered = match dut_state {
+        Some(OutputState::Off) | Some(OutputState::OffFloating) => false,
+        Some(OutputState::InvertedPolarity)
+        | Some(OutputState::OverCurrent)
+        | Some(OutputState::OverVoltage)
+        | Some(OutputState::RealtimeViolation)
+        | Some(OutputState::OverTemperature) => false,
+        Some(OutputState::On) | Some(OutputState::Changing) | None => true,
+    };
+
+    if dut_powered {
+        Some("the DUT is powered")
+    } else if place_locked {
+        Some("the labgrid place is locked")
+    } else {
+        None
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum Decision {
+    /// Do not install the bundle (yet) for the given reason
+    Skip(String),
+    /// Installing the bundle was already attempted, do not try again
+    Attempted,
+    Install,
+}
+
+/// Decide what to do about a new bundle on a channel
+fn decide(
+    policy: Option<&AutoUpdatePolicy>,
+    version: &str,
+    attempted: bool,
+    now: NaiveTime,
+    blocker: Option<&str>,
+    busy: bool,
+) -> Decision {
+    let policy = match policy {
+        Some(policy) if policy.mode == AutoUpdateMode::Install => policy,
+        Some(policy) if policy.mode == AutoUpdateMode::Never => {
+            return Decision::Skip(format!("Ignoring bundle {version}"));
+        }
+        _ => return Decision::Skip(format!("Notifying about bundle {version}")),
+    };
+
+    if attempted {
+        return Decision::Attempted;
+    }
+
+    if !TimeOfDay::span_contains(policy.window_start, policy.window_end, now) {
+        return Decision::Skip(format!(
+            "Will install bundle {version} during the maintenance window from {} to {}",
+            String::from(policy.window_start),
+            String::from(policy.window_end),
+        ));
+    }
+
+    if let Some(blocker) = blocker {
+        return Decision::Skip(format!(
+            "Deferring installation of bundle {version} while {blocker}"
+        ));
+    }
+
+    if busy {
+        return Decision::Skip(format!(
+            "Deferring installation of bundle {version} while RAUC is busy"
+        ));
+    }
+
+    Decision::Install
+}
+
+struct Decisions {
+    topic: Arc<Topic<AutoUpdateDecision>>,
+    previous: HashMap<Option<String>, String>,
+}
+
+impl Decisions {
+    /// Log a decision, unless it is the same as the last one for this channel
+    fn log(&mut self, channel: Option<&str>, message: String) {
+        let channel = channel.map(|c| c.to_string());
+
+        if self.previous.get(&channel) == Some(&message) {
+            return;
+        }
+
+        match &channel {
+            Some(ch) => info!("Auto update of channel \"{ch}\": {message}"),
+            None => info!("Auto update: {message}"),
+        }
+
+        self.previous.insert(channel.clone(), message.clone());
+
+        self.topic.set(AutoUpdateDecision {
+            ts: Timestamp::now(),
+            channel,
+            message,
+        });
+    }
+}
+
+impl Rauc {
+    /// Act on new bundles in the update channels according to the policies
+    ///
+    /// Installations are deferred while the DUT is powered or the labgrid
+    /// place is locked, so that running tests are not disturbed.
+    /// The same is true for the reboot after an installation.
+    pub fn auto_update(
+        &self,
+        bb: &mut BrokerBuilder,
+        reboot: Arc<Topic<bool>>,
+        dut_state: Arc<Topic<OutputState>>,
+    ) {
+        // There is no way for the tacd to know if the labgrid place the TAC
+        // is part of is locked. Labgrid hooks or scripts on the coordinator
+        // have to set this topic on acquire/release instead.
+        // The topic is persistent, so that a place that was locked stays
+        // locked when the tacd is restarted.
+        let place_locked = bb.topic("/v1/labgrid/place_locked", true, true, true, Some(false), 1);
+
+        let mut decisions = Decisions {
+            topic: bb.topic(
+                "/v1/tac/update/decisions",
+                true,
+                false,
+                false,
+                None,
+                DECISION_HISTORY,
+            ),
+            previous: HashMap::new(),
+        };
+
+        let channels = self.channels.clone();
+        let policies = self.policies.clone();
+        let operation = self.operation.clone();
+        let should_reboot = self.should_reboot.clone();
+        let install = self.install.clone();
+
+        let (channels_events, _) = channels.clone().subscribe_unbounded();
+        let (policies_events, _) = policies.clone().subscribe_unbounded();
+        let (should_reboot_events, _) = should_reboot.clone().subscribe_unbounded();
+
+        let mut changes = channels_events
+            .map(|_| ())
+            .merge(policies_events.map(|_| ()))
+            .merge(should_reboot_events.map(|_| ()));
+
+        spawn(async move {
+            // The versions we already tried to install, so that a failing
+            // installation is not retried over and over again.
+            let mut attempted: Vec<String> = Vec::new();
+
+            // Reboot at the given time if we installed a bundle
+            let mut reboot_at: Option<DateTime<Local>> = None;
+
+            loop {
+                if let Ok(None) = timeout(SCHEDULE_INTERVAL, changes.next()).await {
+                    break;
+                }
+
+                let now = Local::now();
+
+                let locked = place_locked.try_get().unwrap_or(true);
+                let busy = operation.try_get().map(|op| op != "idle").unwrap_or(true);
+                let blocker = update_blocker(dut_state.try_get(), locked);
+
+                if let Some(at) = reboot_at.filter(|_| !busy) {
+                    if now < at {
+                        decisions.log(None, format!("Will reboot at {}", at.format("%F %R")));
+                    } else if !should_reboot.try_get().unwrap_or(false) {
+                        decisions.log(
+                            None,
+                            "Not rebooting, the installed bundle would not be booted".into(),
+                        );
+                        reboot_at = None;
+                    } else if let Some(blocker) = blocker {
+                        decisions.log(None, format!("Deferring the reboot while {blocker}"));
+                    } else {
+                        decisions.log(None, "Rebooting into the new bundle".into());
+                        reboot_at = None;
+                        reboot.set(true);
+                    }
+                }
+
+                let policies = policies.try_get();
+
+                for ch in channels.try_get().unwrap_or_default() {
+                    let bundle = match ch.bundle.as_ref() {
+                        Some(b) if ch.enabled && b.newer_than_installed => b,
+                        _ => continue,
+                    };
+
+                    let name = Some(ch.name.as_str());
+
+                    let policy = policies.as_ref().and_then(|p| p.get(&ch.name));
+                    let decision = decide(
+                        policy,
+                        &bundle.version,
+                        attempted.contains(&bundle.version),
+                        now.time(),
+                        blocker,
+                        busy,
+                    );
+
+                    match decision {
+                        Decision::Skip(message) => {
+                            decisions.log(name, message);
+                            continue;
+                        }
+                        Decision::Attempted => continue,
+                        Decision::Install => {}
+                    }
+
+                    decisions.log(name, format!("Installing bundle {}", bundle.version));
+
+                    attempted.push(bundle.version.clone());
+                    install.set(ch.url.clone());
+
+                    reboot_at = policy.and_then(|p| p.reboot_at).and_then(next_occurrence);
+
+                    // Only install one bundle at a time
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+
+    use super::{decide, update_blocker, AutoUpdateMode, AutoUpdatePolicy, Decision};
+    use crate::dut_power::OutputState;
+    use crate::time_of_day::TimeOfDay;
+
+    fn at(hour: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn dut_and_lock() {
+        assert_eq!(update_blocker(Some(OutputState::Off), false), None);
+        assert_eq!(update_blocker(Some(OutputState::OffFloating), false), None);
+
+        // The DUT power switch was turned off due to a fault
+        assert_eq!(update_blocker(Some(OutputState::OverCurrent), false), None);
+        assert_eq!(
+            update_blocker(Some(OutputState::OverTemperature), false),
+            None
+        );
+
+        assert_eq!(
+            update_blocker(Some(OutputState::On), false),
+            Some("the DUT is powered")
+        );
+        assert_eq!(
+            update_blocker(Some(OutputState::Changing), false),
+            Some("the DUT is powered")
+        );
+        assert_eq!(update_blocker(None, false), Some("the DUT is powered"));
+
+        assert_eq!(
+            update_blocker(Some(OutputState::Off), true),
+            Some("the labgrid place is locked")
+        );
+    }
+
+    #[test]
+    fn policy() {
+        let mut policy = AutoUpdatePolicy {
+            mode: AutoUpdateMode::Install,
+            window_start: TimeOfDay::new(22, 0),
+            window_end: TimeOfDay::new(6, 0),
+            reboot_at: None,
+        };
+
+        let decide_at = |policy: Option<&AutoUpdatePolicy>, hour, blocker, busy| {
+            decide(policy, "1.2", false, at(hour), blocker, busy)
+        };
+
+        // Inside of the window and nothing blocking
+        assert_eq!(decide_at(Some(&policy), 23, None, false), Decision::Install);
+        assert_eq!(decide_at(Some(&policy), 3, None, false), Decision::Install);
+
+        assert_eq!(
+            decide_at(Some(&policy), 12, None, false),
+            Decision::Skip(
+                "Will install bundle 1.2 during the maintenance window from 22:00 to 06:00".into()
+            )
+        );
+        assert_eq!(
+            decide_at(
+                Some(&policy),
+                23,
+                Some("the labgrid place is locked"),
+                false
+            ),
+            Decision::Skip(
+                "Deferring installation of bundle 1.2 while the labgrid place is locked".into()
+            )
+        );
+        assert_eq!(
+            decide_at(Some(&policy), 23, None, true),
+            Decision::Skip("Deferring installation of bundle 1.2 while RAUC is busy".into())
+        );
+
+        // Do not retry failed installations
+        assert_eq!(
+            decide(Some(&policy), "1.2", true, at(23), None, false),
+            Decision::Attempted
+        );
+
+        // Only install if the channel asks for it
+        assert_eq!(
+            decide_at(None, 23, None, false),
+            Decision::Skip("Notifying about bundle 1.2".into())
+        );
+
+        policy.mode = AutoUpdateMode::Never;
+        assert_eq!(
+            decide_at(Some(&policy), 23, None, false),
+            Decision::Skip("Ignoring bundle 1.2".into())
+        );
+    }
+}
@@ -23,13 +23,19 @@ use async_std::channel::Receiver;
 use async_std::stream::StreamExt;
 use async_std::sync::Arc;
 use async_std::task::{sleep, spawn, JoinHandle};
-use log::warn;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
 use super::Connection;
 use crate::broker::{BrokerBuilder, Topic};
+use crate::config::UpdateConfig;
 
+mod auto_update;
+mod slots;
 mod update_channels;
+mod upload;
+pub use auto_update::{mode_of, AutoUpdateMode, AutoUpdatePolicies};
+pub use slots::{other_rootfs, MarkRequest, SlotBootAttempts, SlotMark};
 pub use update_channels::Channel;
 
 #[cfg(feature = "demo_mode")]
@@ -43,13 +49,25 @@ use installer::InstallerProxy;
 
 #[cfg(feature = "demo_mode")]
 mod imports {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use async_std::sync::Arc;
+    use async_std::task::{sleep, spawn};
+
+    use crate::broker::Topic;
+
+    // There is no RAUC to report the operation it performs in demo mode,
+    // so the fake proxy below updates the topic of the Rauc instance itself.
+    pub static DEMO_OPERATION: Mutex<Option<Arc<Topic<String>>>> = Mutex::new(None);
+
     pub struct InstallerProxy<'a> {
         _dummy: &'a (),
     }
 
     impl<'a> InstallerProxy<'a> {
-        pub async fn new<C>(_conn: C) -> Option<InstallerProxy<'a>> {
-            Some(Self { _dummy: &() })
+        pub async fn new<C>(_conn: C) -> anyhow::Result<InstallerProxy<'a>> {
+            Ok(Self { _dummy: &() })
         }
 
         pub async fn info(&self, _url: &str) -> anyhow::Result<(String, String)> {
@@ -58,18 +76,35 @@ mod imports {
 
             Ok((compatible, version))
         }
-    }
 
-    pub const CHANNELS_DIR: &str = "demo_files/usr/share/tacd/update_channels";
+        pub async fn compatible(&self) -> anyhow::Result<String> {
+            Ok("LXA TAC".to_string())
+        }
+
+        pub async fn install(&self, _source: &str) -> anyhow::Result<()> {
+            let operation = DEMO_OPERATION.lock().unwrap().clone();
+
+            // Pretend to install the bundle for a while
+            if let Some(operation) = operation {
+                spawn(async move {
+                    operation.set("installing".to_string());
+                    sleep(Duration::from_secs(10)).await;
+                    operation.set("idle".to_string());
+                });
+            }
+
+            Ok(())
+        }
+    }
 }
 
 #[cfg(not(feature = "demo_mode"))]
 mod imports {
     pub use anyhow::{anyhow, bail, Result};
+    pub use async_std::channel::unbounded;
+    pub use async_std::task::spawn_blocking;
     pub use futures::{select, FutureExt};
     pub use log::{error, info};
-
-    pub const CHANNELS_DIR: &str = "/usr/share/tacd/update_channels";
 }
 
 const RELOAD_RATE_LIMIT: Duration = Duration::from_secs(10 * 60);
@@ -93,7 +128,7 @@ impl From<(i32, String, i32)> for Progress {
     }
 }
 
-type SlotStatus = HashMap<String, HashMap<String, String>>;
+pub type SlotStatus = HashMap<String, HashMap<String, String>>;
 
 pub struct Rauc {
     pub operation: Arc<Topic<String>>,
@@ -104,6 +139,11 @@ pub struct Rauc {
     pub channels: Arc<Topic<Vec<Channel>>>,
     pub reload: Arc<Topic<bool>>,
     pub should_reboot: Arc<Topic<bool>>,
+    pub policies: Arc<Topic<AutoUpdatePolicies>>,
+    pub mark: Arc<Topic<MarkRequest>>,
+    pub primary: Arc<Topic<String>>,
+    pub boot_attempts: Arc<Topic<SlotBootAttempts>>,
+    conn: Arc<Connection>,
 }
 
 fn compare_versions(v1: &str, v2: &str) -> Option<Ordering> {
@@ -208,6 +248,7 @@ async fn channel_list_update_task(
     mut reload_stream: Receiver<bool>,
     channels: Arc<Topic<Vec<Channel>>>,
     slot_status: Arc<Topic<Arc<SlotStatus>>>,
+    channels_dir: String,
 ) {
     let mut previous: Option<Instant> = None;
     let mut polling_tasks: Vec<JoinHandle<_>> = Vec::new();
@@ -227,7 +268,7 @@ async fn channel_list_update_task(
         }
 
         // Read the list of available update channels
-        let new_channels = match Channel::from_directory(CHANNELS_DIR) {
+        let new_channels = match Channel::from_directory(&channels_dir) {
             Ok(chs) => chs,
             Err(e) => {
                 warn!("Failed to get list of update channels: {e}");
@@ -261,7 +302,7 @@ async fn channel_list_update_task(
 }
 
 impl Rauc {
-    fn setup_topics(bb: &mut BrokerBuilder) -> Self {
+    fn setup_topics(bb: &mut BrokerBuilder, conn: &Arc<Connection>) -> Self {
         Self {
             operation: bb.topic_ro("/v1/tac/update/operation", None),
             progress: bb.topic_ro("/v1/tac/update/progress", None),
@@ -271,17 +312,59 @@ impl Rauc {
             channels: bb.topic_ro("/v1/tac/update/channels", None),
             reload: bb.topic_wo("/v1/tac/update/channels/reload", Some(true)),
             should_reboot: bb.topic_ro("/v1/tac/update/should_reboot", Some(false)),
+            policies: bb.topic(
+                "/v1/tac/update/policies",
+                true,
+                true,
+                true,
+                Some(AutoUpdatePolicies::new()),
+                1,
+            ),
+            mark: bb.topic_wo("/v1/tac/update/slots/mark", None),
+            primary: bb.topic_ro("/v1/tac/update/slots/primary", None),
+            boot_attempts: bb.topic_ro("/v1/tac/update/slots/boot_attempts", None),
+            conn: conn.clone(),
         }
     }
 
     #[cfg(feature = "demo_mode")]
-    pub fn new(bb: &mut BrokerBuilder, _conn: &Arc<Connection>) -> Self {
-        let inst = Self::setup_topics(bb);
+    pub fn new(bb: &mut BrokerBuilder, conn: &Arc<Connection>, config: &UpdateConfig) -> Self {
+        let inst = Self::setup_topics(bb, conn);
+
+        let slot_status = demo_mode::slot_status();
 
         inst.operation.set("idle".to_string());
-        inst.slot_status.set(Arc::new(demo_mode::slot_status()));
+        inst.primary.set("rootfs.0".to_string());
+
+        *DEMO_OPERATION.lock().unwrap() = Some(inst.operation.clone());
         inst.last_error.set("".to_string());
 
+        match slots::boot_attempts(&slot_status) {
+            Ok(attempts) => inst.boot_attempts.set(attempts),
+            Err(e) => warn!("Failed to get boot attempts: {e}"),
+        }
+
+        let (mut mark_stream, _) = inst.mark.clone().subscribe_unbounded();
+        let primary = inst.primary.clone();
+        let other = other_rootfs(&slot_status);
+
+        inst.slot_status.set(Arc::new(slot_status));
+
+        // Pretend to switch the primary slot
+        spawn(async move {
+            while let Some(req) = mark_stream.next().await {
+                info!("Marking slot {} as {}", req.slot, req.state.as_rauc_str());
+
+                if req.state == SlotMark::Active {
+                    match req.slot.as_str() {
+                        "booted" => primary.set("rootfs.0".to_string()),
+                        "other" => primary.set(other.clone().unwrap_or_default()),
+                        slot => primary.set(slot.to_string()),
+                    }
+                }
+            }
+        });
+
         // Reload the channel list on request
         let (reload_stream, _) = inst.reload.clone().subscribe_unbounded();
         spawn(channel_list_update_task(
@@ -289,20 +372,27 @@ impl Rauc {
             reload_stream,
             inst.channels.clone(),
             inst.slot_status.clone(),
+            config.channels_dir.clone(),
         ));
 
         inst
     }
 
     #[cfg(not(feature = "demo_mode"))]
-    pub fn new(bb: &mut BrokerBuilder, conn: &Arc<Connection>) -> Self {
-        let inst = Self::setup_topics(bb);
+    pub fn new(bb: &mut BrokerBuilder, conn: &Arc<Connection>, config: &UpdateConfig) -> Self {
+        let inst = Self::setup_topics(bb, conn);
 
         let conn_task = conn.clone();
         let operation = inst.operation.clone();
         let slot_status = inst.slot_status.clone();
         let channels = inst.channels.clone();
         let should_reboot = inst.should_reboot.clone();
+        let primary = inst.primary.clone();
+        let boot_attempts = inst.boot_attempts.clone();
+
+        // Marking a slot does not change the current operation, but should
+        // still update the slot status.
+        let (refresh_tx, refresh_rx) = unbounded::<()>();
 
         spawn(async move {
             let proxy = InstallerProxy::new(&conn_task).await.unwrap();
@@ -379,19 +469,35 @@ impl Rauc {
                         Err(e) => warn!("Could not determine if TAC should be rebooted: {e}"),
                     }
 
+                    match proxy.get_primary().await {
+                        Ok(p) => primary.set_if_changed(p),
+                        Err(e) => warn!("Failed to get primary slot: {e}"),
+                    }
+
+                    let slots_task = slots.clone();
+
+                    match spawn_blocking(move || slots::boot_attempts(&slots_task)).await {
+                        Ok(attempts) => boot_attempts.set_if_changed(attempts),
+                        Err(e) => warn!("Failed to get boot attempts: {e}"),
+                    }
+
                     // In the RAUC API the slot status is a list of (name, info) tuples.
                     // It is once again easier in typescript to represent it as a dict with
                     // the names as keys, so that is what's exposed here.
                     slot_status.set(Arc::new(slots));
                 }
 
-                // Wait for the current operation to change
-                if let Some(v) = stream.next().await {
-                    if let Ok(v) = v.get().await {
-                        operation.set(v);
-                    }
-                } else {
-                    break;
+                // Wait for the current operation to change or a refresh request
+                select! {
+                    v = stream.next().fuse() => match v {
+                        Some(v) => {
+                            if let Ok(v) = v.get().await {
+                                operation.set(v);
+                            }
+                        }
+                        None => break,
+                    },
+                    _ = refresh_rx.recv().fuse() => {}
                 }
             }
         });
@@ -454,6 +560,28 @@ impl Rauc {
             }
         });
 
+        let conn_task = conn.clone();
+        let (mut mark_stream, _) = inst.mark.clone().subscribe_unbounded();
+
+        // Forward requests to mark slots as good/bad/active to RAUC
+        spawn(async move {
+            let proxy = InstallerProxy::new(&conn_task).await.unwrap();
+
+            while let Some(req) = mark_stream.next().await {
+                match proxy.mark(req.state.as_rauc_str(), &req.slot).await {
+                    Ok((slot, msg)) => info!("Marked slot {slot}: {msg}"),
+                    Err(e) => warn!(
+                        "Failed to mark slot {} as {}: {}",
+                        req.slot,
+                        req.state.as_rauc_str(),
+                        e
+                    ),
+                }
+
+                let _ = refresh_tx.send(()).await;
+            }
+        });
+
         // Reload the channel list on request
         let (reload_stream, _) = inst.reload.clone().subscribe_unbounded();
         spawn(channel_list_update_task(
@@ -461,6 +589,7 @@ impl Rauc {
             reload_stream,
             inst.channels.clone(),
             inst.slot_status.clone(),
+            config.channels_dir.clone(),
         ));
 
         inst
@@ -0,0 +1,216 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::SlotStatus;
+
+#[cfg(feature = "demo_mode")]
+mod barebox_state {
+    use anyhow::Result;
+
+    pub fn dump() -> Result<String> {
+        Ok("bootstate.last_chosen=1\n\
+            bootstate.system0.priority=20\n\
+            bootstate.system0.remaining_attempts=3\n\
+            bootstate.system1.priority=10\n\
+            bootstate.system1.remaining_attempts=3\n"
+            .to_string())
+    }
+}
+
+#[cfg(not(feature = "demo_mode"))]
+mod barebox_state {
+    use std::process::Command;
+
+    use anyhow::{bail, Result};
+
+    /// Dump the bootchooser state barebox uses to select the slot to boot
+    pub fn dump() -> Result<String> {
+        let output = Command::new("barebox-state").arg("--dump").output()?;
+
+        if !output.status.success() {
+            bail!(
+                "barebox-state failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        Ok(String::from_utf8(output.stdout)?)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SlotMark {
+    /// The slot booted successfully
+    Good,
+    /// The slot should not be booted anymore
+    Bad,
+    /// Boot the slot next (this also marks it as good)
+    Active,
+}
+
+impl SlotMark {
+    pub fn as_rauc_str(&self) -> &'static str {
+        match self {
+            Self::Good => "good",
+            Self::Bad => "bad",
+            Self::Active => "active",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct MarkRequest {
+    /// "booted", "other" or the name of a slot like "rootfs.0"
+    pub slot: String,
+    pub state: SlotMark,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+pub struct BootAttempts {
+    pub priority: u32,
+    pub remaining_attempts: u32,
+}
+
+/// The bootchooser state of each slot by slot name (e.g. "rootfs.0")
+pub type SlotBootAttempts = BTreeMap<String, BootAttempts>;
+
+/// Match the bootchooser targets in the barebox-state dump to slot names
+///
+/// The dump contains lines like `bootstate.system0.remaining_attempts=3`,
+/// where `system0` is the `bootname` RAUC reports for a slot.
+fn parse_boot_attempts(dump: &str, slot_status: &SlotStatus) -> SlotBootAttempts {
+    let mut attempts = SlotBootAttempts::new();
+
+    for line in dump.lines() {
+        let (key, value) = match line.trim().split_once('=') {
+            Some(kv) => kv,
+            None => continue,
+        };
+
+        let mut parts = key.split('.');
+
+        let (bootname, var) = match (parts.next(), parts.next(), parts.next()) {
+            (Some("bootstate"), Some(bootname), Some(var)) => (bootname, var),
+            _ => continue,
+        };
+
+        let value: u32 = match value.trim().parse() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        let slot = slot_status
+            .values()
+            .find(|info| info.get("bootname").map(|b| b == bootname).unwrap_or(false))
+            .and_then(|info| info.get("name"));
+
+        if let Some(slot) = slot {
+            let entry = attempts.entry(slot.clone()).or_default();
+
+            match var {
+                "priority" => entry.priority = value,
+                "remaining_attempts" => entry.remaining_attempts = value,
+                _ => {}
+            }
+        }
+    }
+
+    attempts
+}
+
+pub fn boot_attempts(slot_status: &SlotStatus) -> Result<SlotBootAttempts> {
+    let dump = barebox_state::dump()?;
+
+    Ok(parse_boot_attempts(&dump, slot_status))
+}
+
+/// Get the name of the rootfs slot that is not currently booted
+pub fn other_rootfs(slot_status: &SlotStatus) -> Option<String> {
+    slot_status
+        .values()
+        .filter(|info| {
+            info.get("slot_class")
+                .map(|c| c == "rootfs")
+                .unwrap_or(false)
+        })
+        .find(|info| info.get("state").map(|s| s != "booted").unwrap_or(false))
+        .and_then(|info| info.get("name").cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{other_rootfs, parse_boot_attempts, BootAttempts};
+
+    #[test]
+    fn boot_attempts() {
+        let slot = |name: &str, bootname: &str, state: &str| {
+            let info: HashMap<String, String> = [
+                ("name", name),
+                ("bootname", bootname),
+                ("state", state),
+                ("slot_class", "rootfs"),
+            ]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+            (name.replace('.', "_"), info)
+        };
+
+        let slot_status = vec![
+            slot("rootfs.0", "system0", "booted"),
+            slot("rootfs.1", "system1", "inactive"),
+        ]
+        .into_iter()
+        .collect();
+
+        let attempts = parse_boot_attempts(
+            "bootstate.last_chosen=1\n\
+             bootstate.system0.priority=20\n\
+             bootstate.system0.remaining_attempts=3\n\
+             bootstate.system1.priority=10\n\
+             bootstate.system1.remaining_attempts=0\n\
+             bootstate.system2.priority=5\n",
+            &slot_status,
+        );
+
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(
+            attempts["rootfs.0"],
+            BootAttempts {
+                priority: 20,
+                remaining_attempts: 3
+            }
+        );
+        assert_eq!(
+            attempts["rootfs.1"],
+            BootAttempts {
+                priority: 10,
+                remaining_attempts: 0
+            }
+        );
+
+        assert_eq!(other_rootfs(&slot_status).as_deref(), Some("rootfs.1"));
+    }
+}
@@ -15,16 +15,196 @@
 // with this program; if not, write to the Free Software Foundation, Inc.,
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 
+use std::cmp::Ordering;
 use std::fs::{read_dir, read_to_string, DirEntry};
 use std::os::unix::ffi::OsStrExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use anyhow::{anyhow, bail, Result};
 use serde::{Deserialize, Serialize};
+use surf::Url;
 
 use super::{compare_versions, InstallerProxy, SlotStatus};
 
+#[cfg(feature = "demo_mode")]
+mod http {
+    use anyhow::Result;
+
+    use super::{Fetched, HttpCache, Method};
+
+    const DEMO_INDEX: &str = "bundles:
+  - version: \"4.0-0-20230428214619\"
+    url: lxatac-core-bundle-base-lxatac.raucb
+    size: 298475520
+    release_notes: |
+      Add support for update channel indexes.
+";
+
+    const DEMO_SIGNATURE: &str = "-----BEGIN CMS-----
+-----END CMS-----
+";
+
+    // Pretend the server sends the same response with the same ETag every time
+    pub async fn fetch(url: &str, method: Method, cache: &HttpCache) -> Result<Fetched> {
+        if cache.etag.is_some() {
+            return Ok(Fetched::NotModified);
+        }
+
+        let content = match url.ends_with(".sig") {
+            true => DEMO_SIGNATURE,
+            false => DEMO_INDEX,
+        };
+
+        let body = match method {
+            Method::Head => None,
+            Method::Get => Some(content.to_string()),
+        };
+
+        Ok(Fetched::Modified {
+            body,
+            size: Some(298475520),
+            cache: HttpCache {
+                etag: Some("\"demo\"".to_string()),
+                last_modified: None,
+            },
+        })
+    }
+}
+
+#[cfg(not(feature = "demo_mode"))]
+mod http {
+    use anyhow::{anyhow, bail, Result};
+    use surf::StatusCode;
+
+    use super::{Fetched, HttpCache, Method};
+
+    /// Perform a conditional request using the validators of the last response
+    pub async fn fetch(url: &str, method: Method, cache: &HttpCache) -> Result<Fetched> {
+        let mut req = match method {
+            Method::Head => surf::head(url),
+            Method::Get => surf::get(url),
+        };
+
+        if let Some(etag) = &cache.etag {
+            req = req.header("If-None-Match", etag.as_str());
+        }
+
+        if let Some(last_modified) = &cache.last_modified {
+            req = req.header("If-Modified-Since", last_modified.as_str());
+        }
+
+        let mut res = req
+            .await
+            .map_err(|e| anyhow!("Request to {url} failed: {e}"))?;
+
+        if res.status() == StatusCode::NotModified {
+            return Ok(Fetched::NotModified);
+        }
+
+        if !res.status().is_success() {
+            bail!("Request to {url} failed with status {}", res.status());
+        }
+
+        let header = |name| res.header(name).map(|v| v.last().as_str().to_string());
+
+        let cache = HttpCache {
+            etag: header("ETag"),
+            last_modified: header("Last-Modified"),
+        };
+
+        let size = res.len().map(|l| l as u64);
+
+        let body = match method {
+            Method::Head => None,
+            Method::Get => Some(
+                res.body_string()
+                    .await
+                    .map_err(|e| anyhow!("Failed to read response from {url}: {e}"))?,
+            ),
+        };
+
+        Ok(Fetched::Modified { body, size, cache })
+    }
+}
+
+#[cfg(feature = "demo_mode")]
+mod signature {
+    use std::path::Path;
+
+    use anyhow::Result;
+
+    // There are no real certificates in demo mode to check against
+    pub fn verify(_index: &str, _signature: &str, _cert: &Path) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "demo_mode"))]
+mod signature {
+    use std::env::temp_dir;
+    use std::fs::{remove_file, write};
+    use std::io::Write;
+    use std::path::Path;
+    use std::process::{self, Command, Stdio};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use anyhow::{anyhow, bail, Result};
+
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    /// Verify the detached CMS signature of a channel index
+    ///
+    /// The signature is checked using the same certificate RAUC uses to
+    /// verify the bundles of the channel.
+    pub fn verify(index: &str, signature: &str, cert: &Path) -> Result<()> {
+        // openssl can only read the signed content from a file.
+        // Channels may be polled concurrently, so every call gets its own.
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let file_name = format!("tacd-index-{}-{}.yaml", process::id(), id);
+        let content = temp_dir().join(file_name);
+        write(&content, index)?;
+
+        let res = run_openssl(&content, signature, cert);
+        let _ = remove_file(&content);
+
+        res
+    }
+
+    fn run_openssl(content: &Path, signature: &str, cert: &Path) -> Result<()> {
+        let mut child = Command::new("openssl")
+            .args([
+                "cms", "-verify", "-binary", "-inform", "PEM", "-purpose", "any",
+            ])
+            .arg("-content")
+            .arg(content)
+            .arg("-CAfile")
+            .arg(cert)
+            .args(["-out", "/dev/null"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("Failed to pass the signature to openssl"))?
+            .write_all(signature.as_bytes())?;
+
+        let output = child.wait_with_output()?;
+
+        if !output.status.success() {
+            bail!(
+                "openssl cms -verify failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(feature = "demo_mode")]
 const ENABLE_DIR: &str = "demo_files/etc/rauc/certificates-enabled";
 
@@ -35,11 +215,37 @@ const ONE_MINUTE: Duration = Duration::from_secs(60);
 const ONE_HOUR: Duration = Duration::from_secs(60 * 60);
 const ONE_DAY: Duration = Duration::from_secs(24 * 60 * 60);
 
+pub enum Method {
+    Head,
+    Get,
+}
+
+pub enum Fetched {
+    NotModified,
+    Modified {
+        body: Option<String>,
+        size: Option<u64>,
+        cache: HttpCache,
+    },
+}
+
+/// The validators of the last response, used to ask the server if a
+/// resource has changed without transferring it again.
+#[derive(Clone, PartialEq, Default)]
+pub struct HttpCache {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct UpstreamBundle {
     pub compatible: String,
     pub version: String,
     pub newer_than_installed: bool,
+    /// The size of the bundle in bytes, if known
+    pub size: Option<u64>,
+    /// The release notes from the channel index, if there is one
+    pub release_notes: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
@@ -47,10 +253,15 @@ pub struct Channel {
     pub name: String,
     pub display_name: String,
     pub description: String,
+    /// The URL of the newest bundle in the channel
     pub url: String,
+    /// The URL of the remote channel index the bundle URL is taken from
+    pub index: Option<String>,
     pub polling_interval: Option<Duration>,
     pub enabled: bool,
     pub bundle: Option<UpstreamBundle>,
+    #[serde(skip)]
+    cache: HttpCache,
 }
 
 #[derive(Deserialize)]
@@ -58,10 +269,60 @@ pub struct ChannelFile {
     pub name: String,
     pub display_name: String,
     pub description: String,
-    pub url: String,
+    pub url: Option<String>,
+    /// The URL of a channel index. Has to be a http:// URL, as the index is
+    /// fetched by tacd itself, which lacks TLS support. The bundles listed in
+    /// it are downloaded by RAUC and may also use https://.
+    /// A detached signature of the index has to be available at the same
+    /// URL with ".sig" appended.
+    pub index: Option<String>,
     pub polling_interval: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct IndexEntry {
+    version: String,
+    /// The URL of the bundle. May be relative to the URL of the index.
+    url: String,
+    size: Option<u64>,
+    release_notes: Option<String>,
+}
+
+/// A list of bundles available in a channel
+///
+/// As the index is fetched via plain http:// it comes with a detached,
+/// PEM encoded CMS signature (e.g. `index.yaml.sig`), created using:
+///
+/// ```text
+/// openssl cms -sign -binary -outform PEM -in index.yaml \
+///     -signer signing.cert.pem -inkey signing.key.pem -out index.yaml.sig
+/// ```
+///
+/// The signature is verified against the certificate of the channel in
+/// the RAUC keyring before the index is used. This keeps others from
+/// e.g. changing the release notes or offering an older bundle as newest.
+/// The bundle picked from the index is additionally verified by RAUC
+/// before it is offered for installation.
+#[derive(Deserialize)]
+struct ChannelIndex {
+    bundles: Vec<IndexEntry>,
+}
+
+impl ChannelIndex {
+    /// Get the newest bundle in the index with its URL resolved
+    fn newest(self, index_url: &str) -> Result<IndexEntry> {
+        let mut newest = self
+            .bundles
+            .into_iter()
+            .max_by(|a, b| compare_versions(&a.version, &b.version).unwrap_or(Ordering::Equal))
+            .ok_or_else(|| anyhow!("The channel index at {index_url} lists no bundles"))?;
+
+        newest.url = Url::parse(index_url)?.join(&newest.url)?.to_string();
+
+        Ok(newest)
+    }
+}
+
 impl Channel {
     fn from_file(path: &Path) -> Result<Self> {
         let file_name = || {
@@ -102,14 +363,40 @@ impl Channel {
             None => None,
         };
 
+        // The bundle URL of channels with an index is only known once the
+        // index was fetched.
+        let (url, index) = match (channel_file.url, channel_file.index) {
+            (Some(url), None) => (url.trim().to_string(), None),
+            (None, Some(index)) => {
+                let index = index.trim().to_string();
+
+                match Url::parse(&index) {
+                    Ok(url) if url.scheme() == "http" => {}
+                    Ok(_) => bail!(
+                        "The index in \"{}\" is not a http:// URL, which is the only kind supported",
+                        file_name()
+                    ),
+                    Err(e) => bail!("Failed to parse index in \"{}\": {}", file_name(), e),
+                }
+
+                (String::new(), Some(index))
+            }
+            _ => bail!(
+                "The channel file \"{}\" needs to have either an url or an index",
+                file_name()
+            ),
+        };
+
         let mut ch = Self {
             name: channel_file.name,
             display_name: channel_file.display_name,
             description: channel_file.description,
-            url: channel_file.url.trim().to_string(),
+            url,
+            index,
             polling_interval,
             enabled: false,
             bundle: None,
+            cache: HttpCache::default(),
         };
 
         ch.update_enabled();
@@ -118,7 +405,7 @@ impl Channel {
     }
 
     pub(super) fn from_directory(dir: &str) -> Result<Vec<Self>> {
-        // Find all .yaml files in CHANNELS_DIR
+        // Find all .yaml files in the channels directory
         let mut dir_entries: Vec<DirEntry> = read_dir(dir)?
             .filter_map(|dir_entry| dir_entry.ok())
             .filter(|dir_entry| {
@@ -149,15 +436,21 @@ impl Channel {
         Ok(channels)
     }
 
-    fn update_enabled(&mut self) {
-        // Which channels are enabled is decided based on which RAUC certificates are enabled.
+    /// The certificate in the RAUC keyring that belongs to this channel
+    fn cert_path(&self) -> PathBuf {
         let cert_file = self.name.clone() + ".cert.pem";
-        let cert_path = Path::new(ENABLE_DIR).join(cert_file);
+        Path::new(ENABLE_DIR).join(cert_file)
+    }
 
-        self.enabled = cert_path.exists();
+    fn update_enabled(&mut self) {
+        // Which channels are enabled is decided based on which RAUC certificates are enabled.
+        self.enabled = self.cert_path().exists();
     }
 
     /// Ask RAUC to determine the version of the bundle on the server
+    ///
+    /// The server is asked if the index or bundle changed since the last poll
+    /// first, so that RAUC does not have to touch unchanged bundles.
     pub(super) async fn poll(
         &mut self,
         proxy: &InstallerProxy<'_>,
@@ -165,15 +458,125 @@ impl Channel {
     ) -> Result<()> {
         self.update_enabled();
 
-        self.bundle = None;
+        let mut previous = self.bundle.take().filter(|_| self.enabled);
+
+        // Only send the validators if we still have the bundle info that
+        // belongs to them.
+        let cache = match previous.as_mut() {
+            Some(bundle) => {
+                if let Some(slot_status) = slot_status {
+                    bundle.update_install(slot_status);
+                }
+
+                self.cache.clone()
+            }
+            None => HttpCache::default(),
+        };
+
+        self.cache = HttpCache::default();
+
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let polled = match self.index.clone() {
+            Some(index) => self.poll_index(proxy, &index, &cache, slot_status).await?,
+            None => self.poll_bundle(proxy, &cache, slot_status).await?,
+        };
 
-        if self.enabled {
-            let (compatible, version) = proxy.info(&self.url).await?;
-            self.bundle = Some(UpstreamBundle::new(compatible, version, slot_status));
+        match polled {
+            Some((bundle, cache)) => {
+                self.bundle = Some(bundle);
+                self.cache = cache;
+            }
+            None => {
+                self.bundle = previous;
+                self.cache = cache;
+            }
         }
 
         Ok(())
     }
+
+    /// Poll a bundle directly. Returns None if it did not change.
+    async fn poll_bundle(
+        &mut self,
+        proxy: &InstallerProxy<'_>,
+        cache: &HttpCache,
+        slot_status: Option<&SlotStatus>,
+    ) -> Result<Option<(UpstreamBundle, HttpCache)>> {
+        let (size, cache) = match head(&self.url, cache).await {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+
+        let (compatible, version) = proxy.info(&self.url).await?;
+
+        let mut bundle = UpstreamBundle::new(compatible, version, slot_status);
+        bundle.size = size;
+
+        Ok(Some((bundle, cache)))
+    }
+
+    /// Poll the channel index and the newest bundle in it.
+    /// Returns None if the index did not change.
+    async fn poll_index(
+        &mut self,
+        proxy: &InstallerProxy<'_>,
+        index_url: &str,
+        cache: &HttpCache,
+        slot_status: Option<&SlotStatus>,
+    ) -> Result<Option<(UpstreamBundle, HttpCache)>> {
+        let (body, cache) = match http::fetch(index_url, Method::Get, cache).await? {
+            Fetched::NotModified => return Ok(None),
+            Fetched::Modified { body, cache, .. } => (body.unwrap_or_default(), cache),
+        };
+
+        // The signature is fetched unconditionally, as it belongs to the
+        // index we just received.
+        let signature_url = format!("{index_url}.sig");
+        let signature =
+            match http::fetch(&signature_url, Method::Get, &HttpCache::default()).await? {
+                Fetched::Modified {
+                    body: Some(body), ..
+                } => body,
+                _ => bail!("Failed to fetch the channel index signature at {signature_url}"),
+            };
+
+        signature::verify(&body, &signature, &self.cert_path()).map_err(|e| {
+            anyhow!("Failed to verify the signature of the channel index at {index_url}: {e}")
+        })?;
+
+        let index: ChannelIndex = serde_yaml::from_str(&body)
+            .map_err(|e| anyhow!("Failed to parse channel index at {index_url}: {e}"))?;
+
+        let newest = index.newest(index_url)?;
+
+        self.url = newest.url;
+
+        // Let RAUC verify the bundle before offering it for installation
+        let (compatible, version) = proxy.info(&self.url).await?;
+
+        let mut bundle = UpstreamBundle::new(compatible, version, slot_status);
+        bundle.size = newest.size;
+        bundle.release_notes = newest.release_notes;
+
+        Ok(Some((bundle, cache)))
+    }
+}
+
+/// Ask the server for the size and validators of a bundle.
+/// Returns None if it did not change.
+async fn head(url: &str, cache: &HttpCache) -> Option<(Option<u64>, HttpCache)> {
+    match http::fetch(url, Method::Head, cache).await {
+        Ok(Fetched::NotModified) => None,
+        Ok(Fetched::Modified { size, cache, .. }) => Some((size, cache)),
+        // The server may not be reachable via our HTTP client, e.g. because
+        // it is a https:// URL and the client lacks TLS support, even though
+        // RAUC can reach it.
+        // Fall back to asking RAUC on every poll in that case.
+        Err(_) => Some((None, HttpCache::default())),
+    }
 }
 
 impl UpstreamBundle {
@@ -182,6 +585,8 @@ impl UpstreamBundle {
             compatible,
             version,
             newer_than_installed: false,
+            size: None,
+            release_notes: None,
         };
 
         if let Some(slot_status) = slot_status {
@@ -207,3 +612,136 @@ impl UpstreamBundle {
         self.newer_than_installed = slot_0_is_older && slot_1_is_older;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::process;
+
+    use async_std::task::block_on;
+
+    use super::{head, Channel, ChannelIndex, HttpCache};
+
+    #[test]
+    fn channel_index() {
+        let index: ChannelIndex = serde_yaml::from_str(
+            "bundles:
+  - version: \"4.0-0-20230428214619\"
+    url: 4.0/bundle.raucb
+  - version: \"4.1-0-20230801120000\"
+    url: 4.1/bundle.raucb
+    size: 1234
+    release_notes: Fix all the bugs
+  - version: \"3.0-0-20221101120000\"
+    url: https://example.com/old/bundle.raucb
+",
+        )
+        .unwrap();
+
+        let newest = index
+            .newest("https://example.com/channels/stable/index.yaml")
+            .unwrap();
+
+        assert_eq!(newest.version, "4.1-0-20230801120000");
+        assert_eq!(
+            newest.url,
+            "https://example.com/channels/stable/4.1/bundle.raucb"
+        );
+        assert_eq!(newest.size, Some(1234));
+        assert_eq!(newest.release_notes.as_deref(), Some("Fix all the bugs"));
+
+        let empty: ChannelIndex = serde_yaml::from_str("bundles: []").unwrap();
+        assert!(empty.newest("https://example.com/index.yaml").is_err());
+    }
+    #[test]
+    fn https_fallback() {
+        let cache = HttpCache {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+        };
+
+        // Nothing listens on port 1, just like our HTTP client can not talk
+        // to https:// servers. The validators must not be reused in that
+        // case, so that RAUC is asked for the bundle info instead.
+        let res = block_on(head("https://127.0.0.1:1/bundle.raucb", &cache));
+        assert!(res == Some((None, HttpCache::default())));
+    }
+
+    #[test]
+    fn index_scheme() {
+        let dir = temp_dir().join(format!("tacd-channels-{}", process::id()));
+        create_dir_all(&dir).unwrap();
+
+        let channel = |index: &str| {
+            let path = dir.join("01_stable.yaml");
+            let content = format!(
+                "name: stable\ndisplay_name: Stable\ndescription: Stable\nindex: {index}\n"
+            );
+            write(&path, content).unwrap();
+            Channel::from_file(&path)
+        };
+
+        let http = channel("http://example.com/stable/index.yaml").unwrap();
+        assert_eq!(
+            http.index.as_deref(),
+            Some("http://example.com/stable/index.yaml")
+        );
+
+        assert!(channel("https://example.com/stable/index.yaml").is_err());
+        assert!(channel("file:///srv/index.yaml").is_err());
+        assert!(channel("not a url").is_err());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(not(feature = "demo_mode"))]
+    #[test]
+    fn index_signature() {
+        use std::fs::read_to_string;
+        use std::process::Command;
+
+        use super::signature;
+
+        let dir = temp_dir().join(format!("tacd-signature-{}", process::id()));
+        create_dir_all(&dir).unwrap();
+
+        let openssl = |cmd: &str| {
+            let status = Command::new("openssl")
+                .args(cmd.split_whitespace())
+                .current_dir(&dir)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success());
+        };
+
+        let index = "bundles: []\n";
+        write(dir.join("index.yaml"), index).unwrap();
+
+        for name in ["test", "other"].iter() {
+            openssl(&format!(
+                "req -x509 -newkey rsa:2048 -nodes -days 1 -subj /CN={name} \
+                 -keyout {name}.key.pem -out {name}.cert.pem"
+            ));
+        }
+
+        openssl(
+            "cms -sign -binary -outform PEM -in index.yaml \
+             -signer test.cert.pem -inkey test.key.pem -out index.yaml.sig",
+        );
+
+        let sig = read_to_string(dir.join("index.yaml.sig")).unwrap();
+        let cert = dir.join("test.cert.pem");
+
+        assert!(signature::verify(index, &sig, &cert).is_ok());
+
+        // A modified index, a signature by someone else or no signature at
+        // all must be rejected.
+        assert!(signature::verify("bundles: [{}]\n", &sig, &cert).is_err());
+        assert!(signature::verify(index, &sig, &dir.join("other.cert.pem")).is_err());
+        assert!(signature::verify(index, "", &cert).is_err());
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
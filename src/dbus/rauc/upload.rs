@@ -0,0 +1,379 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::path::Path;
+use std::time::Duration;
+
+use async_std::channel::Receiver;
+use async_std::fs::{create_dir_all, remove_file, File};
+use async_std::future::timeout;
+use async_std::prelude::*;
+use async_std::sync::{Arc, Mutex};
+use async_std::task::spawn;
+use log::{info, warn};
+use nix::sys::statvfs::statvfs;
+use serde::{Deserialize, Serialize};
+use tide::http::mime;
+use tide::{Request, Response, Server};
+
+use super::{InstallerProxy, Rauc};
+use crate::broker::{BrokerBuilder, Topic};
+use crate::config::UpdateConfig;
+
+const BUNDLE_NAME: &str = "upload.raucb";
+
+// Leave some space on the filesystem for the other users of /srv
+const FREE_SPACE_MARGIN: u64 = 16 * 1024 * 1024;
+
+// Publish the upload progress every time this many bytes were received
+const PROGRESS_STEP: u64 = 1024 * 1024;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// RAUC starts installing right away, but the installation itself may take
+// a while. Give up waiting for it after these timeouts.
+const INSTALL_START_TIMEOUT: Duration = Duration::from_secs(60);
+const INSTALL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UploadState {
+    Idle,
+    Receiving,
+    Verifying,
+    Installing,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadProgress {
+    pub state: UploadState,
+    pub received: u64,
+    /// The size of the bundle, if the client sent a Content-Length
+    pub total: Option<u64>,
+    pub message: String,
+}
+
+impl UploadProgress {
+    fn new(state: UploadState, received: u64, total: Option<u64>, message: String) -> Self {
+        Self {
+            state,
+            received,
+            total,
+            message,
+        }
+    }
+}
+
+fn available_space(path: &str) -> Result<u64, nix::Error> {
+    let stat = statvfs(path)?;
+
+    Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+fn error_response(status: u16, message: &str) -> Response {
+    Response::builder(status)
+        .body(message)
+        .content_type(mime::PLAIN)
+        .build()
+}
+
+struct Upload {
+    progress: Arc<Topic<UploadProgress>>,
+    total: Option<u64>,
+    received: u64,
+}
+
+impl Upload {
+    fn update(&self, state: UploadState, message: &str) {
+        self.progress.set(UploadProgress::new(
+            state,
+            self.received,
+            self.total,
+            message.to_string(),
+        ));
+    }
+
+    /// Mark the upload as failed and generate a matching HTTP response
+    async fn fail(&self, path: &Path, status: u16, message: &str) -> Response {
+        warn!("Bundle upload failed: {message}");
+
+        let _ = remove_file(path).await;
+        self.update(UploadState::Failed, message);
+
+        error_response(status, message)
+    }
+
+    /// Stream the request body into the bundle file on disk
+    ///
+    /// Returns the HTTP status code and message on failure.
+    async fn receive(
+        &mut self,
+        req: &mut Request<()>,
+        path: &Path,
+        available: u64,
+    ) -> Result<(), (u16, String)> {
+        let io_err = |e: std::io::Error| (500, format!("Failed to store bundle: {e}"));
+
+        let mut file = File::create(path).await.map_err(io_err)?;
+        let mut body = req.take_body();
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut next_report = 0;
+
+        loop {
+            let len = body
+                .read(&mut buf)
+                .await
+                .map_err(|e| (400, format!("Failed to receive bundle: {e}")))?;
+
+            if len == 0 {
+                break;
+            }
+
+            self.received += len as u64;
+
+            // The client may not have sent a Content-Length or sent a
+            // wrong one. Make sure not to fill up the filesystem anyways.
+            if self.received.saturating_add(FREE_SPACE_MARGIN) > available {
+                return Err((507, "Not enough space left to store the bundle".into()));
+            }
+
+            file.write_all(&buf[..len]).await.map_err(io_err)?;
+
+            if self.received >= next_report {
+                self.update(UploadState::Receiving, "Receiving bundle");
+                next_report = self.received + PROGRESS_STEP;
+            }
+        }
+
+        file.sync_all().await.map_err(io_err)?;
+
+        Ok(())
+    }
+}
+
+/// Wait for the RAUC operation to go from "idle" to something else and back
+///
+/// The operation has to be subscribed to before the installation is
+/// started, so that a quick installation (or failure) is not missed.
+async fn wait_for_installation(operation_events: &mut Receiver<String>) -> Result<(), &str> {
+    let started = async {
+        while let Some(op) = operation_events.next().await {
+            if op != "idle" {
+                return true;
+            }
+        }
+
+        false
+    };
+
+    if !timeout(INSTALL_START_TIMEOUT, started)
+        .await
+        .unwrap_or(false)
+    {
+        return Err("RAUC did not start the installation");
+    }
+
+    let finished = async {
+        while let Some(op) = operation_events.next().await {
+            if op == "idle" {
+                return true;
+            }
+        }
+
+        false
+    };
+
+    if !timeout(INSTALL_TIMEOUT, finished).await.unwrap_or(false) {
+        return Err("RAUC did not finish the installation in time");
+    }
+
+    Ok(())
+}
+
+/// Remove the uploaded bundle once RAUC is done installing it
+async fn finish_installation(
+    mut operation_events: Receiver<String>,
+    last_error: Arc<Topic<String>>,
+    upload: Upload,
+    path: &Path,
+) {
+    let res = wait_for_installation(&mut operation_events).await;
+
+    let _ = remove_file(path).await;
+
+    let error = match res {
+        Ok(()) => last_error.try_get().filter(|e| !e.is_empty()),
+        Err(e) => Some(e.to_string()),
+    };
+
+    match error {
+        Some(e) => {
+            warn!("Installation of uploaded bundle failed: {e}");
+            upload.update(UploadState::Failed, &format!("Installation failed: {e}"));
+        }
+        None => upload.update(UploadState::Idle, "Bundle installed"),
+    }
+}
+
+impl Rauc {
+    /// Allow installing bundles that are uploaded via HTTP PUT
+    ///
+    /// This is useful if RAUC can not reach the server the bundle is hosted
+    /// on, e.g. because the TAC is in an isolated lab network.
+    pub fn serve_upload(
+        &self,
+        bb: &mut BrokerBuilder,
+        server: &mut Server<()>,
+        update_config: &UpdateConfig,
+    ) {
+        let progress = bb.topic_ro(
+            "/v1/tac/update/upload/progress",
+            Some(UploadProgress::new(UploadState::Idle, 0, None, "".into())),
+        );
+
+        // Only allow one upload at a time, as they all use the same file
+        let lock = Arc::new(Mutex::new(()));
+
+        let conn = self.conn.clone();
+        let operation = self.operation.clone();
+        let last_error = self.last_error.clone();
+        let upload_dir: Arc<str> = update_config.upload_dir.as_str().into();
+
+        server
+            .at("/v1/tac/update/upload")
+            .put(move |mut req: Request<()>| {
+                let lock = lock.clone();
+                let conn = conn.clone();
+                let operation = operation.clone();
+                let last_error = last_error.clone();
+                let progress = progress.clone();
+                let upload_dir = upload_dir.clone();
+
+                async move {
+                    let _guard = match lock.try_lock() {
+                        Some(guard) => guard,
+                        None => return Ok(error_response(409, "Another upload is in progress")),
+                    };
+
+                    let installing = progress
+                        .try_get()
+                        .map(|p| p.state == UploadState::Installing)
+                        .unwrap_or(false);
+
+                    if installing || operation.try_get().map(|op| op != "idle").unwrap_or(true) {
+                        return Ok(error_response(409, "RAUC is busy"));
+                    }
+
+                    let path = Path::new(&*upload_dir).join(BUNDLE_NAME);
+
+                    let mut upload = Upload {
+                        progress,
+                        total: req.len().map(|l| l as u64),
+                        received: 0,
+                    };
+
+                    // Remove leftovers of previous uploads, so that they do
+                    // not count against the available space.
+                    let _ = remove_file(&path).await;
+
+                    if let Err(e) = create_dir_all(&*upload_dir).await {
+                        let msg = format!("Failed to create upload directory: {e}");
+                        return Ok(upload.fail(&path, 500, &msg).await);
+                    }
+
+                    // Check the free space before receiving anything, if
+                    // the client told us how large the bundle is.
+                    let available = match available_space(&upload_dir) {
+                        Ok(available) => available,
+                        Err(e) => {
+                            let msg = format!("Failed to determine free space: {e}");
+                            return Ok(upload.fail(&path, 500, &msg).await);
+                        }
+                    };
+
+                    if let Some(total) = upload.total {
+                        if total.saturating_add(FREE_SPACE_MARGIN) > available {
+                            let msg = "Not enough space left to store the bundle";
+                            return Ok(upload.fail(&path, 507, msg).await);
+                        }
+                    }
+
+                    upload.update(UploadState::Receiving, "Receiving bundle");
+
+                    if let Err((status, msg)) = upload.receive(&mut req, &path, available).await {
+                        return Ok(upload.fail(&path, status, &msg).await);
+                    }
+
+                    // Let RAUC check the signature and compatible of the
+                    // bundle before installing it.
+                    upload.update(UploadState::Verifying, "Verifying bundle");
+
+                    let path_str = path.to_string_lossy().into_owned();
+                    let proxy = match InstallerProxy::new(&conn).await {
+                        Ok(proxy) => proxy,
+                        Err(e) => {
+                            let msg = format!("Failed to connect to RAUC: {e}");
+                            return Ok(upload.fail(&path, 500, &msg).await);
+                        }
+                    };
+
+                    let (compatible, version) = match proxy.info(&path_str).await {
+                        Ok(info) => info,
+                        Err(e) => {
+                            let msg = format!("Bundle verification failed: {e}");
+                            return Ok(upload.fail(&path, 400, &msg).await);
+                        }
+                    };
+
+                    match proxy.compatible().await {
+                        Ok(system) if system == compatible => {}
+                        Ok(system) => {
+                            let msg = format!(
+                                "Bundle compatible \"{compatible}\" does not match system compatible \"{system}\""
+                            );
+                            return Ok(upload.fail(&path, 400, &msg).await);
+                        }
+                        Err(e) => {
+                            let msg = format!("Failed to get system compatible: {e}");
+                            return Ok(upload.fail(&path, 500, &msg).await);
+                        }
+                    }
+
+                    // Subscribe before starting the installation, so that
+                    // no change of the operation is missed.
+                    let (operation_events, _) = operation.clone().subscribe_unbounded();
+
+                    if let Err(e) = proxy.install(&path_str).await {
+                        let msg = format!("Failed to install bundle: {e}");
+                        return Ok(upload.fail(&path, 500, &msg).await);
+                    }
+
+                    info!("Installing uploaded bundle {version}");
+                    upload.update(
+                        UploadState::Installing,
+                        &format!("Installing bundle {version}"),
+                    );
+
+                    spawn(async move {
+                        finish_installation(operation_events, last_error, upload, &path).await
+                    });
+
+                    Ok(Response::new(204))
+                }
+            });
+    }
+}
@@ -15,12 +15,20 @@
 // with this program; if not, write to the Free Software Foundation, Inc.,
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 
+use std::collections::BTreeMap;
+
 use async_std::prelude::*;
 use async_std::sync::Arc;
 use async_std::task::spawn;
-use futures::join;
+use futures::future::join_all;
 use serde::{Deserialize, Serialize};
 
+#[cfg(not(feature = "demo_mode"))]
+use std::time::Duration;
+
+#[cfg(not(feature = "demo_mode"))]
+use async_std::future::timeout;
+
 #[cfg(not(feature = "demo_mode"))]
 use futures_lite::future::race;
 
@@ -29,6 +37,7 @@ pub use log::warn;
 
 use super::{Connection, Result};
 use crate::broker::{BrokerBuilder, Topic};
+use crate::config::{ServiceConfig, DEFAULT_SERVICES};
 
 #[cfg(not(feature = "demo_mode"))]
 mod manager;
@@ -36,12 +45,25 @@ mod manager;
 #[cfg(not(feature = "demo_mode"))]
 mod service;
 
-#[derive(Serialize, Deserialize, Clone)]
+// Properties like the memory usage do not emit change signals.
+// Re-read them this often.
+#[cfg(not(feature = "demo_mode"))]
+const STATUS_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct ServiceStatus {
     pub active_state: String,
     pub sub_state: String,
     pub active_enter_ts: u64,
     pub active_exit_ts: u64,
+    /// The result of the last run, e.g. "success" or "exit-code"
+    pub result: String,
+    /// The number of automatic restarts since the unit was started
+    pub n_restarts: u32,
+    /// The current memory usage in bytes, if memory accounting is enabled
+    pub memory_current: Option<u64>,
+    /// e.g. "enabled", "disabled" or "static"
+    pub unit_file_state: String,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -49,6 +71,10 @@ pub enum ServiceAction {
     Start,
     Stop,
     Restart,
+    Reload,
+    Enable,
+    Disable,
+    ResetFailed,
 }
 
 #[derive(Clone)]
@@ -60,9 +86,6 @@ pub struct Service {
 #[derive(Clone)]
 pub struct Systemd {
     pub reboot: Arc<Topic<bool>>,
-    pub networkmanager: Service,
-    pub labgrid: Service,
-    pub iobus: Service,
 }
 
 impl ServiceStatus {
@@ -73,22 +96,37 @@ impl ServiceStatus {
             sub_state: "running".to_string(),
             active_enter_ts: 0,
             active_exit_ts: 0,
+            result: "success".to_string(),
+            n_restarts: 0,
+            memory_current: Some(4 * 1024 * 1024),
+            unit_file_state: "enabled".to_string(),
         })
     }
 
     #[cfg(not(feature = "demo_mode"))]
-    async fn get<'a>(unit: &service::UnitProxy<'a>) -> Result<Self> {
+    async fn get<'a>(
+        unit: &service::UnitProxy<'a>,
+        service: &service::ServiceProxy<'a>,
+    ) -> Result<Self> {
+        // systemd uses the maximum value to signal that the memory
+        // usage is not known.
+        let memory_current = service.memory_current().await?;
+
         Ok(Self {
             active_state: unit.active_state().await?,
             sub_state: unit.sub_state().await?,
             active_enter_ts: unit.active_enter_timestamp().await?,
             active_exit_ts: unit.active_exit_timestamp().await?,
+            result: service.result().await?,
+            n_restarts: service.nrestarts().await?,
+            memory_current: (memory_current != u64::MAX).then_some(memory_current),
+            unit_file_state: unit.unit_file_state().await?,
         })
     }
 }
 
 impl Service {
-    fn new(bb: &mut BrokerBuilder, topic_name: &'static str) -> Self {
+    fn new(bb: &mut BrokerBuilder, topic_name: &str) -> Self {
         Self {
             action: bb.topic_wo(&format!("/v1/tac/service/{topic_name}/action"), None),
             status: bb.topic_ro(&format!("/v1/tac/service/{topic_name}/status"), None),
@@ -96,18 +134,32 @@ impl Service {
     }
 
     #[cfg(feature = "demo_mode")]
-    async fn connect(&self, _conn: Arc<Connection>, _unit_name: &'static str) {
+    async fn connect(&self, _conn: Arc<Connection>, _unit_name: String) {
         self.status.set(ServiceStatus::get().await.unwrap());
     }
 
     #[cfg(not(feature = "demo_mode"))]
-    async fn connect(&self, conn: Arc<Connection>, unit_name: &'static str) {
-        let unit_path = {
-            let manager = manager::ManagerProxy::new(&conn).await.unwrap();
-            manager.get_unit(unit_name).await.unwrap()
+    async fn connect(&self, conn: Arc<Connection>, unit_name: String) {
+        let manager = manager::ManagerProxy::new(&conn).await.unwrap();
+
+        // Units that are not running may not be loaded.
+        // LoadUnit loads them on demand.
+        let unit_path = match manager.load_unit(&unit_name).await {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("Failed to load systemd unit {unit_name}: {e}");
+                return;
+            }
         };
 
         let unit = service::UnitProxy::builder(&conn)
+            .path(unit_path.clone())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let service = service::ServiceProxy::builder(&conn)
             .path(unit_path)
             .unwrap()
             .build()
@@ -131,15 +183,19 @@ impl Service {
                 .map(|_| ());
 
             loop {
-                let status = ServiceStatus::get(&unit_task).await.unwrap();
-                status_topic.set(status);
+                match ServiceStatus::get(&unit_task, &service).await {
+                    Ok(status) => status_topic.set_if_changed(status),
+                    Err(e) => warn!("Failed to get status of a systemd unit: {e}"),
+                }
 
-                race(
+                let changes = race(
                     race(active_state_stream.next(), sub_state_stream.next()),
                     race(active_enter_stream.next(), active_exit_stream.next()),
-                )
-                .await
-                .unwrap();
+                );
+
+                if let Ok(None) = timeout(STATUS_INTERVAL, changes).await {
+                    break;
+                }
             }
         });
 
@@ -147,10 +203,27 @@ impl Service {
 
         spawn(async move {
             while let Some(action) = action_reqs.next().await {
+                let files = [unit_name.as_str()];
+
                 let res = match action {
-                    ServiceAction::Start => unit.start("replace").await,
-                    ServiceAction::Stop => unit.stop("replace").await,
-                    ServiceAction::Restart => unit.restart("replace").await,
+                    ServiceAction::Start => unit.start("replace").await.map(|_| ()),
+                    ServiceAction::Stop => unit.stop("replace").await.map(|_| ()),
+                    ServiceAction::Restart => unit.restart("replace").await.map(|_| ()),
+                    ServiceAction::Reload => unit.reload("replace").await.map(|_| ()),
+                    ServiceAction::ResetFailed => unit.reset_failed().await,
+                    // Reload the systemd configuration after changing the
+                    // unit file links, just like systemctl does.
+                    ServiceAction::Enable => {
+                        match manager.enable_unit_files(&files, false, false).await {
+                            Ok(_) => manager.reload().await,
+                            Err(e) => Err(e),
+                        }
+                    }
+                    ServiceAction::Disable => match manager.disable_unit_files(&files, false).await
+                    {
+                        Ok(_) => manager.reload().await,
+                        Err(e) => Err(e),
+                    },
                 };
 
                 if let Err(e) = res {
@@ -195,26 +268,38 @@ impl Systemd {
         });
     }
 
-    pub async fn new(bb: &mut BrokerBuilder, conn: &Arc<Connection>) -> Self {
+    pub async fn new(
+        bb: &mut BrokerBuilder,
+        conn: &Arc<Connection>,
+        services: &[ServiceConfig],
+    ) -> Self {
         let reboot = bb.topic_rw("/v1/tac/reboot", Some(false));
 
         Self::handle_reboot(reboot.clone(), conn.clone());
 
-        let networkmanager = Service::new(bb, "network-manager");
-        let labgrid = Service::new(bb, "labgrid-exporter");
-        let iobus = Service::new(bb, "lxa-iobus");
+        // Only the units in this list can be controlled via the API
+        let entries: Vec<(String, String)> = DEFAULT_SERVICES
+            .iter()
+            .map(|(name, unit)| (name.to_string(), unit.to_string()))
+            .chain(services.iter().map(|s| (s.name.clone(), s.unit.clone())))
+            .collect();
 
-        join!(
-            networkmanager.connect(conn.clone(), "NetworkManager.service"),
-            labgrid.connect(conn.clone(), "labgrid-exporter.service"),
-            iobus.connect(conn.clone(), "lxa-iobus.service"),
-        );
+        let list: BTreeMap<String, String> = entries.iter().cloned().collect();
 
-        Self {
-            reboot,
-            networkmanager,
-            labgrid,
-            iobus,
-        }
+        bb.topic_ro("/v1/tac/services", Some(list));
+
+        let services: Vec<(Service, String)> = entries
+            .into_iter()
+            .map(|(name, unit)| (Service::new(bb, &name), unit))
+            .collect();
+
+        join_all(
+            services
+                .iter()
+                .map(|(service, unit)| service.connect(conn.clone(), unit.clone())),
+        )
+        .await;
+
+        Self { reboot }
     }
 }
@@ -0,0 +1,286 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read, read_dir, remove_file, write};
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+use async_std::prelude::*;
+use async_std::sync::Arc;
+use async_std::task::{spawn, spawn_blocking};
+use chrono::Local;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{info, warn};
+use tide::{Response, Server};
+
+use crate::broker::{AnyTopic, Topic};
+use crate::config::PersistenceConfig;
+use crate::journal;
+
+// Only keep the newest archives saved via the display to not fill up /srv
+const MAX_SAVED: usize = 5;
+
+const JOURNAL_ENTRIES: usize = 2000;
+
+const BLOCK_SIZE: usize = 512;
+
+/// A minimal writer for the ustar archive format
+///
+/// Only supports regular files with names of less than 100 bytes,
+/// which is all we need to bundle a few JSON files.
+struct TarWriter<W: Write> {
+    inner: W,
+    mtime: u64,
+}
+
+impl<W: Write> TarWriter<W> {
+    fn new(inner: W, mtime: u64) -> Self {
+        Self { inner, mtime }
+    }
+
+    fn append(&mut self, name: &str, content: &[u8]) -> std::io::Result<()> {
+        let mut header = [0u8; BLOCK_SIZE];
+
+        let mut field = |offset: usize, len: usize, value: &[u8]| {
+            header[offset..offset + value.len().min(len)]
+                .copy_from_slice(&value[..value.len().min(len)])
+        };
+
+        // Numeric fields are zero-padded octal numbers followed by a NUL
+        let octal = |len: usize, value: u64| format!("{value:0width$o}\0", width = len - 1);
+
+        field(0, 100, name.as_bytes());
+        field(100, 8, octal(8, 0o644).as_bytes());
+        field(108, 8, octal(8, 0).as_bytes());
+        field(116, 8, octal(8, 0).as_bytes());
+        field(124, 12, octal(12, content.len() as u64).as_bytes());
+        field(136, 12, octal(12, self.mtime).as_bytes());
+        field(148, 8, b"        ");
+        field(156, 1, b"0");
+        field(257, 8, b"ustar\x0000");
+        field(265, 32, b"root");
+        field(297, 32, b"root");
+
+        // The checksum is calculated with the checksum field set to spaces
+        let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+        header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+
+        self.inner.write_all(&header)?;
+        self.inner.write_all(content)?;
+
+        let padding = (BLOCK_SIZE - content.len() % BLOCK_SIZE) % BLOCK_SIZE;
+        self.inner.write_all(&[0; BLOCK_SIZE][..padding])
+    }
+
+    /// Terminate the archive with two empty blocks
+    fn finish(mut self) -> std::io::Result<W> {
+        self.inner.write_all(&[0; 2 * BLOCK_SIZE])?;
+
+        Ok(self.inner)
+    }
+}
+
+/// Collect the current state of the TAC into a .tar.gz archive
+///
+/// The RAUC slot status, systemd service states and everything else the
+/// tacd knows about are part of the topic snapshot.
+/// Only topics readable via the API are included, and no files besides
+/// the tacd state file are, so that secrets like the authorized_keys
+/// file of the setup mode do not end up in the archive.
+///
+/// Returns the suggested file name and the archive content.
+fn collect(topics: &[Arc<dyn AnyTopic>], state_file: &str) -> Result<(String, Vec<u8>)> {
+    let now = Local::now();
+    let name = format!("tacd-diagnostics-{}", now.format("%Y%m%d-%H%M%S"));
+    let mtime = now.timestamp().max(0) as u64;
+
+    let snapshot: BTreeMap<String, serde_json::Value> = topics
+        .iter()
+        .filter(|t| t.web_readable())
+        .filter_map(|t| t.try_get_json_value().map(|v| (t.path().to_string(), v)))
+        .collect();
+
+    let journal = match journal::recent(JOURNAL_ENTRIES) {
+        Ok(entries) => serde_json::to_vec_pretty(&entries)?,
+        Err(e) => format!("Failed to read the journal: {e}\n").into_bytes(),
+    };
+
+    let gz = GzEncoder::new(Vec::new(), Compression::default());
+    let mut tar = TarWriter::new(gz, mtime);
+
+    tar.append(
+        &format!("{name}/topics.json"),
+        &serde_json::to_vec_pretty(&snapshot)?,
+    )?;
+    tar.append(&format!("{name}/journal.json"), &journal)?;
+
+    if let Ok(state) = read(state_file) {
+        tar.append(&format!("{name}/state.json"), &state)?;
+    }
+
+    let archive = tar.finish()?.finish()?;
+
+    Ok((format!("{name}.tar.gz"), archive))
+}
+
+/// Save an archive to save_dir and remove old ones
+fn save(topics: &[Arc<dyn AnyTopic>], state_file: &str, save_dir: &str) -> Result<String> {
+    let (name, archive) = collect(topics, state_file)?;
+
+    create_dir_all(save_dir)?;
+
+    let path = Path::new(save_dir).join(&name);
+    write(&path, archive)?;
+
+    let mut saved: Vec<_> = read_dir(save_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.starts_with("tacd-diagnostics-") && n.ends_with(".tar.gz"))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    // The names contain the creation time, so sorting them sorts by age
+    saved.sort();
+
+    for old in saved.iter().rev().skip(MAX_SAVED) {
+        remove_file(old)?;
+    }
+
+    Ok(path.to_string_lossy().into_owned())
+}
+
+#[derive(Clone)]
+pub struct Diagnostics {
+    /// Save an archive to the diagnostics directory when set to true
+    pub save: Arc<Topic<bool>>,
+    state_file: Arc<str>,
+    save_dir: Arc<str>,
+}
+
+impl Diagnostics {
+    pub fn new(persistence: &PersistenceConfig) -> Self {
+        Self {
+            save: Topic::anonymous(Some(false)),
+            state_file: persistence.state_file.as_str().into(),
+            save_dir: persistence.diagnostics_dir.as_str().into(),
+        }
+    }
+
+    /// Serve diagnostic archives via HTTP and save them on request
+    ///
+    /// This has to happen after the broker is built, as the archive
+    /// contains a snapshot of all topics.
+    pub fn serve(&self, server: &mut Server<()>, topics: Arc<Vec<Arc<dyn AnyTopic>>>) {
+        let (mut save_events, _) = self.save.clone().subscribe_unbounded();
+        let save_topic = self.save.clone();
+        let save_topics = topics.clone();
+        let save_state_file = self.state_file.clone();
+        let save_dir = self.save_dir.clone();
+        let state_file = self.state_file.clone();
+
+        spawn(async move {
+            while let Some(req) = save_events.next().await {
+                if !req {
+                    continue;
+                }
+
+                let topics = save_topics.clone();
+                let state_file = save_state_file.clone();
+                let save_dir = save_dir.clone();
+
+                match spawn_blocking(move || save(&topics, &state_file, &save_dir)).await {
+                    Ok(path) => info!("Saved diagnostics to {path}"),
+                    Err(e) => warn!("Failed to save diagnostics: {e}"),
+                }
+
+                save_topic.set(false);
+            }
+        });
+
+        server.at("/v1/tac/diagnostics").get(move |_req| {
+            let topics = topics.clone();
+            let state_file = state_file.clone();
+
+            async move {
+                let (name, archive) = spawn_blocking(move || collect(&topics, &state_file)).await?;
+
+                Ok(Response::builder(200)
+                    .body(archive)
+                    .content_type("application/gzip")
+                    .header(
+                        "Content-Disposition",
+                        format!("attachment; filename=\"{name}\""),
+                    )
+                    .build())
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::{TarWriter, BLOCK_SIZE};
+
+    #[test]
+    fn tar_archive() {
+        let gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut tar = TarWriter::new(gz, 1_700_000_000);
+
+        tar.append("diag/a.json", b"{}").unwrap();
+        tar.append("diag/b.json", &[b'x'; BLOCK_SIZE]).unwrap();
+
+        let archive = tar.finish().unwrap().finish().unwrap();
+
+        let mut raw = Vec::new();
+        GzDecoder::new(&archive[..]).read_to_end(&mut raw).unwrap();
+
+        // Two headers, one padded block for each file and the end marker
+        assert_eq!(raw.len(), 6 * BLOCK_SIZE);
+
+        let header = &raw[..BLOCK_SIZE];
+        assert_eq!(&header[..11], b"diag/a.json");
+        assert_eq!(&header[124..136], b"00000000002\0");
+        assert_eq!(&header[257..265], b"ustar\x0000");
+        assert_eq!(&raw[BLOCK_SIZE..BLOCK_SIZE + 2], b"{}");
+
+        let stored: u32 =
+            u32::from_str_radix(std::str::from_utf8(&header[148..154]).unwrap(), 8).unwrap();
+        let expected: u32 = header
+            .iter()
+            .enumerate()
+            .map(|(i, b)| if (148..156).contains(&i) { b' ' } else { *b } as u32)
+            .sum();
+        assert_eq!(stored, expected);
+
+        let second = &raw[2 * BLOCK_SIZE..3 * BLOCK_SIZE];
+        assert_eq!(&second[..11], b"diag/b.json");
+        assert_eq!(&second[124..136], b"00000001000\0");
+
+        assert!(raw[4 * BLOCK_SIZE..].iter().all(|b| *b == 0));
+    }
+}
@@ -0,0 +1,427 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+//! Observe which hosts are connected to the DUT port of the TAC
+//!
+//! The bridge forwarding database tells us which MAC addresses were seen
+//! on the DUT port, the neighbor table of the bridge interface maps these
+//! to IP addresses and the lease file of a local DHCP server (if any) adds
+//! lease information.
+
+use std::collections::HashMap;
+use std::fs::{read_to_string, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_std::task::spawn_blocking;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::broker::BrokerBuilder;
+use crate::measurement::Timestamp;
+
+#[cfg(feature = "demo_mode")]
+mod nl {
+    use anyhow::Result;
+
+    pub const LEASE_FILES: &[&str] =
+        &["demo_files/var/lib/NetworkManager/dnsmasq-tac-bridge.leases"];
+    pub const OUI_DB: &str = "demo_files/usr/share/hwdata/oui.txt";
+
+    pub fn port_macs(_port: &str) -> Result<Vec<String>> {
+        Ok(vec![
+            "00:0a:35:12:34:56".to_string(),
+            "b8:27:eb:65:43:21".to_string(),
+        ])
+    }
+
+    pub fn ip_neighbors(_bridge: &str) -> Result<Vec<(String, String)>> {
+        Ok(vec![
+            ("00:0a:35:12:34:56".to_string(), "192.168.1.50".to_string()),
+            (
+                "00:0a:35:12:34:56".to_string(),
+                "fe80::20a:35ff:fe12:3456".to_string(),
+            ),
+        ])
+    }
+}
+
+#[cfg(not(feature = "demo_mode"))]
+mod nl {
+    use std::convert::TryFrom;
+    use std::net::IpAddr;
+
+    use anyhow::{anyhow, Result};
+    use netlink_packet_core::{
+        NetlinkHeader, NetlinkMessage, NetlinkPayload, NLM_F_DUMP, NLM_F_REQUEST,
+    };
+    use netlink_packet_route::{
+        nlas::neighbour::Nla, NeighbourMessage, RtnlMessage, AF_BRIDGE, AF_INET, AF_INET6,
+        NUD_FAILED, NUD_INCOMPLETE, NUD_NOARP, NUD_PERMANENT,
+    };
+    use netlink_sys::{protocols::NETLINK_ROUTE, Socket, SocketAddr};
+    use nix::net::if_::if_nametoindex;
+
+    pub const LEASE_FILES: &[&str] = &[
+        "/var/lib/NetworkManager/dnsmasq-tac-bridge.leases",
+        "/var/lib/misc/dnsmasq.leases",
+    ];
+    pub const OUI_DB: &str = "/usr/share/hwdata/oui.txt";
+
+    /// Dump the kernel neighbor table entries of the given address family
+    fn dump(family: u16) -> Result<Vec<NeighbourMessage>> {
+        let mut socket = Socket::new(NETLINK_ROUTE)?;
+        socket.bind_auto()?;
+        socket.connect(&SocketAddr::new(0, 0))?;
+
+        let mut nl_hdr = NetlinkHeader::default();
+        nl_hdr.flags = NLM_F_DUMP | NLM_F_REQUEST;
+
+        let mut get = NeighbourMessage::default();
+        get.header.family = family as u8;
+
+        let mut req = NetlinkMessage::new(nl_hdr, RtnlMessage::GetNeighbour(get).into());
+        req.finalize();
+
+        let mut buf = vec![0; req.header.length as usize];
+        req.serialize(&mut buf[..]);
+        socket.send(&buf[..], 0)?;
+
+        let mut entries = Vec::new();
+        let mut recv_buf = vec![0; 32 * 1024];
+
+        loop {
+            let size = socket.recv(&mut &mut recv_buf[..], 0)?;
+            let mut offset = 0;
+
+            while offset < size {
+                let msg: NetlinkMessage<RtnlMessage> =
+                    NetlinkMessage::deserialize(&recv_buf[offset..size])
+                        .map_err(|e| anyhow!("Failed to parse netlink message: {e}"))?;
+
+                match msg.payload {
+                    NetlinkPayload::Done(_) => return Ok(entries),
+                    NetlinkPayload::Error(e) => {
+                        return Err(anyhow!("Neighbor table dump failed: {e}"))
+                    }
+                    NetlinkPayload::InnerMessage(RtnlMessage::NewNeighbour(entry)) => {
+                        entries.push(entry)
+                    }
+                    _ => {}
+                }
+
+                if msg.header.length == 0 {
+                    break;
+                }
+
+                offset += msg.header.length as usize;
+            }
+        }
+    }
+
+    fn format_mac(mac: &[u8]) -> String {
+        mac.iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+
+    fn lladdr(entry: &NeighbourMessage) -> Option<String> {
+        entry.nlas.iter().find_map(|nla| match nla {
+            Nla::LinkLocalAddress(mac) if mac.len() == 6 => Some(format_mac(mac)),
+            _ => None,
+        })
+    }
+
+    fn destination(entry: &NeighbourMessage) -> Option<IpAddr> {
+        entry.nlas.iter().find_map(|nla| match nla {
+            Nla::Destination(ip) => match ip.len() {
+                4 => <[u8; 4]>::try_from(&ip[..]).ok().map(IpAddr::from),
+                16 => <[u8; 16]>::try_from(&ip[..]).ok().map(IpAddr::from),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    /// Get the MAC addresses the bridge has learned on a port
+    pub fn port_macs(port: &str) -> Result<Vec<String>> {
+        let ifindex = if_nametoindex(port)?;
+
+        let macs = dump(AF_BRIDGE)?
+            .iter()
+            .filter(|e| e.header.ifindex == ifindex)
+            // Permanent entries are the addresses of the TAC itself
+            .filter(|e| e.header.state & NUD_PERMANENT == 0)
+            .filter_map(lladdr)
+            .collect();
+
+        Ok(macs)
+    }
+
+    /// Get the (MAC, IP) pairs in the neighbor table of an interface
+    pub fn ip_neighbors(bridge: &str) -> Result<Vec<(String, String)>> {
+        let ifindex = if_nametoindex(bridge)?;
+        let mut neighbors = Vec::new();
+
+        for family in [AF_INET, AF_INET6] {
+            let valid = dump(family)?
+                .into_iter()
+                .filter(|e| e.header.ifindex == ifindex)
+                .filter(|e| e.header.state & (NUD_INCOMPLETE | NUD_FAILED | NUD_NOARP) == 0)
+                .filter_map(|e| Some((lladdr(&e)?, destination(&e)?.to_string())));
+
+            neighbors.extend(valid);
+        }
+
+        Ok(neighbors)
+    }
+}
+
+use nl::{ip_neighbors, port_macs, LEASE_FILES, OUI_DB};
+
+const DUT_PORT: &str = "dut";
+const BRIDGE: &str = "tac-bridge";
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// Hosts that have not been seen for this long are removed from the list
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct DhcpLease {
+    pub ip: String,
+    pub hostname: Option<String>,
+    /// Expiry of the lease in seconds since the Unix Epoch
+    pub expires: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DutHost {
+    pub mac: String,
+    pub vendor: Option<String>,
+    pub ips: Vec<String>,
+    pub lease: Option<DhcpLease>,
+    pub first_seen: Timestamp,
+    pub last_seen: Timestamp,
+}
+
+/// Parse a dnsmasq lease file into a map from MAC address to lease
+///
+/// Every line has the form `<expiry> <mac> <ip> <hostname> <client id>`,
+/// where the hostname is `*` if the client did not send one.
+fn parse_leases(content: &str) -> HashMap<String, DhcpLease> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let expires = fields.next()?.parse().ok()?;
+            let mac = fields.next()?.to_lowercase();
+            let ip = fields.next()?.to_string();
+            let hostname = fields.next().filter(|h| *h != "*").map(|h| h.to_string());
+
+            Some((
+                mac,
+                DhcpLease {
+                    ip,
+                    hostname,
+                    expires,
+                },
+            ))
+        })
+        .collect()
+}
+
+fn read_leases() -> HashMap<String, DhcpLease> {
+    LEASE_FILES
+        .iter()
+        .filter_map(|path| read_to_string(path).ok())
+        .flat_map(|content| parse_leases(&content))
+        .collect()
+}
+
+/// The first three bytes of a MAC address, which identify the vendor
+type Oui = [u8; 3];
+
+/// Parse the OUI from e.g. `b8:27:eb:65:43:21` (with `sep` = ':')
+fn parse_oui(addr: &str, sep: char) -> Option<Oui> {
+    let mut bytes = addr.split(sep).map(|b| u8::from_str_radix(b, 16).ok());
+
+    Some([bytes.next()??, bytes.next()??, bytes.next()??])
+}
+
+/// Look up the vendor for an OUI in the IEEE database
+///
+/// The database uses lines like `00-0A-35   (hex)\t\tXilinx`.
+/// It is several megabytes in size, so it is scanned line by line whenever
+/// a new OUI shows up instead of being kept in memory.
+fn lookup_vendor(oui_db: impl BufRead, oui: Oui) -> Option<String> {
+    oui_db.lines().map_while(|line| line.ok()).find_map(|line| {
+        let (prefix, vendor) = line.split_once("(hex)")?;
+
+        (parse_oui(prefix.trim(), '-')? == oui).then(|| vendor.trim().to_string())
+    })
+}
+
+struct Observer {
+    hosts: Vec<DutHost>,
+    vendors: HashMap<Oui, Option<String>>,
+}
+
+impl Observer {
+    fn new() -> Self {
+        if !Path::new(OUI_DB).exists() {
+            info!("No OUI database available at {OUI_DB}, will not resolve vendors");
+        }
+
+        Self {
+            hosts: Vec::new(),
+            vendors: HashMap::new(),
+        }
+    }
+
+    fn vendor(&mut self, mac: &str) -> Option<String> {
+        let oui = parse_oui(mac, ':')?;
+
+        self.vendors
+            .entry(oui)
+            .or_insert_with(|| {
+                let oui_db = File::open(OUI_DB).ok()?;
+                lookup_vendor(BufReader::new(oui_db), oui)
+            })
+            .clone()
+    }
+
+    fn update(&mut self) -> Result<()> {
+        let macs = port_macs(DUT_PORT)?;
+        let neighbors = ip_neighbors(BRIDGE)?;
+        let mut leases = read_leases();
+        let now = Timestamp::now();
+
+        for mac in macs {
+            let mut ips: Vec<String> = neighbors
+                .iter()
+                .filter(|(m, _)| *m == mac)
+                .map(|(_, ip)| ip.clone())
+                .collect();
+
+            let lease = leases.remove(&mac);
+
+            if let Some(lease) = &lease {
+                if !ips.contains(&lease.ip) {
+                    ips.push(lease.ip.clone());
+                }
+            }
+
+            ips.sort();
+
+            match self.hosts.iter_mut().find(|h| h.mac == mac) {
+                Some(host) => {
+                    host.ips = ips;
+                    host.lease = lease;
+                    host.last_seen = now;
+                }
+                None => {
+                    info!("New host {mac} on the DUT port");
+
+                    let vendor = self.vendor(&mac);
+
+                    self.hosts.push(DutHost {
+                        mac,
+                        vendor,
+                        ips,
+                        lease,
+                        first_seen: now,
+                        last_seen: now,
+                    })
+                }
+            }
+        }
+
+        self.hosts.retain(|h| h.last_seen.elapsed() < FORGET_AFTER);
+
+        Ok(())
+    }
+}
+
+pub fn setup(bb: &mut BrokerBuilder) {
+    let hosts = bb.topic_ro("/v1/tac/network/hosts/dut", Some(Vec::new()));
+
+    spawn_blocking(move || {
+        let mut observer = Observer::new();
+        let mut failing = false;
+
+        loop {
+            match observer.update() {
+                Ok(()) => {
+                    failing = false;
+                    hosts.set(observer.hosts.clone());
+                }
+                Err(e) => {
+                    // Only log the first of a series of errors, e.g. when the
+                    // interfaces do not exist.
+                    if !failing {
+                        warn!("Failed to update the hosts on the DUT port: {e}");
+                        failing = true;
+                    }
+                }
+            }
+
+            sleep(POLL_INTERVAL);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lookup_vendor, parse_leases, parse_oui};
+
+    #[test]
+    fn leases_and_vendors() {
+        let leases = parse_leases(
+            "1700000000 00:0A:35:12:34:56 192.168.1.50 zynq-dut 01:00:0a:35:12:34:56\n\
+             1700000100 b8:27:eb:65:43:21 192.168.1.51 * *\n\
+             garbage\n",
+        );
+
+        assert_eq!(leases.len(), 2);
+
+        let zynq = &leases["00:0a:35:12:34:56"];
+        assert_eq!(zynq.ip, "192.168.1.50");
+        assert_eq!(zynq.hostname.as_deref(), Some("zynq-dut"));
+        assert_eq!(zynq.expires, 1700000000);
+
+        assert_eq!(leases["b8:27:eb:65:43:21"].hostname, None);
+
+        let oui_db = "OUI/MA-L\t\t\tOrganization\n\
+                      00-0A-35   (hex)\t\tXilinx\n\
+                      000A35     (base 16)\t\tXilinx\n\
+                      B8-27-EB   (hex)\t\tRaspberry Pi Foundation\n";
+
+        let vendor = |mac| lookup_vendor(oui_db.as_bytes(), parse_oui(mac, ':').unwrap());
+
+        assert_eq!(
+            vendor("b8:27:eb:65:43:21").as_deref(),
+            Some("Raspberry Pi Foundation")
+        );
+        assert_eq!(vendor("00:0a:35:12:34:56").as_deref(), Some("Xilinx"));
+        assert_eq!(vendor("00:11:22:33:44:55"), None);
+        assert_eq!(parse_oui("garbage", ':'), None);
+    }
+}
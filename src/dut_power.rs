@@ -30,6 +30,7 @@ use crate::adc::AdcChannel;
 use crate::broker::{BrokerBuilder, Topic};
 use crate::digital_io::{find_line, LineHandle, LineRequestFlags};
 use crate::led::{BlinkPattern, BlinkPatternBuilder};
+use crate::thermal_protection::ThermalLock;
 
 #[cfg(any(test, feature = "demo_mode"))]
 mod prio {
@@ -109,6 +110,7 @@ pub enum OutputState {
     OverCurrent,
     OverVoltage,
     RealtimeViolation,
+    OverTemperature,
 }
 
 impl From<u8> for OutputState {
@@ -145,6 +147,10 @@ impl From<u8> for OutputState {
             return OutputState::RealtimeViolation;
         }
 
+        if val == (OutputState::OverTemperature as u8) {
+            return OutputState::OverTemperature;
+        }
+
         panic!()
     }
 }
@@ -184,6 +190,7 @@ impl TickReader {
 pub struct DutPwrThread {
     pub request: Arc<Topic<OutputRequest>>,
     pub state: Arc<Topic<OutputState>>,
+    pub thermal_lock: Arc<Topic<ThermalLock>>,
     tick: Arc<AtomicU32>,
 }
 
@@ -326,19 +333,25 @@ impl DutPwrThread {
                 let mut volt_filter = MedianFilter::<4>::new();
                 let mut curr_filter = MedianFilter::<4>::new();
 
-                let (tick_weak, request, state) = match realtime_priority() {
+                let (tick_weak, request, state, thermal_lock) = match realtime_priority() {
                     Ok(_) => {
                         let tick = Arc::new(AtomicU32::new(0));
                         let tick_weak = Arc::downgrade(&tick);
 
                         let request = Arc::new(AtomicU8::new(OutputRequest::Idle as u8));
                         let state = Arc::new(AtomicU8::new(OutputState::Off as u8));
+                        let thermal_lock = Arc::new(AtomicU8::new(ThermalLock::Released as u8));
 
                         thread_res_tx
-                            .try_send(Ok((tick, request.clone(), state.clone())))
+                            .try_send(Ok((
+                                tick,
+                                request.clone(),
+                                state.clone(),
+                                thermal_lock.clone(),
+                            )))
                             .unwrap();
 
-                        (tick_weak, request, state)
+                        (tick_weak, request, state, thermal_lock)
                     }
                     Err(e) => {
                         thread_res_tx.try_send(Err(e)).unwrap();
@@ -397,7 +410,7 @@ impl DutPwrThread {
                     // could be quite surprising for the output to turn on
                     // immediately when a fault is cleared after quite some time
                     // of the output being off.
-                    let req = request
+                    let mut req = request
                         .swap(OutputRequest::Idle as u8, Ordering::Relaxed)
                         .into();
 
@@ -442,6 +455,36 @@ impl DutPwrThread {
                         continue;
                     }
 
+                    // Keep the output off while the thermal protection is active.
+                    // Requests to turn the output off are still handled, so
+                    // that it is not turned on again once the TAC has cooled
+                    // down.
+                    match thermal_lock.load(Ordering::Relaxed).into() {
+                        ThermalLock::Released => {}
+                        ThermalLock::Locked => {
+                            let is_on = state.load(Ordering::Relaxed) == OutputState::On as u8;
+
+                            if is_on || req == OutputRequest::On {
+                                turn_off_with_reason(
+                                    OutputState::OverTemperature,
+                                    &pwr_line,
+                                    &discharge_line,
+                                    &state,
+                                );
+
+                                continue;
+                            }
+                        }
+                        ThermalLock::Restore => {
+                            let is_overtemp =
+                                state.load(Ordering::Relaxed) == OutputState::OverTemperature as u8;
+
+                            if is_overtemp && req == OutputRequest::Idle {
+                                req = OutputRequest::On;
+                            }
+                        }
+                    }
+
                     // There is no ongoing fault condition, so we could e.g. turn
                     // the output on if requested.
                     match req {
@@ -472,7 +515,7 @@ impl DutPwrThread {
                 turn_off_with_reason(OutputState::Off, &pwr_line, &discharge_line, &state);
             })?;
 
-        let (tick, request, state) = thread_res_rx.next().await.unwrap()?;
+        let (tick, request, state, thermal_lock) = thread_res_rx.next().await.unwrap()?;
 
         // The request and state topic use the same external path, this way one
         // can e.g. publish "On" to the topic and be sure that the output is
@@ -495,6 +538,16 @@ impl DutPwrThread {
             }
         });
 
+        // The thermal protection can lock the output in the off state.
+        // The lock is placed into an atomic variable read by the thread.
+        let thermal_lock_topic = Topic::anonymous(Some(ThermalLock::Released));
+        let (mut thermal_lock_stream, _) = thermal_lock_topic.clone().subscribe_unbounded();
+        task::spawn(async move {
+            while let Some(lock) = thermal_lock_stream.next().await {
+                thermal_lock.store(lock as u8, Ordering::Relaxed);
+            }
+        });
+
         // State information comes from the thread in the form of an atomic
         // variable and is forwarded to the broker framework.
         let state_topic_task = state_topic.clone();
@@ -541,6 +594,7 @@ impl DutPwrThread {
         Ok(Self {
             request: request_topic,
             state: state_topic,
+            thermal_lock: thermal_lock_topic,
             tick,
         })
     }
@@ -559,6 +613,7 @@ mod tests {
     use crate::adc::Adc;
     use crate::broker::{BrokerBuilder, Topic};
     use crate::digital_io::find_line;
+    use crate::thermal_protection::ThermalLock;
 
     use super::{
         DutPwrThread, OutputRequest, OutputState, DISCHARGE_LINE_ASSERTED, MAX_CURRENT,
@@ -715,6 +770,41 @@ mod tests {
         assert_eq!(block_on(dut_pwr.state.get()), OutputState::On);
         assert!(block_on(led.get()).is_on());
 
+        println!("Lock due to overtemperature");
+        dut_pwr.thermal_lock.set(ThermalLock::Locked);
+        block_on(sleep(Duration::from_millis(500)));
+        assert_eq!(pwr_line.stub_get(), 1 - PWR_LINE_ASSERTED);
+        assert_eq!(discharge_line.stub_get(), DISCHARGE_LINE_ASSERTED);
+        assert_eq!(block_on(dut_pwr.state.get()), OutputState::OverTemperature);
+        assert!(block_on(led.get()).is_blinking());
+
+        println!("Try to turn on while locked (Output should stay off)");
+        dut_pwr.request.set(OutputRequest::On);
+        block_on(sleep(Duration::from_millis(500)));
+        assert_eq!(pwr_line.stub_get(), 1 - PWR_LINE_ASSERTED);
+        assert_eq!(block_on(dut_pwr.state.get()), OutputState::OverTemperature);
+
+        println!("Restore after cooling down");
+        dut_pwr.thermal_lock.set(ThermalLock::Restore);
+        block_on(sleep(Duration::from_millis(500)));
+        assert_eq!(pwr_line.stub_get(), PWR_LINE_ASSERTED);
+        assert_eq!(discharge_line.stub_get(), 1 - DISCHARGE_LINE_ASSERTED);
+        assert_eq!(block_on(dut_pwr.state.get()), OutputState::On);
+        assert!(block_on(led.get()).is_on());
+
+        println!("Turn off while locked (Output should not be restored)");
+        dut_pwr.thermal_lock.set(ThermalLock::Locked);
+        block_on(sleep(Duration::from_millis(500)));
+        dut_pwr.request.set(OutputRequest::Off);
+        block_on(sleep(Duration::from_millis(500)));
+        assert_eq!(block_on(dut_pwr.state.get()), OutputState::Off);
+        dut_pwr.thermal_lock.set(ThermalLock::Restore);
+        block_on(sleep(Duration::from_millis(500)));
+        assert_eq!(pwr_line.stub_get(), 1 - PWR_LINE_ASSERTED);
+        assert_eq!(discharge_line.stub_get(), DISCHARGE_LINE_ASSERTED);
+        assert_eq!(block_on(dut_pwr.state.get()), OutputState::Off);
+        dut_pwr.thermal_lock.set(ThermalLock::Released);
+
         println!("Drop DutPwrThread");
         std::mem::drop(dut_pwr);
         block_on(sleep(Duration::from_millis(500)));
@@ -18,34 +18,25 @@
 use std::fs::write;
 use std::net::TcpListener;
 
+use async_std::sync::Arc;
 use tide::{Body, Response, Server};
 
 mod serve_dir;
 use serve_dir::serve_dir;
 
+use crate::config::HttpConfig;
+
 #[cfg(feature = "demo_mode")]
-mod consts {
-    pub const WEBUI_DIR: &str = "web/build";
-    pub const EXTRA_DIR: &str = "demo_files/srv/www";
-    pub const FS_PREFIX: &str = "demo_files";
-    pub const FALLBACK_PORT: &str = "[::]:8080";
-}
+pub const FS_PREFIX: &str = "demo_files";
 
 #[cfg(not(feature = "demo_mode"))]
-mod consts {
-    pub const WEBUI_DIR: &str = "/usr/share/tacd/webui";
-    pub const EXTRA_DIR: &str = "/srv/www";
-    pub const FS_PREFIX: &str = "";
-    pub const FALLBACK_PORT: &str = "[::]:80";
-}
-
-use consts::{EXTRA_DIR, FALLBACK_PORT, FS_PREFIX, WEBUI_DIR};
+pub const FS_PREFIX: &str = "";
 
 // openapi.json is generated by build.rs from openapi.yaml
 const OPENAPI_JSON: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/openapi.json"));
 
 // Files that should be read-/writeable from the webinterface
-const EXPOSED_FILES_RW: &[(&str, &str)] = &[
+pub const EXPOSED_FILES_RW: &[(&str, &str)] = &[
     (
         "/etc/labgrid/configuration.yaml",
         "/v1/labgrid/configuration",
@@ -60,23 +51,23 @@ pub struct HttpServer {
 }
 
 impl HttpServer {
-    pub fn new() -> Self {
+    pub fn new(config: &HttpConfig) -> Self {
         let mut this = Self {
             listeners: Vec::new(),
             server: tide::new(),
         };
 
-        // Open [::]:80 / [::]:8080. This, somewhat confusingly also listens on
-        // 0.0.0.0 and not only on IPv6.
+        // Open [::]:80 / [::]:8080 by default. This, somewhat confusingly also
+        // listens on 0.0.0.0 and not only on IPv6.
         this.listeners.push(
-            TcpListener::bind(FALLBACK_PORT).expect(
+            TcpListener::bind(&config.listen).expect(
                 "Could not bind web API to port, is there already another service running?",
             ),
         );
 
         this.expose_openapi_json();
-        this.expose_dir(WEBUI_DIR, "/", false);
-        this.expose_dir(EXTRA_DIR, "/srv", true);
+        this.expose_dir(&config.webui_dir, "/", false);
+        this.expose_dir(&config.srv_dir, "/srv", true);
 
         for (fs_path, web_path) in EXPOSED_FILES_RW {
             let fs_path = FS_PREFIX.to_owned() + *fs_path;
@@ -100,11 +91,16 @@ impl HttpServer {
     }
 
     /// Serve a directory from disk for reading
-    fn expose_dir(&mut self, fs_path: &'static str, web_path: &str, directory_listings: bool) {
-        let handler = move |req| async move { serve_dir(fs_path, directory_listings, req).await };
+    fn expose_dir(&mut self, fs_path: &str, web_path: &str, directory_listings: bool) {
+        let fs_path: Arc<str> = fs_path.into();
+
+        let handler = move |req| {
+            let fs_path = fs_path.clone();
+            async move { serve_dir(&fs_path, directory_listings, req).await }
+        };
 
-        self.server.at(web_path).get(handler);
-        self.server.at(web_path).at("").get(handler);
+        self.server.at(web_path).get(handler.clone());
+        self.server.at(web_path).at("").get(handler.clone());
         self.server.at(web_path).at("*rel_path").get(handler);
     }
 
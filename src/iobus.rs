@@ -15,8 +15,6 @@
 // with this program; if not, write to the Free Software Foundation, Inc.,
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 
-use std::time::Duration;
-
 use async_std::sync::Arc;
 use async_std::task::{sleep, spawn};
 
@@ -24,9 +22,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::adc::CalibratedChannel;
 use crate::broker::{BrokerBuilder, Topic};
-
-const CURRENT_MAX: f32 = 0.2;
-const VOLTAGE_MIN: f32 = 10.0;
+use crate::config::IoBusConfig;
 
 #[cfg(feature = "demo_mode")]
 mod http {
@@ -109,6 +105,7 @@ pub struct IoBus {
 impl IoBus {
     pub fn new(
         bb: &mut BrokerBuilder,
+        config: &IoBusConfig,
         iobus_pwr_en: Arc<Topic<bool>>,
         iobus_curr: CalibratedChannel,
         iobus_volt: CalibratedChannel,
@@ -121,19 +118,19 @@ impl IoBus {
         let server_info_task = server_info.clone();
         let nodes_task = nodes.clone();
 
+        let server_info_url = format!("{}/server-info/", config.server.trim_end_matches('/'));
+        let nodes_url = format!("{}/nodes/", config.server.trim_end_matches('/'));
+        let poll_interval = config.poll_interval();
+        let max_current = config.max_current;
+        let min_voltage = config.min_voltage;
+
         spawn(async move {
             loop {
-                if let Ok(si) = http::get("http://127.0.0.1:8080/server-info/")
-                    .recv_json::<ServerInfo>()
-                    .await
-                {
+                if let Ok(si) = http::get(&server_info_url).recv_json::<ServerInfo>().await {
                     server_info_task.set_if_changed(si);
                 }
 
-                if let Ok(nodes) = http::get("http://127.0.0.1:8080/nodes/")
-                    .recv_json::<Nodes>()
-                    .await
-                {
+                if let Ok(nodes) = http::get(&nodes_url).recv_json::<Nodes>().await {
                     nodes_task.set_if_changed(nodes);
                 }
 
@@ -142,12 +139,12 @@ impl IoBus {
                 let current = iobus_curr.get();
                 let voltage = iobus_volt.get();
 
-                let undervolt = pwr_en && (voltage.value < VOLTAGE_MIN);
-                let overcurrent = current.value > CURRENT_MAX;
+                let undervolt = pwr_en && (voltage.value < min_voltage);
+                let overcurrent = current.value > max_current;
 
                 supply_fault_task.set_if_changed(undervolt || overcurrent);
 
-                sleep(Duration::from_secs(1)).await;
+                sleep(poll_interval).await;
             }
         });
 
@@ -15,14 +15,17 @@
 // with this program; if not, write to the Free Software Foundation, Inc.,
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 
+use std::time::UNIX_EPOCH;
+
 use async_std::channel::bounded;
 use async_std::io::BufReader;
 use async_std::prelude::*;
 use async_std::task::{block_on, spawn_blocking};
 
-use serde::Deserialize;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use serde_json::to_string;
-use tide::http::Body;
+use tide::http::{mime, Body};
 use tide::{Request, Response, Server};
 
 #[cfg(any(test, feature = "demo_mode"))]
@@ -34,9 +37,21 @@ mod sd {
     use std::time::{Duration, SystemTime};
 
     pub type JournalRecord = BTreeMap<String, String>;
-    pub struct Journal;
     pub struct OpenOptions;
 
+    const UNITS: &[&str] = &[
+        "tacd.service",
+        "labgrid-exporter.service",
+        "lxa-iobus.service",
+    ];
+    const PRIORITIES: &[&str] = &["6", "4", "3"];
+    const INTERVAL: Duration = Duration::from_secs(5);
+    const HISTORY_LEN: u64 = 60;
+
+    fn now_us() -> u64 {
+        SystemTime::UNIX_EPOCH.elapsed().unwrap().as_micros() as u64
+    }
+
     impl OpenOptions {
         pub fn default() -> Self {
             Self
@@ -51,37 +66,125 @@ mod sd {
         }
 
         pub fn open(self) -> Result<Journal> {
-            Ok(Journal)
+            let mut journal = Journal {
+                entries: Vec::new(),
+                position: Position::Between(0),
+            };
+
+            let now = now_us();
+            let interval = INTERVAL.as_micros() as u64;
+
+            for i in 0..HISTORY_LEN {
+                journal.push(now - (HISTORY_LEN - i) * interval);
+            }
+
+            Ok(journal)
         }
     }
 
+    enum Position {
+        /// Before the entry with the given index (like after a seek)
+        Between(usize),
+        On(usize),
+    }
+
+    /// An in-memory journal with some made up history
+    pub struct Journal {
+        entries: Vec<(u64, JournalRecord)>,
+        position: Position,
+    }
+
     impl Journal {
+        fn push(&mut self, ts: u64) {
+            let idx = self.entries.len();
+            let priority = PRIORITIES[(idx / UNITS.len()) % PRIORITIES.len()];
+
+            let mut rec = JournalRecord::new();
+            rec.insert("_SOURCE_REALTIME_TIMESTAMP".to_string(), format!("{ts}"));
+            rec.insert("UNIT".to_string(), UNITS[idx % UNITS.len()].to_string());
+            rec.insert("PRIORITY".to_string(), priority.to_string());
+            rec.insert("MESSAGE".to_string(), format!("Says HI! #{idx}"));
+
+            self.entries.push((ts, rec));
+        }
+
+        fn current(&self) -> Result<usize> {
+            match self.position {
+                Position::On(idx) => Ok(idx),
+                Position::Between(_) => Err(Error::new(ErrorKind::Other, "Not on an entry")),
+            }
+        }
+
         pub fn seek_tail(&mut self) -> Result<()> {
+            self.position = Position::Between(self.entries.len());
             Ok(())
         }
 
-        pub fn previous_entry(&mut self) -> Result<Option<JournalRecord>> {
-            Ok(None)
+        pub fn seek_realtime_usec(&mut self, usec: u64) -> Result<()> {
+            let idx = self
+                .entries
+                .iter()
+                .position(|(ts, _)| *ts >= usec)
+                .unwrap_or(self.entries.len());
+
+            self.position = Position::Between(idx);
+            Ok(())
+        }
+
+        pub fn seek_cursor(&mut self, cursor: &str) -> Result<()> {
+            let idx: usize = cursor
+                .strip_prefix("demo-")
+                .and_then(|idx| idx.parse().ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid cursor"))?;
+
+            self.position = Position::Between(idx.min(self.entries.len()));
+            Ok(())
         }
 
-        pub fn watch_all_elements<F>(&mut self, mut f: F) -> Result<()>
-        where
-            F: FnMut(JournalRecord) -> Result<()>,
-        {
-            for _i in 0..10 {
-                let ts = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_micros();
+        pub fn cursor(&self) -> Result<String> {
+            Ok(format!("demo-{}", self.current()?))
+        }
+
+        pub fn timestamp(&self) -> Result<SystemTime> {
+            let ts = self.entries[self.current()?].0;
+            Ok(SystemTime::UNIX_EPOCH + Duration::from_micros(ts))
+        }
 
-                let mut rec = JournalRecord::new();
-                rec.insert("_SOURCE_REALTIME_TIMESTAMP".to_string(), format!("{ts}"));
-                rec.insert("UNIT".to_string(), "tacd.service".to_string());
-                rec.insert("MESSAGE".to_string(), "Says HI!".to_string());
+        pub fn next_entry(&mut self) -> Result<Option<JournalRecord>> {
+            let next = match self.position {
+                Position::Between(idx) => idx,
+                Position::On(idx) => idx + 1,
+            };
+
+            if next >= self.entries.len() {
+                return Ok(None);
+            }
 
-                f(rec)?;
+            self.position = Position::On(next);
+            Ok(Some(self.entries[next].1.clone()))
+        }
 
-                sleep(Duration::from_secs(5));
+        pub fn previous_entry(&mut self) -> Result<Option<JournalRecord>> {
+            let previous = match self.position {
+                Position::Between(idx) | Position::On(idx) => idx.checked_sub(1),
+            };
+
+            match previous {
+                Some(idx) => {
+                    self.position = Position::On(idx);
+                    Ok(Some(self.entries[idx].1.clone()))
+                }
+                None => Ok(None),
             }
+        }
 
-            Err(Error::new(ErrorKind::Other, "Simulation ended"))
+        pub fn await_next_entry(
+            &mut self,
+            _wait_time: Option<Duration>,
+        ) -> Result<Option<JournalRecord>> {
+            sleep(INTERVAL);
+            self.push(now_us());
+            self.next_entry()
         }
     }
 }
@@ -94,149 +197,559 @@ mod sd {
 
 use sd::{Journal, JournalRecord, OpenOptions, Result};
 
-#[derive(Deserialize)]
+// Limit the number of entries looked at for a single request, so that
+// requests with very selective filters can not keep the TAC busy for long.
+// Clients can continue from the returned cursor instead.
+const SCAN_LIMIT: usize = 100_000;
+
+const DEFAULT_HISTORY_LEN: usize = 10;
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 10_000;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    /// Follow the journal via server sent events
+    Sse,
+    /// Return a single page of entries
+    Json,
+}
+
+#[derive(Deserialize, Default)]
 struct QueryParams {
-    history_len: Option<u64>,
+    /// The number of past entries to send before following the journal
+    history_len: Option<usize>,
+    /// A comma separated list of units
     unit: Option<String>,
+    /// Only include entries with this or a more important priority (0-7)
+    priority: Option<u8>,
+    /// Milliseconds since the Unix epoch
+    since: Option<u64>,
+    /// Milliseconds since the Unix epoch
+    until: Option<u64>,
+    /// Only include entries whose message contains this string
+    message: Option<String>,
+    /// Only include entries whose message matches this regular expression
+    regex: Option<String>,
+    /// Continue after the entry with this cursor
+    after: Option<String>,
+    /// The maximum number of entries in a JSON response
+    limit: Option<usize>,
+    format: Option<Format>,
 }
 
-struct UnitFilter {
-    unit: Option<String>,
+#[derive(Serialize)]
+struct Page {
+    entries: Vec<JournalRecord>,
+    /// Pass this as `after` to get the entries following this page
+    cursor: Option<String>,
+    /// False if there may be more matching entries that did not fit into
+    /// this page.
+    complete: bool,
 }
 
-impl UnitFilter {
-    pub fn new(unit: Option<String>) -> Self {
-        Self { unit }
+struct Filter {
+    units: Vec<String>,
+    priority: Option<u8>,
+    since_us: Option<u64>,
+    until_us: Option<u64>,
+    message: Option<String>,
+    regex: Option<Regex>,
+}
+
+impl Filter {
+    fn new(params: &QueryParams) -> std::result::Result<Self, String> {
+        let units = params
+            .unit
+            .iter()
+            .flat_map(|units| units.split(','))
+            .map(|unit| unit.trim().to_string())
+            .filter(|unit| !unit.is_empty())
+            .collect();
+
+        if params.priority.map(|p| p > 7).unwrap_or(false) {
+            return Err("The priority has to be in the range from 0 to 7".to_string());
+        }
+
+        let regex = params
+            .regex
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| format!("Invalid regular expression: {e}"))?;
+
+        Ok(Self {
+            units,
+            priority: params.priority,
+            since_us: params.since.map(|ms| ms * 1000),
+            until_us: params.until.map(|ms| ms * 1000),
+            message: params.message.clone(),
+            regex,
+        })
+    }
+
+    fn is_after_range(&self, ts: u64) -> bool {
+        self.until_us.map(|until| ts > until).unwrap_or(false)
+    }
+
+    fn is_before_range(&self, ts: u64) -> bool {
+        self.since_us.map(|since| ts < since).unwrap_or(false)
     }
 
-    pub fn filter(&self, record: JournalRecord) -> Option<JournalRecord> {
-        let unit = record.get("UNIT").or(record.get("_SYSTEMD_UNIT"));
+    fn matches(&self, ts: u64, record: &JournalRecord) -> bool {
+        if self.is_before_range(ts) || self.is_after_range(ts) {
+            return false;
+        }
+
+        if !self.units.is_empty() {
+            let unit = record.get("UNIT").or_else(|| record.get("_SYSTEMD_UNIT"));
+
+            if !unit.map(|u| self.units.contains(u)).unwrap_or(false) {
+                return false;
+            }
+        }
+
+        if let Some(max) = self.priority {
+            let priority = record.get("PRIORITY").and_then(|p| p.parse::<u8>().ok());
+
+            if !priority.map(|p| p <= max).unwrap_or(false) {
+                return false;
+            }
+        }
+
+        let message = record.get("MESSAGE").map(|m| m.as_str()).unwrap_or("");
 
-        // Send the entry if the unit matches or no filter was set
-        let should_send = match (unit, self.unit.as_ref()) {
-            (_, None) => true,
-            (Some(u), Some(f)) => u == f,
-            (None, Some(_)) => false,
+        if let Some(pattern) = &self.message {
+            if !message.contains(pattern.as_str()) {
+                return false;
+            }
+        }
+
+        if let Some(regex) = &self.regex {
+            if !regex.is_match(message) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+fn open_journal() -> Result<Journal> {
+    OpenOptions::default().system(true).local_only(true).open()
+}
+
+/// Add the cursor and timestamp of the current entry to its record, so
+/// that clients can resume from it.
+fn annotate(journal: &Journal, mut record: JournalRecord) -> Result<(u64, JournalRecord)> {
+    let ts = journal
+        .timestamp()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0);
+
+    record.insert("__CURSOR".to_string(), journal.cursor()?);
+    record.insert("__REALTIME_TIMESTAMP".to_string(), format!("{ts}"));
+
+    Ok((ts, record))
+}
+
+/// Move to the position to read entries forwards from
+///
+/// Returns false if there is no such position and the newest entries
+/// (before `until`) should be read backwards instead.
+fn seek_start(journal: &mut Journal, after: Option<&str>, filter: &Filter) -> Result<bool> {
+    if let Some(cursor) = after {
+        journal.seek_cursor(cursor)?;
+
+        // Skip the entry the cursor points to, as the client already has it
+        journal.next_entry()?;
+
+        return Ok(true);
+    }
+
+    if let Some(since) = filter.since_us {
+        journal.seek_realtime_usec(since)?;
+        return Ok(true);
+    }
+
+    match filter.until_us {
+        Some(until) => journal.seek_realtime_usec(until)?,
+        None => journal.seek_tail()?,
+    }
+
+    Ok(false)
+}
+
+/// Collect up to `count` matching entries going backwards in time
+///
+/// Returns the entries in chronological order, the cursor of the newest entry
+/// looked at and if there are no more (older) matching entries.
+fn read_backwards(
+    journal: &mut Journal,
+    filter: &Filter,
+    count: usize,
+) -> Result<(Vec<JournalRecord>, Option<String>, bool)> {
+    let mut entries = Vec::new();
+    let mut newest = None;
+    let mut complete = false;
+
+    for _ in 0..SCAN_LIMIT {
+        if entries.len() >= count {
+            break;
+        }
+
+        let (ts, record) = match journal.previous_entry()? {
+            Some(record) => annotate(journal, record)?,
+            None => {
+                complete = true;
+                break;
+            }
         };
 
-        if should_send {
-            Some(record)
-        } else {
-            None
+        if newest.is_none() {
+            newest = record.get("__CURSOR").cloned();
+        }
+
+        if filter.is_before_range(ts) {
+            complete = true;
+            break;
+        }
+
+        if filter.matches(ts, &record) {
+            entries.push(record);
         }
     }
+
+    entries.reverse();
+
+    Ok((entries, newest, complete))
 }
 
-fn open_journal(mut history_len: u64, filter: &UnitFilter) -> Result<Journal> {
-    let mut journal = OpenOptions::default()
-        .system(true)
-        .local_only(true)
-        .open()?;
+/// Collect up to `count` matching entries going forwards in time
+///
+/// Returns the entries, the cursor of the last entry looked at and if
+/// the end of the journal or requested time range was reached.
+fn read_forwards(
+    journal: &mut Journal,
+    filter: &Filter,
+    count: usize,
+) -> Result<(Vec<JournalRecord>, Option<String>, bool)> {
+    let mut entries = Vec::new();
+    let mut last = None;
+    let mut complete = false;
+
+    for _ in 0..SCAN_LIMIT {
+        if entries.len() >= count {
+            break;
+        }
+
+        let (ts, record) = match journal.next_entry()? {
+            Some(record) => annotate(journal, record)?,
+            None => {
+                complete = true;
+                break;
+            }
+        };
+
+        if filter.is_after_range(ts) {
+            complete = true;
+            break;
+        }
+
+        last = record.get("__CURSOR").cloned();
+
+        if filter.matches(ts, &record) {
+            entries.push(record);
+        }
+    }
+
+    Ok((entries, last, complete))
+}
+
+fn query(params: &QueryParams, filter: &Filter) -> Result<Page> {
+    let mut journal = open_journal()?;
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+
+    let (entries, cursor, complete) = if seek_start(&mut journal, params.after.as_deref(), filter)?
+    {
+        read_forwards(&mut journal, filter, limit)?
+    } else {
+        read_backwards(&mut journal, filter, limit)?
+    };
+
+    Ok(Page {
+        entries,
+        // Let the client resume from the same position if nothing new was read
+        cursor: cursor.or_else(|| params.after.clone()),
+        complete,
+    })
+}
+
+/// Get the newest `count` entries of the journal in chronological order
+pub fn recent(count: usize) -> Result<Vec<JournalRecord>> {
+    let filter = Filter::new(&QueryParams::default()).unwrap();
+    let mut journal = open_journal()?;
 
     journal.seek_tail()?;
 
-    let mut element_limit = 2048;
+    let (entries, _, _) = read_backwards(&mut journal, &filter, count)?;
 
-    // Try to go back far enough to have history_len entries matching the
-    // specified filter in in the backlog. But limit the effort to a maximum
-    // number of elements to look at.
-    while (history_len > 0) && (element_limit > 0) {
-        if let Some(entry) = journal.previous_entry()? {
-            if filter.filter(entry).is_some() {
-                history_len -= 1;
+    Ok(entries)
+}
+
+/// Send matching entries as server sent events
+///
+/// Sends the history first and then follows the journal until the end of
+/// the requested time range (if any) is reached.
+fn follow(
+    journal: &mut Journal,
+    params: &QueryParams,
+    filter: &Filter,
+    sender: &async_sse::Sender,
+) -> Result<()> {
+    let send = |record: &JournalRecord| -> Result<()> {
+        let json = to_string(record)?;
+        block_on(sender.send("entry", &json, None))
+    };
+
+    if !seek_start(journal, params.after.as_deref(), filter)? {
+        let history_len = params.history_len.unwrap_or(DEFAULT_HISTORY_LEN);
+        let (history, newest, _) = read_backwards(journal, filter, history_len)?;
+
+        for record in history.iter() {
+            send(record)?;
+        }
+
+        // Continue after the newest entry we have looked at
+        match newest {
+            Some(cursor) => {
+                journal.seek_cursor(cursor.as_str())?;
+                journal.next_entry()?;
             }
+            None => journal.seek_tail()?,
+        }
+    }
+
+    loop {
+        let record = match journal.next_entry()? {
+            Some(record) => record,
+            None => match journal.await_next_entry(None)? {
+                Some(record) => record,
+                None => continue,
+            },
+        };
 
-            element_limit -= 1
-        } else {
-            element_limit = 0;
+        let (ts, record) = annotate(journal, record)?;
+
+        if filter.is_after_range(ts) {
+            return Ok(());
         }
+
+        if filter.matches(ts, &record) {
+            send(&record)?;
+        }
+    }
+}
+
+fn error_response(status: u16, message: String) -> Response {
+    Response::builder(status)
+        .body(message)
+        .content_type(mime::PLAIN)
+        .build()
+}
+
+async fn serve_json(params: QueryParams, filter: Filter) -> Response {
+    // The Journal is not Send, so it has to be used in a single thread
+    let page = spawn_blocking(move || query(&params, &filter)).await;
+
+    match page {
+        Ok(page) => match Body::from_json(&page) {
+            Ok(body) => Response::builder(200)
+                .body(body)
+                .content_type(mime::JSON)
+                .build(),
+            Err(e) => error_response(500, format!("Failed to encode journal entries: {e}")),
+        },
+        Err(e) => error_response(500, format!("Failed to read the journal: {e}")),
     }
+}
+
+async fn serve_sse(params: QueryParams, filter: Filter) -> Response {
+    let (response_tx, mut response_rx) = bounded::<Response>(1);
+
+    // The Journal is not Send, so it has to be set up in the thread
+    // that uses it.
+    // It would however be nice to return a HTTP error code if the
+    // setup process fails early on.
+    // This is why we have this channel contraption, which sends a single
+    // response back to be sent to the client.
+    spawn_blocking(move || {
+        let mut journal = match open_journal() {
+            Ok(j) => j,
+            Err(e) => {
+                let resp = error_response(500, format!("Failed to open journal file(s): {e}"));
+                let _ = response_tx.try_send(resp);
+                return;
+            }
+        };
+
+        // The journal was opened successfully, we can send a successful
+        // response to the client.
+        let (sender, encoder) = async_sse::encode();
+
+        let resp = Response::builder(200)
+            .body(Body::from_reader(BufReader::new(encoder), None))
+            .header("Cache-Control", "no-cache")
+            .content_type(mime::SSE)
+            .build();
+
+        if response_tx.try_send(resp).is_err() {
+            // The Future handling the get request was canceled, the
+            // response Receiver dropped and thus the channel closed.
+            return;
+        }
 
-    Ok(journal)
+        // An error occurred once we have already set up the SSE session
+        // (e.g. a success was already signaled via HTTP response code).
+        // Use an extra "error" SSE topic to somehow inform the client
+        // anyways.
+        if let Err(e) = follow(&mut journal, &params, &filter, &sender) {
+            let _ = block_on(sender.send("error", &e.to_string(), None));
+        }
+    });
+
+    response_rx.next().await.unwrap_or(
+        Response::builder(500)
+            .body("Journal reader stopped unexpectedly")
+            .build(),
+    )
 }
 
 pub fn serve(server: &mut Server<()>) {
     server
         .at("/v1/tac/journal")
         .get(|req: Request<()>| async move {
-            let (response_tx, mut response_rx) = bounded::<Response>(1);
-
-            // The Journal is not Send, so it has to be set up in the thread
-            // that uses it.
-            // It would however be nice to return a HTTP error code if the
-            // setup process fails early on.
-            // This is why we have this channel contraption, which sends a single
-            // response back to be sent to the client.
-            spawn_blocking(move || {
-                let (sender, mut journal, filter) = {
-                    let (unit, history_len) = match req.query() {
-                        Ok(QueryParams { history_len, unit }) => (unit, history_len),
-                        Err(e) => {
-                            let resp = Response::builder(500)
-                                .body(format!("Failed to parse query parameters: {e}"))
-                                .build();
-                            let _ = response_tx.try_send(resp);
-                            return;
-                        }
-                    };
-
-                    let filter = UnitFilter::new(unit);
-
-                    let journal = match open_journal(history_len.unwrap_or(10), &filter) {
-                        Ok(j) => j,
-                        Err(e) => {
-                            let resp = Response::builder(500)
-                                .body(format!("Failed to open journal file(s): {e}"))
-                                .build();
-                            let _ = response_tx.try_send(resp);
-                            return;
-                        }
-                    };
-
-                    // The journal was opened successfully, we can send a successful
-                    // response to the client.
-                    let (sender, encoder) = async_sse::encode();
-
-                    let resp = Response::builder(200)
-                        .body(Body::from_reader(BufReader::new(encoder), None))
-                        .header("Cache-Control", "no-cache")
-                        .content_type(tide::http::mime::SSE)
-                        .build();
-
-                    if response_tx.try_send(resp).is_err() {
-                        // The Future handling the get request was canceled, the
-                        // response Receiver dropped and thus the channel closed.
-                        return;
-                    }
-
-                    (sender, journal, filter)
-                };
-
-                let sender_watch = sender.clone();
-                let res = journal.watch_all_elements(move |element| {
-                    if let Some(elem) = filter.filter(element) {
-                        let json = to_string(&elem)?;
-                        block_on(sender_watch.send("entry", &json, None))?;
-                    }
-
-                    Ok(())
-                });
-
-                // An error occurred once we have already set up the SSE session
-                // (e.g. a success was already signaled via HTTP response code).
-                // Use an extra "error" SSE topic to somehow inform the client
-                // anyways.
-                if let Err(e) = res {
-                    let _ = block_on(sender.send("error", &e.to_string(), None));
+            let params: QueryParams = match req.query() {
+                Ok(params) => params,
+                Err(e) => {
+                    let msg = format!("Failed to parse query parameters: {e}");
+                    return Ok(error_response(400, msg));
                 }
-            });
+            };
 
-            let resp = response_rx.next().await.unwrap_or(
-                Response::builder(500)
-                    .body("Journal reader stopped unexpectedly")
-                    .build(),
-            );
+            let filter = match Filter::new(&params) {
+                Ok(filter) => filter,
+                Err(e) => return Ok(error_response(400, e)),
+            };
+
+            let resp = match params.format.unwrap_or(Format::Sse) {
+                Format::Sse => serve_sse(params, filter).await,
+                Format::Json => serve_json(params, filter).await,
+            };
 
             Ok(resp)
         });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{query, Filter, QueryParams};
+
+    fn page(params: QueryParams) -> (Vec<String>, Option<String>, bool) {
+        let filter = Filter::new(&params).unwrap();
+        let page = query(&params, &filter).unwrap();
+
+        let messages = page.entries.iter().map(|e| e["MESSAGE"].clone()).collect();
+
+        (messages, page.cursor, page.complete)
+    }
+
+    #[test]
+    fn filters() {
+        // Without a start position the newest entries are returned in
+        // chronological order.
+        let (messages, _, complete) = page(QueryParams {
+            limit: Some(3),
+            ..Default::default()
+        });
+        assert_eq!(messages, ["Says HI! #57", "Says HI! #58", "Says HI! #59"]);
+        assert!(!complete);
+
+        let (messages, _, _) = page(QueryParams {
+            unit: Some("labgrid-exporter.service, lxa-iobus.service".to_string()),
+            limit: Some(3),
+            ..Default::default()
+        });
+        assert_eq!(messages, ["Says HI! #56", "Says HI! #58", "Says HI! #59"]);
+
+        // Entries #3 to #5, #12 to #14 ... have priority 4 (warning).
+        let (messages, _, _) = page(QueryParams {
+            priority: Some(4),
+            unit: Some("tacd.service".to_string()),
+            regex: Some("#[0-9]$".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(messages, ["Says HI! #3", "Says HI! #6"]);
+
+        let (messages, _, complete) = page(QueryParams {
+            message: Some("HI! #4".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(messages.len(), 11);
+        assert!(complete);
+
+        assert!(Filter::new(&QueryParams {
+            priority: Some(8),
+            ..Default::default()
+        })
+        .is_err());
+
+        assert!(Filter::new(&QueryParams {
+            regex: Some("(".to_string()),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn pagination() {
+        let mut after = None;
+        let mut messages = Vec::new();
+
+        // Page through all tacd entries using the cursor
+        loop {
+            let (page, cursor, complete) = page(QueryParams {
+                unit: Some("tacd.service".to_string()),
+                since: Some(0),
+                after: after.take(),
+                limit: Some(7),
+                ..Default::default()
+            });
+
+            assert!(page.len() <= 7);
+            messages.extend(page);
+            after = cursor;
+
+            if complete {
+                break;
+            }
+        }
+
+        let expected: Vec<String> = (0..60)
+            .step_by(3)
+            .map(|i| format!("Says HI! #{i}"))
+            .collect();
+
+        assert_eq!(messages, expected);
+
+        // There is nothing new after the last entry
+        let (page, cursor, complete) = page(QueryParams {
+            after: after.clone(),
+            ..Default::default()
+        });
+        assert!(page.is_empty());
+        assert_eq!(cursor, after);
+        assert!(complete);
+    }
+}
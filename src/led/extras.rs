@@ -15,9 +15,11 @@
 // with this program; if not, write to the Free Software Foundation, Inc.,
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 
+use std::convert::{TryFrom, TryInto};
 use std::io::Result;
 use std::time::Duration;
 
+use anyhow::bail;
 use serde::{Deserialize, Serialize};
 
 use super::{Brightness, Leds, SysClass};
@@ -47,12 +49,151 @@ impl RgbColor for Leds {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+// Limits imposed by the kernel's ledtrig-pattern driver.
+// Patterns exceeding these would be rejected when written to sysfs.
+const MAX_STEPS: usize = 1024;
+const MAX_STEP_DURATION_MS: u64 = u32::MAX as u64;
+
+// The whole pattern is written to sysfs as a single string, which can be at
+// most PAGE_SIZE - 1 bytes long.
+const MAX_PATTERN_LEN: usize = 4095;
+
+// The largest max_brightness of the LEDs on the TAC (the RGB status LED).
+// Patterns are checked against it, as it results in the longest strings.
+const MAX_LED_BRIGHTNESS: u64 = 65535;
+
+/// Render the steps of a pattern in the format expected by the kernel
+fn render(steps: &[(f32, Duration)], max_brightness: u64) -> String {
+    let max = max_brightness as f32;
+
+    steps
+        .iter()
+        .map(|(brightness, duration)| {
+            let brightness = (brightness * max).round();
+            let duration = duration.as_millis();
+            format!("{} {} ", brightness, duration)
+        })
+        .collect()
+}
+
+/// A single step in the JSON representation of a blink pattern
+///
+/// The LED takes `duration_ms` to go from this step's `brightness` to the
+/// brightness of the next step.
+/// If both steps have the same brightness the LED stays at it for the
+/// duration (stay), if they differ the LED fades between them (fade).
+/// A step with a duration of zero results in an instantaneous change
+/// (step).
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BlinkStep {
+    brightness: f32,
+    duration_ms: u64,
+}
+
+/// The JSON representation of a blink pattern
+///
+/// This is the stable format used to exchange patterns via the API.
+/// `repetitions` is the number of times the pattern is played, or -1 to
+/// repeat it forever.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BlinkPatternJson {
+    repetitions: i32,
+    steps: Vec<BlinkStep>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(try_from = "BlinkPatternJson", into = "BlinkPatternJson")]
 pub struct BlinkPattern {
     repetitions: i32,
     steps: Vec<(f32, Duration)>,
 }
 
+impl TryFrom<BlinkPatternJson> for BlinkPattern {
+    type Error = anyhow::Error;
+
+    fn try_from(json: BlinkPatternJson) -> anyhow::Result<Self> {
+        if json.repetitions == 0 || json.repetitions < -1 {
+            bail!(
+                "Blink pattern repetitions must be -1 (forever) or positive, not {}",
+                json.repetitions
+            );
+        }
+
+        if json.steps.is_empty() {
+            bail!("Blink pattern must contain at least one step");
+        }
+
+        if json.steps.len() > MAX_STEPS {
+            bail!(
+                "Blink pattern contains {} steps but at most {} are supported",
+                json.steps.len(),
+                MAX_STEPS
+            );
+        }
+
+        let steps = json
+            .steps
+            .into_iter()
+            .map(|step| {
+                if !(0.0..=1.0).contains(&step.brightness) {
+                    bail!(
+                        "Blink pattern brightness must be in the range 0.0 to 1.0, not {}",
+                        step.brightness
+                    );
+                }
+
+                if step.duration_ms > MAX_STEP_DURATION_MS {
+                    bail!(
+                        "Blink pattern step duration must be at most {} ms, not {}",
+                        MAX_STEP_DURATION_MS,
+                        step.duration_ms
+                    );
+                }
+
+                Ok((step.brightness, Duration::from_millis(step.duration_ms)))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // Check the length with every step at full brightness on the LED
+        // with the largest max_brightness, which is the longest it can get.
+        let worst_case: Vec<_> = steps.iter().map(|(_, d)| (1.0, *d)).collect();
+        let len = render(&worst_case, MAX_LED_BRIGHTNESS).len();
+
+        if len > MAX_PATTERN_LEN {
+            bail!(
+                "Blink pattern is up to {} bytes long when written to sysfs, but at most {} are supported",
+                len,
+                MAX_PATTERN_LEN
+            );
+        }
+
+        Ok(Self {
+            repetitions: json.repetitions,
+            steps,
+        })
+    }
+}
+
+impl From<BlinkPattern> for BlinkPatternJson {
+    fn from(pattern: BlinkPattern) -> Self {
+        let steps = pattern
+            .steps
+            .into_iter()
+            .map(|(brightness, duration)| BlinkStep {
+                brightness,
+                duration_ms: duration.as_millis().try_into().unwrap_or(u64::MAX),
+            })
+            .collect();
+
+        Self {
+            repetitions: pattern.repetitions,
+            steps,
+        }
+    }
+}
+
 impl BlinkPattern {
     pub fn solid(val: f32) -> Self {
         Self {
@@ -132,20 +273,125 @@ pub trait Pattern: SysClass {
 
 impl Pattern for Leds {
     fn set_pattern(&self, pattern: BlinkPattern) -> Result<()> {
-        let max = self.max_brightness()? as f32;
+        let max = self.max_brightness()?;
         let repetitions = pattern.repetitions;
-        let pattern: String = pattern
-            .steps
-            .iter()
-            .map(|(brightness, duration)| {
-                let brightness = (brightness * max).round();
-                let duration = duration.as_millis();
-                format!("{} {} ", brightness, duration)
-            })
-            .collect();
+        let pattern = render(&pattern.steps, max);
 
         self.write_file("trigger", "pattern")?;
         self.write_file("pattern", pattern)?;
         self.write_file("repeat", repetitions.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{BlinkPattern, BlinkPatternBuilder, MAX_STEPS};
+
+    fn from_json(json: &str) -> serde_json::Result<BlinkPattern> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let patterns = [
+            BlinkPattern::solid(0.0),
+            BlinkPattern::solid(1.0),
+            BlinkPatternBuilder::new(0.0)
+                .fade_to(1.0, Duration::from_millis(500))
+                .stay_for(Duration::from_millis(1000))
+                .step_to(0.0)
+                .stay_for(Duration::from_millis(250))
+                .forever(),
+            BlinkPatternBuilder::new(1.0)
+                .step_to(0.5)
+                .stay_for(Duration::from_millis(100))
+                .repeat(3),
+        ];
+
+        for pattern in patterns {
+            let json = serde_json::to_string(&pattern).unwrap();
+            let parsed = from_json(&json).unwrap();
+
+            assert_eq!(pattern, parsed);
+        }
+    }
+
+    #[test]
+    fn stable_format() {
+        let pattern = BlinkPatternBuilder::new(0.0)
+            .step_to(1.0)
+            .fade_to(0.0, Duration::from_millis(200))
+            .forever();
+
+        let json = serde_json::to_string(&pattern).unwrap();
+
+        assert_eq!(
+            json,
+            r#"{"repetitions":-1,"steps":[{"brightness":1.0,"duration_ms":0},{"brightness":0.0,"duration_ms":200}]}"#
+        );
+    }
+
+    #[test]
+    fn limits() {
+        fn valid(repetitions: i32, steps: &[&str]) -> bool {
+            let json = format!(
+                r#"{{"repetitions": {}, "steps": [{}]}}"#,
+                repetitions,
+                steps.join(",")
+            );
+
+            from_json(&json).is_ok()
+        }
+
+        let off = r#"{"brightness": 0.0, "duration_ms": 10}"#;
+
+        // Repetitions must be -1 (forever) or positive
+        assert!(valid(1, &[off]));
+        assert!(valid(-1, &[off]));
+        assert!(!valid(0, &[off]));
+        assert!(!valid(-2, &[off]));
+
+        // There must be at least one but at most MAX_STEPS steps
+        assert!(!valid(1, &[]));
+        assert!(!valid(1, &vec![off; MAX_STEPS + 1]));
+
+        // The pattern has to fit into a single sysfs write, even at full
+        // brightness on the LED with the largest max_brightness.
+        // Each of these steps is rendered as "65535 100 ".
+        let short = r#"{"brightness": 0.0, "duration_ms": 100}"#;
+        let mut steps = vec![short; 408];
+
+        // 408 * 10 + 15 bytes ("65535 10000000 ")
+        steps.push(r#"{"brightness": 0.0, "duration_ms": 10000000}"#);
+        assert!(valid(1, &steps));
+
+        // 408 * 10 + 16 bytes ("65535 100000000 ")
+        steps.pop();
+        steps.push(r#"{"brightness": 0.0, "duration_ms": 100000000}"#);
+        assert!(!valid(1, &steps));
+
+        // Brightness must be in the range 0.0 to 1.0
+        assert!(valid(1, &[r#"{"brightness": 1.0, "duration_ms": 0}"#]));
+        assert!(!valid(1, &[r#"{"brightness": 1.5, "duration_ms": 0}"#]));
+        assert!(!valid(1, &[r#"{"brightness": -0.1, "duration_ms": 0}"#]));
+
+        // The kernel stores step durations as 32 bit unsigned milliseconds
+        assert!(valid(
+            1,
+            &[r#"{"brightness": 0.0, "duration_ms": 4294967295}"#]
+        ));
+        assert!(!valid(
+            1,
+            &[r#"{"brightness": 0.0, "duration_ms": 4294967296}"#]
+        ));
+
+        // Missing and unknown fields are rejected
+        assert!(!valid(1, &[r#"{"brightness": 0.0}"#]));
+        assert!(!valid(
+            1,
+            &[r#"{"brightness": 0.0, "duration_ms": 0, "x": 1}"#]
+        ));
+    }
+}
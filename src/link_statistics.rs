@@ -0,0 +1,309 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use async_std::task::{sleep, spawn};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::broker::BrokerBuilder;
+use crate::measurement::{Measurement, Timestamp};
+
+#[cfg(feature = "demo_mode")]
+mod counters {
+    use std::io::Result;
+    use std::time::SystemTime;
+
+    use super::Counters;
+
+    // Pretend there is a steady stream of traffic on every interface
+    pub fn read(interface: &str) -> Result<Counters> {
+        let secs = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let bytes_per_sec = if interface == "uplink" {
+            125_000
+        } else {
+            12_500
+        };
+
+        Ok(Counters {
+            rx_bytes: secs * bytes_per_sec,
+            tx_bytes: secs * bytes_per_sec / 4,
+            rx_packets: secs * bytes_per_sec / 1000,
+            tx_packets: secs * bytes_per_sec / 4000,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(not(feature = "demo_mode"))]
+mod counters {
+    use std::fs::read_to_string;
+    use std::io::{Error, ErrorKind, Result};
+
+    use super::Counters;
+
+    fn read_counter(interface: &str, name: &str) -> Result<u64> {
+        let path = format!("/sys/class/net/{interface}/{name}");
+
+        read_to_string(path)?
+            .trim()
+            .parse()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    pub fn read(interface: &str) -> Result<Counters> {
+        let stat = |name| read_counter(interface, &format!("statistics/{name}"));
+
+        Ok(Counters {
+            rx_bytes: stat("rx_bytes")?,
+            tx_bytes: stat("tx_bytes")?,
+            rx_packets: stat("rx_packets")?,
+            tx_packets: stat("tx_packets")?,
+            rx_errors: stat("rx_errors")?,
+            tx_errors: stat("tx_errors")?,
+            rx_dropped: stat("rx_dropped")?,
+            tx_dropped: stat("tx_dropped")?,
+            carrier_down_count: read_counter(interface, "carrier_down_count")?,
+            carrier_changes: read_counter(interface, "carrier_changes")?,
+        })
+    }
+}
+
+use counters::read;
+
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+const HISTORY_LENGTH: usize = 200;
+
+// The rates are averaged over this many update intervals
+const RATE_WINDOW: usize = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+    /// The number of times the link went down (a.k.a. link flaps)
+    pub carrier_down_count: u64,
+    /// The number of times the link went up or down
+    pub carrier_changes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinkStatistics {
+    #[serde(flatten)]
+    pub counters: Counters,
+    /// The last time the link went up or down since the tacd was started
+    pub last_change: Option<Timestamp>,
+}
+
+/// The number of bytes transferred between two samples of a counter
+///
+/// The counters are `unsigned long`s in the kernel, so they wrap around at
+/// 2^32 on 32 bit systems like the TAC.
+/// They are also reset to zero when the interface is re-created.
+/// A counter that went down from the upper half of the 32 bit range is
+/// assumed to have wrapped around, otherwise it is assumed to have been
+/// reset and only the bytes transferred since are counted.
+fn counter_delta(old: u64, new: u64) -> u64 {
+    const WRAP: u64 = 1 << 32;
+
+    if new >= old {
+        new - old
+    } else if (WRAP / 2..WRAP).contains(&old) {
+        WRAP - old + new
+    } else {
+        new
+    }
+}
+
+/// Averages the rx/tx rates over the last RATE_WINDOW update intervals
+#[derive(Default)]
+struct RateWindow {
+    samples: VecDeque<(Instant, Counters)>,
+}
+
+impl RateWindow {
+    fn last(&self) -> Option<&Counters> {
+        self.samples.back().map(|(_, counters)| counters)
+    }
+
+    /// Add a new sample and get the rx and tx rates in bytes per second
+    fn push(&mut self, ts: Instant, counters: Counters) -> Option<(f32, f32)> {
+        self.samples.push_back((ts, counters));
+
+        if self.samples.len() > RATE_WINDOW + 1 {
+            self.samples.pop_front();
+        }
+
+        let (start, _) = self.samples.front()?;
+        let (end, _) = self.samples.back()?;
+        let secs = end.duration_since(*start).as_secs_f32();
+
+        if secs <= 0.0 {
+            return None;
+        }
+
+        // Sum up the individual intervals, so that a wrap or reset only
+        // affects the interval it occurred in.
+        let pairs = self.samples.iter().zip(self.samples.iter().skip(1));
+        let (rx, tx) = pairs.fold((0, 0), |(rx, tx), ((_, old), (_, new))| {
+            (
+                rx + counter_delta(old.rx_bytes, new.rx_bytes),
+                tx + counter_delta(old.tx_bytes, new.tx_bytes),
+            )
+        });
+
+        Some((rx as f32 / secs, tx as f32 / secs))
+    }
+}
+
+/// Periodically read the counters of an interface and calculate the rates
+fn setup_interface(bb: &mut BrokerBuilder, interface: &'static str) {
+    let base = format!("/v1/tac/network/statistics/{interface}");
+
+    let counters = bb.topic_ro(&base, None);
+    let rx_rate = bb.topic(
+        &format!("{base}/rx_rate"),
+        true,
+        false,
+        false,
+        None,
+        HISTORY_LENGTH,
+    );
+    let tx_rate = bb.topic(
+        &format!("{base}/tx_rate"),
+        true,
+        false,
+        false,
+        None,
+        HISTORY_LENGTH,
+    );
+
+    spawn(async move {
+        let mut window = RateWindow::default();
+        let mut last_change = None;
+        let mut failing = false;
+
+        loop {
+            sleep(UPDATE_INTERVAL).await;
+
+            let now = match read(interface) {
+                Ok(now) => {
+                    failing = false;
+                    now
+                }
+                Err(e) => {
+                    if !failing {
+                        warn!("Failed to read statistics of {interface}: {e}");
+                        failing = true;
+                    }
+                    continue;
+                }
+            };
+
+            if let Some(prev) = window.last() {
+                if prev.carrier_changes != now.carrier_changes {
+                    last_change = Some(Timestamp::now());
+                }
+            }
+
+            if let Some((rx, tx)) = window.push(Instant::now(), now) {
+                rx_rate.set(Measurement::now(rx));
+                tx_rate.set(Measurement::now(tx));
+            }
+
+            counters.set(LinkStatistics {
+                counters: now,
+                last_change,
+            });
+        }
+    });
+}
+
+/// Provide statistics for the uplink and DUT network interfaces
+///
+/// These are read from sysfs and work regardless of whether the interfaces
+/// are managed by NetworkManager or not.
+pub fn setup(bb: &mut BrokerBuilder) {
+    for interface in ["dut", "uplink"] {
+        setup_interface(bb, interface);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Counters, RateWindow, RATE_WINDOW};
+
+    fn sample(rx_bytes: u64, tx_bytes: u64) -> Counters {
+        Counters {
+            rx_bytes,
+            tx_bytes,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rates() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        let mut window = RateWindow::default();
+
+        // A single sample is not enough to calculate a rate
+        assert_eq!(window.push(at(0), sample(0, 0)), None);
+        assert_eq!(window.push(at(1), sample(1000, 100)), Some((1000.0, 100.0)));
+
+        // The rate is averaged over the window
+        assert_eq!(window.push(at(2), sample(4000, 200)), Some((2000.0, 100.0)));
+
+        for secs in 3..=RATE_WINDOW as u64 + 2 {
+            window.push(at(secs), sample(2000 + secs * 1000, secs * 100));
+        }
+
+        // The window slid past the initial burst
+        assert_eq!(window.last(), Some(&sample(9000, 700)));
+        assert_eq!(
+            window.push(at(8), sample(10000, 800)),
+            Some((1000.0, 100.0))
+        );
+
+        // The interface was re-created and the counters were reset.
+        // Only the bytes transferred since the reset are counted.
+        let mut window = RateWindow::default();
+        window.push(at(0), sample(1000, 1000));
+        assert_eq!(window.push(at(1), sample(500, 0)), Some((500.0, 0.0)));
+
+        // 32 bit counters wrap around
+        let mut window = RateWindow::default();
+        let near_wrap = (1 << 32) - 1000;
+        window.push(at(0), sample(near_wrap, near_wrap));
+        assert_eq!(window.push(at(1), sample(1000, 0)), Some((2000.0, 1000.0)));
+    }
+}
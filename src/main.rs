@@ -15,52 +15,114 @@
 // with this program; if not, write to the Free Software Foundation, Inc.,
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 
-use async_std::future::pending;
+use std::time::Duration;
+
+use async_std::channel::{bounded, Receiver};
+use async_std::future::{pending, timeout};
+use async_std::task::block_on;
 use futures::{select, FutureExt};
-use log::{error, info};
+use log::{error, info, warn};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
 
 mod adc;
 mod backlight;
 mod broker;
+mod config;
 mod dbus;
+mod diagnostics;
 mod digital_io;
+mod dut_hosts;
 mod dut_power;
 mod http_server;
 mod iobus;
 mod journal;
 mod led;
+mod link_statistics;
 mod measurement;
+mod metrics;
 mod regulators;
+mod rules;
+mod settings;
 mod setup_mode;
 mod system;
 mod temperatures;
+mod thermal_protection;
+mod time_of_day;
 mod ui;
 mod usb_hub;
 mod watchdog;
+mod webhooks;
 
 use adc::Adc;
 use backlight::Backlight;
-use broker::BrokerBuilder;
+use broker::{BrokerBuilder, Persistence};
+use config::Config;
 use dbus::DbusSession;
+use diagnostics::Diagnostics;
 use digital_io::DigitalIo;
 use dut_power::DutPwrThread;
 use http_server::HttpServer;
 use iobus::IoBus;
 use led::Led;
 use regulators::Regulators;
+use rules::Rules;
 use setup_mode::SetupMode;
 use system::System;
 use temperatures::Temperatures;
+use thermal_protection::ThermalProtection;
 use ui::{message, setup_display, Display, Ui, UiResources};
 use usb_hub::UsbHub;
 use watchdog::Watchdog;
+use webhooks::{Deliveries, Webhooks};
+
+// Queued webhook deliveries get this long to finish when the tacd is stopped,
+// which is well below the time systemd waits before killing it.
+const WEBHOOK_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The parts of the tacd that need to finish their work before it exits
+struct Shutdown {
+    persistence: Persistence,
+    webhooks: Deliveries,
+}
 
-async fn init() -> anyhow::Result<(Ui, HttpServer, Option<Watchdog>)> {
+impl Shutdown {
+    async fn run(self) {
+        self.persistence.shutdown().await;
+
+        if timeout(WEBHOOK_DRAIN_TIMEOUT, self.webhooks.shutdown())
+            .await
+            .is_err()
+        {
+            warn!("Gave up waiting for queued webhook deliveries");
+        }
+    }
+}
+
+/// Get notified about SIGTERM and SIGINT, which are used to stop the tacd
+fn stop_signals() -> Result<Receiver<i32>, std::io::Error> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    let (tx, rx) = bounded(1);
+
+    std::thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            let _ = block_on(tx.send(signal));
+        }
+    });
+
+    Ok(rx)
+}
+
+async fn init(config: Config) -> anyhow::Result<(Ui, HttpServer, Option<Watchdog>, Shutdown)> {
     // The BrokerBuilder collects topics that should be exported via the
     // MQTT/REST APIs.
     // The topics are also used to pass around data inside the tacd.
     let mut bb = BrokerBuilder::new();
 
+    // Let API users see which configuration the tacd ended up using
+    // (e.g. the defaults for settings missing in the config file).
+    bb.topic_ro("/v1/tac/config", Some(config.clone()));
+
     // Expose hardware on the TAC via the broker framework.
     let backlight = Backlight::new(&mut bb).unwrap();
     let led = Led::new(&mut bb);
@@ -77,46 +139,100 @@ async fn init() -> anyhow::Result<(Ui, HttpServer, Option<Watchdog>)> {
     let temperatures = Temperatures::new(&mut bb);
     let usb_hub = UsbHub::new(
         &mut bb,
+        &config.usb_hub,
         adc.usb_host_curr.fast.clone(),
         adc.usb_host1_curr.fast.clone(),
         adc.usb_host2_curr.fast.clone(),
         adc.usb_host3_curr.fast.clone(),
     );
 
+    // Switch off loads if the TAC stays critically hot for too long.
+    let thermal_protection = ThermalProtection::new(
+        &mut bb,
+        temperatures.warning.clone(),
+        dut_pwr.thermal_lock.clone(),
+        dut_pwr.state.clone(),
+        [
+            usb_hub.port1.clone(),
+            usb_hub.port2.clone(),
+            usb_hub.port3.clone(),
+        ],
+        regulators.iobus_pwr_en.clone(),
+    );
+
+    // Set up a http server and provide some static files like the web
+    // interface and config files that may be edited inside the web ui.
+    let mut http_server = HttpServer::new(&config.http);
+
+    // Allow editing some aspects of the TAC configuration when in "setup mode".
+    let setup_mode = SetupMode::new(&mut bb, &mut http_server.server);
+    let setup_mode_topic = setup_mode.setup_mode.clone();
+
     // Expose other software on the TAC via the broker framework by connecting
     // to them via HTTP / DBus APIs.
     let iobus = IoBus::new(
         &mut bb,
+        &config.iobus,
         regulators.iobus_pwr_en.clone(),
         adc.iobus_curr.fast.clone(),
         adc.iobus_volt.fast.clone(),
     );
     let (network, rauc, systemd) = {
-        let dbus = DbusSession::new(&mut bb, led.eth_dut.clone(), led.eth_lab.clone()).await;
+        let dbus = DbusSession::new(
+            &mut bb,
+            led.eth_dut.clone(),
+            led.eth_lab.clone(),
+            setup_mode.setup_mode.clone(),
+            &config.update,
+            &config.services,
+        )
+        .await;
 
         (dbus.network, dbus.rauc, dbus.systemd)
     };
 
+    // Install updates from the update channels according to the per-channel
+    // policies. Needs to know about the DUT power state to not disturb
+    // running tests.
+    rauc.auto_update(&mut bb, systemd.reboot.clone(), dut_pwr.state.clone());
+
+    // Allow installing bundles that are uploaded via the web interface,
+    // for TACs that can not reach an update server themselves.
+    rauc.serve_upload(&mut bb, &mut http_server.server, &config.update);
+
     // Expose information about the system provided by the kernel via the
     // broker framework.
-    let system = System::new(&mut bb);
+    let system = System::new(&mut bb, &config.http, &config.persistence);
+
+    // Observe which hosts are connected to the DUT port and publish their
+    // MAC and IP addresses.
+    dut_hosts::setup(&mut bb);
+
+    // Provide traffic counters and rates for the uplink and DUT network
+    // interfaces.
+    link_statistics::setup(&mut bb);
 
     // Make sure the ADC and power switching threads of the tacd are not
     // stalled for too long by providing watchdog events to systemd
     // (if requested on start).
     let watchdog = Watchdog::new(dut_pwr.tick());
 
-    // Set up a http server and provide some static files like the web
-    // interface and config files that may be edited inside the web ui.
-    let mut http_server = HttpServer::new();
-
-    // Allow editing some aspects of the TAC configuration when in "setup mode".
-    let setup_mode = SetupMode::new(&mut bb, &mut http_server.server);
-
     // Expose a live log of the TAC's systemd journal so it can be viewed
     // in the web interface.
     journal::serve(&mut http_server.server);
 
+    // Allow collecting the state of the TAC into an archive, e.g. to attach
+    // it to bug reports. The archives are served once the broker is built.
+    let diagnostics = Diagnostics::new(&config.persistence);
+
+    // Glue topics together using the rules from the config file,
+    // e.g. to switch off a USB port when the DUT is switched off.
+    // The rules are started once the broker is built.
+    let rules = Rules::new(&mut bb, &config.rules);
+
+    // Notify other systems, like a CI, about changes of topics via HTTP.
+    let webhooks = Webhooks::new(&mut bb, &config.webhooks, network.hostname.clone());
+
     // Set up the user interface for the hardware display on the TAC.
     // The different screens receive updates via the topics provided in
     // the UiResources struct.
@@ -124,6 +240,7 @@ async fn init() -> anyhow::Result<(Ui, HttpServer, Option<Watchdog>)> {
         let resources = UiResources {
             adc,
             backlight,
+            diagnostics: diagnostics.clone(),
             dig_io,
             dut_pwr,
             iobus,
@@ -135,6 +252,7 @@ async fn init() -> anyhow::Result<(Ui, HttpServer, Option<Watchdog>)> {
             system,
             systemd,
             temperatures,
+            thermal_protection,
             usb_hub,
         };
 
@@ -143,36 +261,70 @@ async fn init() -> anyhow::Result<(Ui, HttpServer, Option<Watchdog>)> {
 
     // Consume the BrokerBuilder (no further topics can be added or removed)
     // and expose the topics via HTTP and MQTT-over-websocket.
-    bb.build(&mut http_server.server);
+    let (topics, persistence) = bb.build(&mut http_server.server, &config.persistence);
 
-    Ok((ui, http_server, watchdog))
+    diagnostics.serve(&mut http_server.server, topics.clone());
+    rules.run(&topics);
+    let webhooks = webhooks.run(&topics);
+
+    // Let Prometheus scrape measurements and states from /metrics
+    metrics::serve(&mut http_server.server, topics.clone());
+
+    // Allow exporting the settings of one TAC and importing them into
+    // others (in setup mode).
+    settings::serve(&mut http_server.server, topics, setup_mode_topic);
+
+    let shutdown = Shutdown {
+        persistence,
+        webhooks,
+    };
+
+    Ok((ui, http_server, watchdog, shutdown))
 }
 
 async fn run(
     ui: Ui,
     mut http_server: HttpServer,
     watchdog: Option<Watchdog>,
+    shutdown: Shutdown,
     display: Display,
 ) -> Result<(), std::io::Error> {
     // Expose the display as a .png on the web server
     ui::serve_display(&mut http_server.server, display.screenshooter());
 
+    let stop_signals = stop_signals()?;
+
+    let stopped = async {
+        if let Ok(signal) = stop_signals.recv().await {
+            info!("Received signal {signal}. Shutting down");
+        }
+
+        Ok(())
+    };
+
     info!("Setup complete. Handling requests");
 
     // Run until the user interface, http server or (if selected) the watchdog
-    // exits (with an error).
-    if let Some(watchdog) = watchdog {
+    // exits (with an error) or the tacd is asked to stop.
+    let res = if let Some(watchdog) = watchdog {
         select! {
             ui_err = ui.run(display).fuse() => ui_err,
             wi_err = http_server.serve().fuse() => wi_err,
             wd_err = watchdog.keep_fed().fuse() => wd_err,
+            stop = stopped.fuse() => stop,
         }
     } else {
         select! {
             ui_err = ui.run(display).fuse() => ui_err,
             wi_err = http_server.serve().fuse() => wi_err,
+            stop = stopped.fuse() => stop,
         }
-    }
+    };
+
+    // Save the persistent topics and deliver queued webhooks before exiting
+    shutdown.run().await;
+
+    res
 }
 
 #[async_std::main]
@@ -182,18 +334,32 @@ async fn main() -> Result<(), std::io::Error> {
     // Show a splash screen very early on
     let display = setup_display();
 
-    match init().await {
-        Ok((ui, http_server, watchdog)) => run(ui, http_server, watchdog, display).await,
-        Err(e) => {
+    let res = match Config::load() {
+        Ok(config) => init(config).await.map_err(|e| {
             // Display a detailed error message on stderr (and thus the journal) ...
             error!("Failed to initialize tacd: {e}");
 
             // ... and a generic message on the LCD, as it can not fit a lot of detail.
+            "tacd failed to start.".to_string()
+        }),
+        Err(e) => {
+            error!("Failed to load the configuration: {e}");
+
+            // Tell the user what to look for in the config file
+            Err(format!("Invalid tacd.yaml:\n{}", e.summary))
+        }
+    };
+
+    match res {
+        Ok((ui, http_server, watchdog, shutdown)) => {
+            run(ui, http_server, watchdog, shutdown, display).await
+        }
+        Err(msg) => {
             display.clear();
             display.with_lock(|target| {
                 message(
                     target,
-                    "tacd failed to start.\nCheck log for info.\nWaiting for watchdog.",
+                    &format!("{msg}\nCheck log for info.\nWaiting for watchdog."),
                 );
             });
 
@@ -0,0 +1,326 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use async_std::channel::unbounded;
+use async_std::prelude::*;
+use async_std::sync::Arc;
+use async_std::task::spawn;
+use serde_json::{Map, Value};
+use tide::{Response, Server};
+
+use crate::broker::AnyTopic;
+
+// Rust enums are serialized as their variant names, like "On" or
+// "OverCurrent". Only strings like these are exported as states, to keep
+// free form text like error messages out of the label values.
+const MAX_STATE_LEN: usize = 32;
+
+fn is_state(value: &str) -> bool {
+    let mut chars = value.chars();
+
+    value.len() <= MAX_STATE_LEN
+        && chars.next().is_some_and(|c| c.is_ascii_uppercase())
+        && chars.all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Get the value of a Measurement, which is serialized as {"ts": …, "value": …}
+fn measurement(value: &Value) -> Option<f64> {
+    let obj = value.as_object()?;
+
+    match obj.len() == 2 && obj.contains_key("ts") {
+        true => obj.get("value")?.as_f64(),
+        false => None,
+    }
+}
+
+/// Get the fields of events like button presses, which only consist of states
+fn event_labels(value: &Value) -> Option<&Map<String, Value>> {
+    let obj = value.as_object().filter(|obj| !obj.is_empty())?;
+
+    obj.values()
+        .all(|v| v.as_str().is_some_and(is_state))
+        .then_some(obj)
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Render a label set like {topic="/v1/dut/powered",state="On"}
+fn labels<'a>(pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    let inner: Vec<String> = pairs
+        .into_iter()
+        .map(|(k, v)| format!("{k}=\"{}\"", escape(v)))
+        .collect();
+
+    format!("{{{}}}", inner.join(","))
+}
+
+/// Counters derived from the stream of topic updates
+#[derive(Default)]
+struct Counters {
+    /// The last value of each topic, to only count actual changes
+    last: BTreeMap<String, Value>,
+    /// How often each topic entered each state
+    state_changes: BTreeMap<(String, String), u64>,
+    /// How often each event occurred, by topic and rendered labels
+    events: BTreeMap<(String, String), u64>,
+}
+
+impl Counters {
+    /// Start counting from the values the topics currently have
+    fn new(topics: &[Arc<dyn AnyTopic>]) -> Self {
+        let last = topics
+            .iter()
+            .filter_map(|t| t.try_get_json_value().map(|v| (t.path().to_string(), v)))
+            .collect();
+
+        Self {
+            last,
+            ..Default::default()
+        }
+    }
+
+    /// Handle an update of a topic
+    fn update(&mut self, topic: &str, value: Value) {
+        let prev = self.last.insert(topic.to_string(), value.clone());
+
+        if let Some(state) = value.as_str().filter(|s| is_state(s)) {
+            if prev.as_ref() != Some(&value) {
+                let key = (topic.to_string(), state.to_string());
+                *self.state_changes.entry(key).or_default() += 1;
+            }
+        }
+
+        if let Some(fields) = event_labels(&value) {
+            let pairs = fields
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str().unwrap()));
+            let key = (topic.to_string(), labels(pairs));
+            *self.events.entry(key).or_default() += 1;
+        }
+    }
+}
+
+/// Render the current topic values and counters in the Prometheus text format
+fn render(topics: &[Arc<dyn AnyTopic>], counters: &Counters) -> String {
+    let mut measurements = String::new();
+    let mut booleans = String::new();
+    let mut states = String::new();
+
+    for topic in topics.iter().filter(|t| t.web_readable()) {
+        let path: &str = topic.path();
+
+        let value = match topic.try_get_json_value() {
+            Some(value) => value,
+            None => continue,
+        };
+
+        if let Some(m) = measurement(&value) {
+            let _ = writeln!(
+                measurements,
+                "tacd_measurement{} {m}",
+                labels([("topic", path)])
+            );
+        } else if let Some(b) = value.as_bool() {
+            let _ = writeln!(
+                booleans,
+                "tacd_boolean{} {}",
+                labels([("topic", path)]),
+                b as u8
+            );
+        } else if let Some(state) = value.as_str().filter(|s| is_state(s)) {
+            let l = labels([("topic", path), ("state", state)]);
+            let _ = writeln!(states, "tacd_state{l} 1");
+        }
+    }
+
+    let mut out = String::new();
+
+    let mut section = |name: &str, kind: &str, help: &str, body: &str| {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        out.push_str(body);
+    };
+
+    section(
+        "tacd_measurement",
+        "gauge",
+        "The most recent value of a measurement topic",
+        &measurements,
+    );
+    section(
+        "tacd_boolean",
+        "gauge",
+        "The value of a boolean topic (0 or 1)",
+        &booleans,
+    );
+    section(
+        "tacd_state",
+        "gauge",
+        "The current state of a topic with enumerated states",
+        &states,
+    );
+
+    let mut state_changes = String::new();
+
+    for ((topic, state), count) in counters.state_changes.iter() {
+        let l = labels([("topic", topic.as_str()), ("state", state.as_str())]);
+        let _ = writeln!(state_changes, "tacd_state_changes_total{l} {count}");
+    }
+
+    section(
+        "tacd_state_changes_total",
+        "counter",
+        "How often a topic entered a state, e.g. OverCurrent for /v1/dut/powered",
+        &state_changes,
+    );
+
+    let mut events = String::new();
+
+    for ((topic, event_labels), count) in counters.events.iter() {
+        // Merge the topic into the labels of the event
+        let l = format!(
+            "{{topic=\"{}\",{}",
+            escape(topic),
+            event_labels.trim_start_matches('{')
+        );
+        let _ = writeln!(events, "tacd_events_total{l} {count}");
+    }
+
+    section(
+        "tacd_events_total",
+        "counter",
+        "How often an event like a button press occurred",
+        &events,
+    );
+
+    out
+}
+
+/// Expose measurements and states for scraping by Prometheus
+///
+/// This has to happen after the broker is built, as all readable topics
+/// are exported generically.
+pub fn serve(server: &mut Server<()>, topics: Arc<Vec<Arc<dyn AnyTopic>>>) {
+    let readable: Vec<_> = topics
+        .iter()
+        .filter(|t| t.web_readable())
+        .cloned()
+        .collect();
+
+    let counters = Arc::new(Mutex::new(Counters::new(&readable)));
+
+    let (tx, mut rx) = unbounded();
+
+    let handles: Vec<_> = readable
+        .into_iter()
+        .map(|t| t.subscribe_as_bytes(tx.clone(), false))
+        .collect();
+
+    let counters_task = counters.clone();
+
+    spawn(async move {
+        // Keep the subscriptions for as long as the counters are updated
+        let _handles = handles;
+
+        while let Some((topic, msg)) = rx.next().await {
+            if let Ok(value) = serde_json::from_slice(&msg) {
+                let path: &str = &topic;
+                counters_task.lock().unwrap().update(path, value);
+            }
+        }
+    });
+
+    server.at("/metrics").get(move |_req| {
+        let topics = topics.clone();
+        let counters = counters.clone();
+
+        async move {
+            let body = render(&topics, &counters.lock().unwrap());
+
+            Ok(Response::builder(200)
+                .body(body)
+                .content_type("text/plain; version=0.0.4")
+                .build())
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::sync::Arc;
+    use serde_json::json;
+
+    use super::{render, Counters};
+    use crate::broker::{AnyTopic, BrokerBuilder};
+    use crate::measurement::Measurement;
+
+    #[test]
+    fn exposition() {
+        let mut bb = BrokerBuilder::new();
+
+        let current = bb.topic_ro("/v1/dut/feedback/current", Some(Measurement::now(0.5)));
+        let asserted = bb.topic_rw("/v1/output/out_0/asserted", Some(true));
+        let powered = bb.topic_ro("/v1/dut/powered", Some("On".to_string()));
+        let hostname = bb.topic_ro("/v1/tac/network/hostname", Some("lxatac".to_string()));
+        let hidden = bb.topic("/v1/hidden", false, true, false, Some(true), 1);
+
+        let topics: Vec<Arc<dyn AnyTopic>> = vec![current, asserted, powered, hostname, hidden];
+
+        let mut counters = Counters::new(&topics);
+
+        counters.update("/v1/dut/powered", json!("Off"));
+        counters.update("/v1/dut/powered", json!("On"));
+        counters.update("/v1/dut/powered", json!("On"));
+        counters.update("/v1/dut/powered", json!("OverCurrent"));
+        counters.update("/v1/dut/powered", json!("On"));
+
+        let press = json!({"dir": "Press", "btn": "Upper", "dur": "Short"});
+        counters.update("/v1/tac/display/buttons", press.clone());
+        counters.update("/v1/tac/display/buttons", press);
+
+        assert_eq!(
+            render(&topics, &counters),
+            "# HELP tacd_measurement The most recent value of a measurement topic
+# TYPE tacd_measurement gauge
+tacd_measurement{topic=\"/v1/dut/feedback/current\"} 0.5
+# HELP tacd_boolean The value of a boolean topic (0 or 1)
+# TYPE tacd_boolean gauge
+tacd_boolean{topic=\"/v1/output/out_0/asserted\"} 1
+# HELP tacd_state The current state of a topic with enumerated states
+# TYPE tacd_state gauge
+tacd_state{topic=\"/v1/dut/powered\",state=\"On\"} 1
+# HELP tacd_state_changes_total How often a topic entered a state, e.g. OverCurrent for /v1/dut/powered
+# TYPE tacd_state_changes_total counter
+tacd_state_changes_total{topic=\"/v1/dut/powered\",state=\"Off\"} 1
+tacd_state_changes_total{topic=\"/v1/dut/powered\",state=\"On\"} 2
+tacd_state_changes_total{topic=\"/v1/dut/powered\",state=\"OverCurrent\"} 1
+# HELP tacd_events_total How often an event like a button press occurred
+# TYPE tacd_events_total counter
+tacd_events_total{topic=\"/v1/tac/display/buttons\",btn=\"Upper\",dir=\"Press\",dur=\"Short\"} 2
+"
+        );
+    }
+}
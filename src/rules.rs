@@ -0,0 +1,503 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use async_std::channel::unbounded;
+use async_std::future::timeout;
+use async_std::prelude::*;
+use async_std::sync::Arc;
+use async_std::task::spawn;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::broker::{AnyTopic, BrokerBuilder, Topic};
+use crate::config::{RetryConfig, RuleAction, RuleCondition, RuleConfig};
+use crate::measurement::Timestamp;
+use crate::webhooks::deliver;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RuleStatus {
+    pub name: String,
+    /// The condition holds and the actions ran
+    pub active: bool,
+    /// How often the actions ran since the tacd started
+    pub triggered: u64,
+    pub last_triggered: Option<Timestamp>,
+    /// Why the rule could not be set up (e.g. an unknown topic)
+    pub error: Option<String>,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+enum Transition {
+    Activate,
+    Release,
+}
+
+/// The part of a rule that decides when to run actions
+struct Evaluator {
+    condition: RuleCondition,
+    field: Option<String>,
+    hold_time: Duration,
+    active: bool,
+    /// When the condition became true, if the actions did not run yet
+    since: Option<Instant>,
+    last: Option<Value>,
+}
+
+/// Compare numbers by value, so that e.g. 1 and 1.0 are equal
+fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+impl Evaluator {
+    fn new(rule: &RuleConfig) -> Self {
+        Self {
+            condition: rule.condition.clone(),
+            field: rule.field.clone(),
+            hold_time: rule.hold_time(),
+            active: false,
+            since: None,
+            last: None,
+        }
+    }
+
+    fn matches(&self, value: &Value) -> bool {
+        match &self.condition {
+            RuleCondition::Equals(expected) => values_equal(value, expected),
+            RuleCondition::NotEquals(expected) => !values_equal(value, expected),
+            RuleCondition::Above(threshold) => value.as_f64().is_some_and(|v| v > *threshold),
+            RuleCondition::Below(threshold) => value.as_f64().is_some_and(|v| v < *threshold),
+            RuleCondition::Changed => false,
+        }
+    }
+
+    /// Handle a new value of the watched topic
+    fn update(&mut self, value: &Value, now: Instant) -> Option<Transition> {
+        let value = match &self.field {
+            Some(field) => value.pointer(field).cloned().unwrap_or(Value::Null),
+            None => value.clone(),
+        };
+
+        if let RuleCondition::Changed = self.condition {
+            let changed = self
+                .last
+                .as_ref()
+                .is_some_and(|last| !values_equal(last, &value));
+
+            self.last = Some(value);
+
+            return changed.then_some(Transition::Activate);
+        }
+
+        if !self.matches(&value) {
+            self.since = None;
+
+            return match self.active {
+                true => {
+                    self.active = false;
+                    Some(Transition::Release)
+                }
+                false => None,
+            };
+        }
+
+        if !self.active && self.since.is_none() {
+            self.since = Some(now);
+        }
+
+        self.poll(now)
+    }
+
+    /// When to call poll() next
+    fn deadline(&self) -> Option<Instant> {
+        self.since.map(|since| since + self.hold_time)
+    }
+
+    /// Check if the condition held for long enough
+    fn poll(&mut self, now: Instant) -> Option<Transition> {
+        match self.deadline() {
+            Some(deadline) if deadline <= now => {
+                self.since = None;
+                self.active = true;
+                Some(Transition::Activate)
+            }
+            _ => None,
+        }
+    }
+}
+
+enum Action {
+    Set(Arc<dyn AnyTopic>, Value),
+    Webhook(String),
+}
+
+impl Action {
+    fn resolve(topics: &[Arc<dyn AnyTopic>], action: &RuleAction) -> Result<Self> {
+        match action {
+            RuleAction::Set { topic, value } => {
+                let target = find_topic(topics, topic, |t| t.web_writable())
+                    .ok_or_else(|| anyhow!("There is no writable topic {topic}"))?;
+
+                target
+                    .check_json_value(value)
+                    .map_err(|e| anyhow!("Invalid value for topic {topic}: {e}"))?;
+
+                Ok(Self::Set(target, value.clone()))
+            }
+            RuleAction::Webhook { url } => Ok(Self::Webhook(url.clone())),
+        }
+    }
+
+    fn run(&self, rule: &str, topic: &str, value: &Value, active: bool) {
+        match self {
+            Self::Set(target, new) => {
+                if let Err(e) = target.set_from_json_value(new.clone()) {
+                    let path: &str = target.path();
+                    warn!("Rule {rule} failed to set {path}: {e}");
+                }
+            }
+            Self::Webhook(url) => {
+                let url = url.clone();
+                let rule = rule.to_string();
+                let body = json!({
+                    "rule": rule,
+                    "topic": topic,
+                    "value": value,
+                    "active": active,
+                });
+
+                spawn(async move {
+                    if let Err(e) = deliver(&url, &body, &RetryConfig::default()).await {
+                        warn!("Rule {rule} failed to deliver webhook: {e}");
+                    }
+                });
+            }
+        }
+    }
+}
+
+fn find_topic(
+    topics: &[Arc<dyn AnyTopic>],
+    path: &str,
+    filter: impl Fn(&Arc<dyn AnyTopic>) -> bool,
+) -> Option<Arc<dyn AnyTopic>> {
+    topics
+        .iter()
+        .find(|t| {
+            let topic_path: &str = t.path();
+            topic_path == path && filter(t)
+        })
+        .cloned()
+}
+
+/// A rule with all topics looked up
+struct Rule {
+    index: usize,
+    config: RuleConfig,
+    watched: Arc<dyn AnyTopic>,
+    actions: Vec<Action>,
+    release_actions: Vec<Action>,
+}
+
+impl Rule {
+    fn resolve(topics: &[Arc<dyn AnyTopic>], index: usize, config: &RuleConfig) -> Result<Self> {
+        // Topics that are readable via the API are the ones the rules are
+        // meant to be written against. If there is a read only and a write
+        // only topic with the same path the read only one is the state.
+        let watched = find_topic(topics, &config.topic, |t| t.web_readable())
+            .ok_or_else(|| anyhow!("There is no readable topic {}", config.topic))?;
+
+        let resolve_all = |actions: &[RuleAction]| -> Result<Vec<Action>> {
+            actions.iter().map(|a| Action::resolve(topics, a)).collect()
+        };
+
+        Ok(Self {
+            index,
+            config: config.clone(),
+            watched,
+            actions: resolve_all(&config.actions)?,
+            release_actions: resolve_all(&config.release_actions)?,
+        })
+    }
+
+    async fn run(self, status: Arc<Topic<Vec<RuleStatus>>>) {
+        let (tx, mut rx) = unbounded();
+        let _handle = self.watched.clone().subscribe_as_bytes(tx, true);
+
+        let mut evaluator = Evaluator::new(&self.config);
+        let mut value = Value::Null;
+
+        loop {
+            // Wake up either on a new value or once the condition held for
+            // long enough.
+            let msg = match evaluator.deadline() {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+
+                    match timeout(remaining, rx.next()).await {
+                        Ok(Some((_, msg))) => Some(msg),
+                        Ok(None) => break,
+                        Err(_) => None,
+                    }
+                }
+                None => match rx.next().await {
+                    Some((_, msg)) => Some(msg),
+                    None => break,
+                },
+            };
+
+            let transition = match msg {
+                Some(msg) => match serde_json::from_slice(&msg) {
+                    Ok(new) => {
+                        value = new;
+                        evaluator.update(&value, Instant::now())
+                    }
+                    Err(_) => None,
+                },
+                None => evaluator.poll(Instant::now()),
+            };
+
+            let active = match transition {
+                Some(Transition::Activate) => true,
+                Some(Transition::Release) => false,
+                None => continue,
+            };
+
+            let name = &self.config.name;
+            let topic = &self.config.topic;
+
+            info!(
+                "Rule {name} {}",
+                if active { "triggered" } else { "released" }
+            );
+
+            let actions = match active {
+                true => &self.actions,
+                false => &self.release_actions,
+            };
+
+            for action in actions {
+                action.run(name, topic, &value, active);
+            }
+
+            let index = self.index;
+
+            status.modify(|prev| {
+                let mut list = prev?;
+                let entry = list.get_mut(index)?;
+
+                entry.active = active;
+
+                if active {
+                    entry.triggered += 1;
+                    entry.last_triggered = Some(Timestamp::now());
+                }
+
+                Some(list)
+            });
+        }
+    }
+}
+
+/// Glue topics together using the rules from the config file
+pub struct Rules {
+    status: Arc<Topic<Vec<RuleStatus>>>,
+    rules: Vec<RuleConfig>,
+}
+
+impl Rules {
+    pub fn new(bb: &mut BrokerBuilder, rules: &[RuleConfig]) -> Self {
+        let initial = rules
+            .iter()
+            .map(|rule| RuleStatus {
+                name: rule.name.clone(),
+                active: false,
+                triggered: 0,
+                last_triggered: None,
+                error: None,
+            })
+            .collect();
+
+        Self {
+            status: bb.topic_ro("/v1/tac/rules", Some(initial)),
+            rules: rules.to_vec(),
+        }
+    }
+
+    /// Start watching the topics
+    ///
+    /// This has to happen after the broker is built, as rules can refer to
+    /// any topic.
+    pub fn run(self, topics: &[Arc<dyn AnyTopic>]) {
+        for (index, config) in self.rules.iter().enumerate() {
+            match Rule::resolve(topics, index, config) {
+                Ok(rule) => {
+                    spawn(rule.run(self.status.clone()));
+                }
+                Err(e) => {
+                    warn!("Rule {} is disabled: {e}", config.name);
+
+                    self.status.modify(|prev| {
+                        let mut list = prev?;
+                        list.get_mut(index)?.error = Some(e.to_string());
+                        Some(list)
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use async_std::channel::Receiver;
+    use async_std::future::timeout;
+    use async_std::prelude::*;
+    use async_std::sync::Arc;
+    use async_std::task::block_on;
+    use serde_json::json;
+
+    use super::{Evaluator, Rules, Transition};
+    use crate::broker::{AnyTopic, BrokerBuilder};
+    use crate::config::{RuleAction, RuleCondition, RuleConfig};
+
+    fn rule(condition: RuleCondition, field: Option<&str>, for_ms: u64) -> RuleConfig {
+        RuleConfig {
+            name: "test".to_string(),
+            topic: "/v1/watched".to_string(),
+            field: field.map(|f| f.to_string()),
+            condition,
+            for_ms,
+            actions: vec![RuleAction::Set {
+                topic: "/v1/target".to_string(),
+                value: json!(true),
+            }],
+            release_actions: vec![RuleAction::Set {
+                topic: "/v1/target".to_string(),
+                value: json!(false),
+            }],
+        }
+    }
+
+    /// Wait for the next value of a topic, but not forever
+    fn next<E>(events: &mut Receiver<E>) -> E {
+        block_on(timeout(Duration::from_secs(5), events.next()))
+            .expect("Timed out waiting for a topic update")
+            .unwrap()
+    }
+
+    #[test]
+    fn evaluation() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // Thresholds have to be exceeded for for_ms before the rule triggers
+        let mut eval = Evaluator::new(&rule(RuleCondition::Above(1.0), Some("/value"), 100));
+
+        assert_eq!(eval.update(&json!({"value": 0.5}), at(0)), None);
+        assert_eq!(eval.update(&json!({"value": 1.5}), at(10)), None);
+        assert_eq!(eval.deadline(), Some(at(110)));
+        assert_eq!(eval.update(&json!({"value": 0.5}), at(50)), None);
+        assert_eq!(eval.deadline(), None);
+        assert_eq!(eval.update(&json!({"value": 1.5}), at(60)), None);
+        assert_eq!(eval.poll(at(100)), None);
+        assert_eq!(eval.poll(at(160)), Some(Transition::Activate));
+        assert_eq!(eval.update(&json!({"value": 2.0}), at(200)), None);
+        assert_eq!(
+            eval.update(&json!({"value": 0.5}), at(300)),
+            Some(Transition::Release)
+        );
+
+        // Without for_ms rules trigger on the edge right away
+        let mut eval = Evaluator::new(&rule(RuleCondition::Equals(json!("Off")), None, 0));
+
+        assert_eq!(eval.update(&json!("On"), at(0)), None);
+        assert_eq!(
+            eval.update(&json!("Off"), at(1)),
+            Some(Transition::Activate)
+        );
+        assert_eq!(eval.update(&json!("Off"), at(2)), None);
+        assert_eq!(eval.update(&json!("On"), at(3)), Some(Transition::Release));
+
+        // Numbers compare by value
+        let mut eval = Evaluator::new(&rule(RuleCondition::Equals(json!(1)), None, 0));
+        assert_eq!(eval.update(&json!(1.0), at(0)), Some(Transition::Activate));
+
+        // The initial value is not a change
+        let mut eval = Evaluator::new(&rule(RuleCondition::Changed, None, 0));
+
+        assert_eq!(eval.update(&json!(1), at(0)), None);
+        assert_eq!(eval.update(&json!(1), at(1)), None);
+        assert_eq!(eval.update(&json!(2), at(2)), Some(Transition::Activate));
+        assert_eq!(eval.update(&json!(3), at(3)), Some(Transition::Activate));
+    }
+
+    #[test]
+    fn set_action() {
+        let mut bb = BrokerBuilder::new();
+
+        let watched = bb.topic_ro("/v1/watched", Some("On".to_string()));
+        let target = bb.topic_rw("/v1/target", Some(false));
+
+        let rules = vec![
+            rule(RuleCondition::Equals(json!("Off")), None, 0),
+            RuleConfig {
+                name: "broken".to_string(),
+                topic: "/v1/missing".to_string(),
+                ..rule(RuleCondition::Changed, None, 0)
+            },
+        ];
+
+        let rules = Rules::new(&mut bb, &rules);
+        let (mut status_events, _) = rules.status.clone().subscribe_unbounded();
+        let (mut target_events, _) = target.clone().subscribe_unbounded();
+
+        let topics: Vec<Arc<dyn AnyTopic>> = vec![watched.clone(), target.clone()];
+        rules.run(&topics);
+
+        assert!(!next(&mut target_events));
+
+        watched.set("Off".to_string());
+        assert!(next(&mut target_events));
+
+        // The status is updated after the actions ran
+        let status = loop {
+            let status = next(&mut status_events);
+
+            if status[0].active {
+                break status;
+            }
+        };
+
+        assert_eq!(status[0].triggered, 1);
+        assert_eq!(
+            status[1].error.as_deref(),
+            Some("There is no readable topic /v1/missing")
+        );
+
+        watched.set("On".to_string());
+        assert!(!next(&mut target_events));
+    }
+}
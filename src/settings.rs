@@ -0,0 +1,569 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_to_string, remove_file, rename, write};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use async_std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tide::http::mime;
+use tide::{Request, Response, Server};
+
+use crate::broker::{AnyTopic, Topic};
+use crate::http_server::{EXPOSED_FILES_RW, FS_PREFIX};
+use crate::setup_mode::{AUTHORIZED_KEYS_PATH, AUTHORIZED_KEYS_WEB_PATH};
+
+const FORMAT_VERSION: u64 = 1;
+
+// Importing these would have surprising side effects, like leaving the
+// setup mode in the middle of provisioning a TAC or marking a labgrid place
+// that is in use as unlocked.
+const EXCLUDED_TOPICS: &[&str] = &["/v1/tac/setup_mode", "/v1/labgrid/place_locked"];
+
+/// All persistent settings of a TAC
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsDocument {
+    pub format_version: u64,
+    /// The values of persistent topics by topic path
+    pub topics: BTreeMap<String, Value>,
+    /// The content of config files by the API path they are exposed at
+    #[serde(default)]
+    pub files: BTreeMap<String, String>,
+}
+
+/// A file that is part of the settings
+struct SettingsFile {
+    web_path: String,
+    fs_path: PathBuf,
+}
+
+fn settings_files(in_setup_mode: bool) -> Vec<SettingsFile> {
+    let mut files: Vec<SettingsFile> = EXPOSED_FILES_RW
+        .iter()
+        .map(|(fs_path, web_path)| SettingsFile {
+            web_path: web_path.to_string(),
+            fs_path: PathBuf::from(FS_PREFIX.to_owned() + fs_path),
+        })
+        .collect();
+
+    // The authorized_keys file can only be accessed in setup mode
+    if in_setup_mode {
+        files.push(SettingsFile {
+            web_path: AUTHORIZED_KEYS_WEB_PATH.to_string(),
+            fs_path: PathBuf::from(AUTHORIZED_KEYS_PATH),
+        });
+    }
+
+    files
+}
+
+/// A persistent topic that is part of the settings
+struct SettingsTopic<'a> {
+    path: &'a str,
+    /// The persistent topic the current value is read from
+    stored: &'a Arc<dyn AnyTopic>,
+    /// The topic imported values are written to
+    writable: &'a Arc<dyn AnyTopic>,
+}
+
+/// Get the persistent topics that could also be set via the API
+///
+/// Settings that are validated by the tacd use a persistent read only topic
+/// and a write only topic with the same path (see `BrokerBuilder::setting`).
+/// These are imported via the write only topic, so that the same checks
+/// apply as for values written via the API.
+fn settings_topics(topics: &[Arc<dyn AnyTopic>]) -> Vec<SettingsTopic<'_>> {
+    topics
+        .iter()
+        .filter(|t| t.persistent())
+        .filter_map(|stored| {
+            let path: &str = stored.path();
+
+            if EXCLUDED_TOPICS.contains(&path) {
+                return None;
+            }
+
+            let writable = match stored.web_writable() {
+                true => stored,
+                false => topics.iter().find(|t| {
+                    let other: &str = t.path();
+                    other == path && t.web_writable() && !t.persistent()
+                })?,
+            };
+
+            Some(SettingsTopic {
+                path,
+                stored,
+                writable,
+            })
+        })
+        .collect()
+}
+
+fn export(topics: &[Arc<dyn AnyTopic>], files: &[SettingsFile]) -> SettingsDocument {
+    let topics = settings_topics(topics)
+        .iter()
+        .filter_map(|t| {
+            t.stored
+                .try_get_json_value()
+                .map(|v| (t.path.to_string(), v))
+        })
+        .collect();
+
+    // Files that do not exist are not part of the export
+    let files = files
+        .iter()
+        .filter_map(|f| {
+            read_to_string(&f.fs_path)
+                .ok()
+                .map(|content| (f.web_path.clone(), content))
+        })
+        .collect();
+
+    SettingsDocument {
+        format_version: FORMAT_VERSION,
+        topics,
+        files,
+    }
+}
+
+/// Check every entry of the document before anything is applied
+///
+/// Returns a list of all problems found.
+fn validate(
+    topics: &[Arc<dyn AnyTopic>],
+    files: &[SettingsFile],
+    doc: &SettingsDocument,
+) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+
+    if doc.format_version != FORMAT_VERSION {
+        errors.push(format!(
+            "Unsupported format version {} (expected {FORMAT_VERSION})",
+            doc.format_version
+        ));
+    }
+
+    let settings_topics = settings_topics(topics);
+
+    for (path, value) in doc.topics.iter() {
+        let topic = settings_topics.iter().find(|t| t.path == path);
+
+        match topic {
+            Some(topic) => {
+                if let Err(e) = topic.writable.check_json_value(value) {
+                    errors.push(format!("Invalid value for topic {path}: {e}"));
+                }
+            }
+            None => errors.push(format!("Unknown topic {path}")),
+        }
+    }
+
+    for path in doc.files.keys() {
+        if !files.iter().any(|f| &f.web_path == path) {
+            errors.push(format!("Unknown file {path}"));
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors),
+    }
+}
+
+/// A file that was written to a temporary path next to the actual file
+struct StagedFile<'a> {
+    tmp_path: PathBuf,
+    fs_path: &'a Path,
+    /// The content to restore if a later file can not be replaced
+    previous: Option<String>,
+}
+
+fn stage_file<'a>(file: &'a SettingsFile, content: &str) -> Result<StagedFile<'a>> {
+    let previous = match read_to_string(&file.fs_path) {
+        Ok(previous) => Some(previous),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(parent) = file.fs_path.parent().filter(|p| !p.exists()) {
+        create_dir_all(parent)?;
+    }
+
+    let tmp_path = file.fs_path.with_extension("import");
+    write(&tmp_path, content)?;
+
+    Ok(StagedFile {
+        tmp_path,
+        fs_path: &file.fs_path,
+        previous,
+    })
+}
+
+/// Write all files to temporary files, without touching the actual files
+fn stage_files<'a>(
+    files: &'a [SettingsFile],
+    contents: &BTreeMap<String, String>,
+) -> Result<Vec<StagedFile<'a>>> {
+    let mut staged = Vec::new();
+
+    for (web_path, content) in contents.iter() {
+        let res = files
+            .iter()
+            .find(|f| &f.web_path == web_path)
+            .ok_or_else(|| anyhow!("Unknown file {web_path}"))
+            .and_then(|file| stage_file(file, content));
+
+        match res {
+            Ok(file) => staged.push(file),
+            Err(e) => {
+                for file in staged {
+                    let _ = remove_file(file.tmp_path);
+                }
+
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(staged)
+}
+
+/// Replace the actual files with the staged ones
+///
+/// If one of them can not be replaced the ones that already were are
+/// restored, so that either all or none of the files change.
+fn commit_files(staged: Vec<StagedFile>) -> Result<()> {
+    for (index, file) in staged.iter().enumerate() {
+        if let Err(e) = rename(&file.tmp_path, file.fs_path) {
+            for done in staged[..index].iter() {
+                let _ = match &done.previous {
+                    Some(previous) => write(done.fs_path, previous),
+                    None => remove_file(done.fs_path),
+                };
+            }
+
+            for pending in staged[index..].iter() {
+                let _ = remove_file(&pending.tmp_path);
+            }
+
+            return Err(e.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply a validated document
+///
+/// Everything that may fail is done before the first setting is changed.
+fn apply(
+    topics: &[Arc<dyn AnyTopic>],
+    files: &[SettingsFile],
+    doc: SettingsDocument,
+) -> Result<()> {
+    let settings_topics = settings_topics(topics);
+
+    let updates = doc
+        .topics
+        .into_iter()
+        .map(|(path, value)| {
+            let topic = settings_topics
+                .iter()
+                .find(|t| t.path == path)
+                .ok_or_else(|| anyhow!("Unknown topic {path}"))?;
+
+            // Make sure setting the topic below can not fail
+            topic.writable.check_json_value(&value)?;
+
+            Ok((topic.writable, value))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let staged = stage_files(files, &doc.files)?;
+    commit_files(staged)?;
+
+    for (topic, value) in updates {
+        topic.set_from_json_value(value)?;
+    }
+
+    Ok(())
+}
+
+fn text_response(status: u16, body: String) -> Response {
+    Response::builder(status)
+        .body(body)
+        .content_type(mime::PLAIN)
+        .build()
+}
+
+/// Allow copying the settings of one TAC to others
+///
+/// This has to happen after the broker is built, as all persistent topics
+/// are part of the settings.
+pub fn serve(
+    server: &mut Server<()>,
+    topics: Arc<Vec<Arc<dyn AnyTopic>>>,
+    setup_mode: Arc<Topic<bool>>,
+) {
+    let topics_task = topics.clone();
+    let setup_mode_task = setup_mode.clone();
+
+    server.at("/v1/tac/settings").get(move |_req| {
+        let topics = topics_task.clone();
+        let setup_mode = setup_mode_task.clone();
+
+        async move {
+            let files = settings_files(setup_mode.get().await);
+            let doc = export(&topics, &files);
+
+            Ok(Response::builder(200)
+                .body(serde_json::to_vec_pretty(&doc)?)
+                .content_type(mime::JSON)
+                .build())
+        }
+    });
+
+    server
+        .at("/v1/tac/settings")
+        .put(move |mut req: Request<()>| {
+            let topics = topics.clone();
+            let setup_mode = setup_mode.clone();
+
+            async move {
+                if !setup_mode.get().await {
+                    let msg = "Settings may only be imported in setup mode".to_string();
+                    return Ok(text_response(403, msg));
+                }
+
+                let doc: SettingsDocument = match serde_json::from_slice(&req.body_bytes().await?) {
+                    Ok(doc) => doc,
+                    Err(e) => return Ok(text_response(400, format!("Malformed document: {e}"))),
+                };
+
+                let files = settings_files(true);
+
+                if let Err(errors) = validate(&topics, &files, &doc) {
+                    return Ok(text_response(400, errors.join("\n")));
+                }
+
+                match apply(&topics, &files, doc) {
+                    Ok(()) => Ok(Response::new(204)),
+                    Err(e) => Ok(text_response(500, format!("Failed to apply settings: {e}"))),
+                }
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use std::time::Duration;
+
+    use async_std::future::timeout;
+    use async_std::prelude::*;
+    use async_std::task::block_on;
+    use serde_json::json;
+    use tide::http::{Method, Request, Response, Url};
+
+    use super::{apply, export, serve, validate, SettingsDocument, SettingsFile, FORMAT_VERSION};
+    use crate::broker::{BrokerBuilder, Topic};
+    use crate::config::PersistenceConfig;
+
+    #[test]
+    fn import_export() {
+        let mut bb = BrokerBuilder::new();
+
+        let brightness = bb.topic("/v1/brightness", true, true, true, Some(1.0f32), 1);
+        let name = bb.topic("/v1/name", true, true, true, Some("tac".to_string()), 1);
+        bb.topic("/v1/volatile", true, true, false, Some(1u32), 1);
+        bb.topic("/v1/tac/setup_mode", true, false, true, Some(true), 1);
+        bb.topic("/v1/boots", true, false, true, Some(3u32), 1);
+
+        // A validated setting. Only values below 10 are accepted.
+        let threshold = bb.setting("/v1/threshold", 1u32, |t| *t < 10);
+
+        let dir = std::env::temp_dir().join(format!("tacd-settings-{}", std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+
+        // Do not write the state file while the test is running
+        let persistence_config = PersistenceConfig {
+            state_file: dir.join("state.json").to_str().unwrap().to_string(),
+            write_delay_ms: 3_600_000,
+            max_write_delay_ms: 3_600_000,
+            ..Default::default()
+        };
+
+        let mut server = tide::new();
+        let (topics, _persistence) = bb.build(&mut server, &persistence_config);
+
+        serve(&mut server, topics.clone(), Topic::anonymous(Some(true)));
+
+        // The userconfig can not be written, as its parent is not a directory
+        write(dir.join("blocker"), "").unwrap();
+
+        let files = vec![
+            SettingsFile {
+                web_path: "/v1/labgrid/environment".to_string(),
+                fs_path: dir.join("environment"),
+            },
+            SettingsFile {
+                web_path: "/v1/labgrid/userconfig".to_string(),
+                fs_path: dir.join("blocker").join("userconfig"),
+            },
+        ];
+
+        // Only persistent topics that can also be set via the API
+        // (but not the setup mode) are exported
+        let doc = export(&topics, &files);
+        assert_eq!(
+            serde_json::to_value(&doc).unwrap(),
+            json!({
+                "format_version": FORMAT_VERSION,
+                "topics": {
+                    "/v1/brightness": 1.0,
+                    "/v1/name": "tac",
+                    "/v1/threshold": 1,
+                },
+                "files": {},
+            })
+        );
+
+        // Nothing is applied if a single entry is invalid
+        let doc: SettingsDocument = serde_json::from_value(json!({
+            "format_version": FORMAT_VERSION,
+            "topics": {
+                "/v1/boots": 4,
+                "/v1/brightness": "bright",
+                "/v1/name": "other",
+                "/v1/volatile": 2,
+                "/v1/tac/setup_mode": false,
+            },
+            "files": {
+                "/v1/labgrid/environment": "LG_HOSTNAME=tac\n",
+                "/v1/tac/ssh/authorized_keys": "ssh-ed25519 AAAA",
+            },
+        }))
+        .unwrap();
+
+        let errors = validate(&topics, &files, &doc).unwrap_err();
+        assert_eq!(errors.len(), 5);
+        assert_eq!(errors[0], "Unknown topic /v1/boots");
+        assert!(errors[1].starts_with("Invalid value for topic /v1/brightness"));
+        assert_eq!(errors[2], "Unknown topic /v1/tac/setup_mode");
+        assert_eq!(errors[3], "Unknown topic /v1/volatile");
+        assert_eq!(errors[4], "Unknown file /v1/tac/ssh/authorized_keys");
+
+        let doc: SettingsDocument = serde_json::from_value(json!({
+            "format_version": FORMAT_VERSION,
+            "topics": {
+                "/v1/brightness": 0.5,
+                "/v1/name": "other",
+                "/v1/threshold": 5,
+            },
+            "files": {
+                "/v1/labgrid/environment": "LG_HOSTNAME=tac\n",
+            },
+        }))
+        .unwrap();
+
+        let (mut threshold_events, _) = threshold.clone().subscribe_unbounded();
+
+        validate(&topics, &files, &doc).unwrap();
+        apply(&topics, &files, doc).unwrap();
+
+        assert_eq!(brightness.try_get(), Some(0.5));
+        assert_eq!(name.try_get().as_deref(), Some("other"));
+
+        // Validated settings are imported via their write only topic,
+        // which forwards the value to the setting itself.
+        block_on(timeout(Duration::from_secs(10), async {
+            while threshold_events.next().await != Some(5) {}
+        }))
+        .unwrap();
+
+        // Values that are rejected by the validator of a setting make the
+        // whole import fail
+        let doc = json!({
+            "format_version": FORMAT_VERSION,
+            "topics": {
+                "/v1/brightness": 0.75,
+                "/v1/threshold": 20,
+            },
+            "files": {},
+        });
+
+        let errors = validate(
+            &topics,
+            &files,
+            &serde_json::from_value(doc.clone()).unwrap(),
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Invalid value for topic /v1/threshold"));
+
+        let mut req = Request::new(
+            Method::Put,
+            Url::parse("http://tac/v1/tac/settings").unwrap(),
+        );
+        req.set_body(doc.to_string());
+        let res: Response = block_on(server.respond(req)).unwrap();
+        assert_eq!(res.status(), 400);
+
+        assert_eq!(brightness.try_get(), Some(0.5));
+        assert_eq!(threshold.try_get(), Some(5));
+        assert_eq!(
+            read_to_string(dir.join("environment")).unwrap(),
+            "LG_HOSTNAME=tac\n"
+        );
+
+        // Files are exported as well
+        let doc = export(&topics, &files);
+        assert_eq!(doc.files["/v1/labgrid/environment"], "LG_HOSTNAME=tac\n");
+
+        // Nothing changes if one of the files can not be written
+        let doc: SettingsDocument = serde_json::from_value(json!({
+            "format_version": FORMAT_VERSION,
+            "topics": {
+                "/v1/brightness": 0.25,
+            },
+            "files": {
+                "/v1/labgrid/environment": "LG_HOSTNAME=other\n",
+                "/v1/labgrid/userconfig": "tags: {}\n",
+            },
+        }))
+        .unwrap();
+
+        validate(&topics, &files, &doc).unwrap();
+        assert!(apply(&topics, &files, doc).is_err());
+
+        assert_eq!(brightness.try_get(), Some(0.5));
+        assert_eq!(
+            read_to_string(dir.join("environment")).unwrap(),
+            "LG_HOSTNAME=tac\n"
+        );
+        assert!(!dir.join("environment.import").exists());
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
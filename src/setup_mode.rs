@@ -27,10 +27,12 @@ use tide::{http::mime, Request, Response, Server};
 use crate::broker::{BrokerBuilder, Topic};
 
 #[cfg(feature = "demo_mode")]
-const AUTHORIZED_KEYS_PATH: &str = "demo_files/home/root/ssh/authorized_keys";
+pub const AUTHORIZED_KEYS_PATH: &str = "demo_files/home/root/ssh/authorized_keys";
 
 #[cfg(not(feature = "demo_mode"))]
-const AUTHORIZED_KEYS_PATH: &str = "/home/root/.ssh/authorized_keys";
+pub const AUTHORIZED_KEYS_PATH: &str = "/home/root/.ssh/authorized_keys";
+
+pub const AUTHORIZED_KEYS_WEB_PATH: &str = "/v1/tac/ssh/authorized_keys";
 
 pub struct SetupMode {
     pub setup_mode: Arc<Topic<bool>>,
@@ -140,7 +142,7 @@ impl SetupMode {
         };
 
         this.handle_leave_requests(bb);
-        this.expose_file_conditionally(server, AUTHORIZED_KEYS_PATH, "/v1/tac/ssh/authorized_keys");
+        this.expose_file_conditionally(server, AUTHORIZED_KEYS_PATH, AUTHORIZED_KEYS_WEB_PATH);
 
         this
     }
@@ -15,16 +15,30 @@
 // with this program; if not, write to the Free Software Foundation, Inc.,
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 
+use std::fs::{create_dir_all, read_to_string, rename, File};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_std::future::timeout;
+use async_std::prelude::*;
 use async_std::sync::Arc;
+use async_std::task::spawn;
+use log::warn;
+use nix::sys::statvfs::statvfs;
 use nix::sys::utsname::uname;
 use serde::{Deserialize, Serialize};
+use serde_json::{from_reader, to_writer};
 
 use crate::broker::{BrokerBuilder, Topic};
+use crate::config::{HttpConfig, PersistenceConfig};
+use crate::measurement::Measurement;
 
 #[cfg(feature = "demo_mode")]
 mod read_dt_props {
     const DEMO_DATA_STR: &[(&str, &str)] = &[
         ("barebox-version", "barebox-2022.11.0-20221121-1"),
+        ("reset-source", "POR"),
         (
             "baseboard-factory-data/pcba-hardware-release",
             "lxatac-S01-R03-B02-C00",
@@ -42,10 +56,15 @@ mod read_dt_props {
         ("powerboard-factory-data/factory-timestamp", 1678086418),
     ];
 
-    pub fn read_dt_property(path: &str) -> String {
-        let (_, content) = DEMO_DATA_STR.iter().find(|(p, _)| *p == path).unwrap();
+    pub fn try_read_dt_property(path: &str) -> Option<String> {
+        DEMO_DATA_STR
+            .iter()
+            .find(|(p, _)| *p == path)
+            .map(|(_, content)| content.to_string())
+    }
 
-        content.to_string()
+    pub fn read_dt_property(path: &str) -> String {
+        try_read_dt_property(path).unwrap()
     }
 
     pub fn read_dt_property_u32(path: &str) -> u32 {
@@ -60,11 +79,15 @@ mod read_dt_props {
 
     const DT_CHOSEN: &str = "/sys/firmware/devicetree/base/chosen/";
 
+    pub fn try_read_dt_property(path: &str) -> Option<String> {
+        let bytes = read([DT_CHOSEN, path].join("/")).ok()?;
+        let content = from_utf8(bytes.strip_suffix(&[0])?).ok()?;
+
+        Some(content.to_string())
+    }
+
     pub fn read_dt_property(path: &str) -> String {
-        let bytes = read([DT_CHOSEN, path].join("/")).unwrap();
-        from_utf8(bytes.strip_suffix(&[0]).unwrap())
-            .unwrap()
-            .to_string()
+        try_read_dt_property(path).unwrap()
     }
 
     pub fn read_dt_property_u32(path: &str) -> u32 {
@@ -72,7 +95,14 @@ mod read_dt_props {
     }
 }
 
-use read_dt_props::{read_dt_property, read_dt_property_u32};
+use read_dt_props::{read_dt_property, read_dt_property_u32, try_read_dt_property};
+
+const ROOTFS_PATH: &str = "/";
+const RESOURCES_INTERVAL: Duration = Duration::from_secs(5);
+
+// A resource warning is only cleared once the value drops below this
+// fraction of the threshold.
+const WARNING_HYSTERESIS: f32 = 0.9;
 
 #[derive(Serialize, Deserialize)]
 pub struct Uname {
@@ -133,20 +163,460 @@ impl Barebox {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Boot {
+    /// Number of times the TAC was booted since the boot counter was created
+    pub count: u64,
+    /// Reason for the last reset as reported by the bootloader
+    pub reset_source: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BootCountFile {
+    boot_id: String,
+    count: u64,
+}
+
+impl Boot {
+    /// Increment the boot counter if this is the first time tacd is started
+    /// during the current boot
+    fn count(path: &Path) -> Result<u64> {
+        let boot_id = read_to_string("/proc/sys/kernel/random/boot_id")?;
+        let boot_id = boot_id.trim().to_string();
+
+        let prev: Option<BootCountFile> = File::open(path).ok().and_then(|f| from_reader(f).ok());
+
+        let count = match prev {
+            Some(prev) if prev.boot_id == boot_id => return Ok(prev.count),
+            Some(prev) => prev.count + 1,
+            None => 1,
+        };
+
+        let path_tmp = path.with_extension("tmp");
+
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        {
+            let fd = File::create(&path_tmp)?;
+            to_writer(&fd, &BootCountFile { boot_id, count })?;
+            fd.sync_all()?;
+        }
+
+        rename(path_tmp, path)?;
+
+        Ok(count)
+    }
+
+    fn get(boot_count_file: &str) -> Self {
+        let count = Self::count(Path::new(boot_count_file)).unwrap_or_else(|e| {
+            warn!("Failed to update the boot counter: {e}");
+            0
+        });
+
+        Self {
+            count,
+            reset_source: try_read_dt_property("reset-source"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct LoadAverage {
+    pub one: f32,
+    pub five: f32,
+    pub fifteen: f32,
+}
+
+impl LoadAverage {
+    fn parse(loadavg: &str) -> Result<Self> {
+        let mut fields = loadavg.split_whitespace().map(|f| f.parse::<f32>());
+        let mut next = || -> Result<f32> {
+            Ok(fields
+                .next()
+                .ok_or_else(|| anyhow!("Missing field in loadavg"))??)
+        };
+
+        Ok(Self {
+            one: next()?,
+            five: next()?,
+            fifteen: next()?,
+        })
+    }
+
+    fn get() -> Result<Self> {
+        Self::parse(&read_to_string("/proc/loadavg")?)
+    }
+}
+
+/// Total and idle jiffies spent by all CPUs since boot
+#[derive(Clone, Copy)]
+struct CpuTimes {
+    total: u64,
+    idle: u64,
+}
+
+impl CpuTimes {
+    fn parse(stat: &str) -> Result<Self> {
+        let cpu = stat
+            .lines()
+            .find(|l| l.starts_with("cpu "))
+            .ok_or_else(|| anyhow!("Missing cpu line in stat"))?;
+
+        let fields = cpu
+            .split_whitespace()
+            .skip(1)
+            .map(|f| f.parse::<u64>())
+            .collect::<Result<Vec<u64>, _>>()?;
+
+        if fields.len() < 5 {
+            return Err(anyhow!("Missing fields in cpu line"));
+        }
+
+        // Only the first eight fields (user up to steal) add up to the total.
+        // The "guest" and "guest_nice" fields that may follow are already
+        // included in "user" and "nice".
+        // Fields 3 and 4 are "idle" and "iowait".
+        Ok(Self {
+            total: fields.iter().take(8).sum(),
+            idle: fields[3] + fields[4],
+        })
+    }
+
+    fn get() -> Result<Self> {
+        Self::parse(&read_to_string("/proc/stat")?)
+    }
+
+    /// Fraction of the time the CPUs were busy since `prev`
+    fn usage_since(&self, prev: &Self) -> f32 {
+        let total = self.total.saturating_sub(prev.total);
+        let idle = self.idle.saturating_sub(prev.idle);
+
+        if total == 0 {
+            0.0
+        } else {
+            1.0 - (idle as f32) / (total as f32)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct MemoryUsage {
+    /// Total usable memory in bytes
+    pub total: u64,
+    /// Memory available to new applications in bytes
+    pub available: u64,
+}
+
+impl MemoryUsage {
+    fn parse(meminfo: &str) -> Result<Self> {
+        let field = |name: &str| -> Result<u64> {
+            let line = meminfo
+                .lines()
+                .find(|l| l.starts_with(name))
+                .ok_or_else(|| anyhow!("Missing {name} in meminfo"))?;
+
+            let kib: u64 = line
+                .split_whitespace()
+                .nth(1)
+                .ok_or_else(|| anyhow!("Missing value for {name} in meminfo"))?
+                .parse()?;
+
+            Ok(kib * 1024)
+        };
+
+        Ok(Self {
+            total: field("MemTotal:")?,
+            available: field("MemAvailable:")?,
+        })
+    }
+
+    fn get() -> Result<Self> {
+        Self::parse(&read_to_string("/proc/meminfo")?)
+    }
+
+    fn used_fraction(&self) -> f32 {
+        1.0 - (self.available as f32) / (self.total.max(1) as f32)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct DiskUsage {
+    /// Size of the filesystem in bytes
+    pub total: u64,
+    /// Space available to unprivileged users in bytes
+    pub available: u64,
+}
+
+impl DiskUsage {
+    fn get(path: &str) -> Result<Self> {
+        let stat = statvfs(path)?;
+        let fragment_size = stat.fragment_size() as u64;
+
+        Ok(Self {
+            total: stat.blocks() as u64 * fragment_size,
+            available: stat.blocks_available() as u64 * fragment_size,
+        })
+    }
+
+    fn used_fraction(&self) -> f32 {
+        1.0 - (self.available as f32) / (self.total.max(1) as f32)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ResourceThresholds {
+    /// One minute load average above which HighLoad is raised
+    pub load: f32,
+    /// Fraction of used memory above which MemoryLow is raised
+    pub memory: f32,
+    /// Fraction of used disk space above which SrvFull / RootfsFull is raised
+    pub disk: f32,
+}
+
+impl ResourceThresholds {
+    fn is_valid(&self) -> bool {
+        let is_fraction = |v: f32| v > 0.0 && v <= 1.0;
+
+        // The comparisons are false for NaN, so those are rejected as well
+        self.load > 0.0
+            && self.load.is_finite()
+            && is_fraction(self.memory)
+            && is_fraction(self.disk)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ResourceWarning {
+    HighLoad,
+    MemoryLow,
+    SrvFull,
+    RootfsFull,
+}
+
+struct Resources {
+    load: Arc<Topic<LoadAverage>>,
+    cpu: Arc<Topic<Measurement>>,
+    memory: Arc<Topic<MemoryUsage>>,
+    srv: Arc<Topic<DiskUsage>>,
+    rootfs: Arc<Topic<DiskUsage>>,
+    uptime: Arc<Topic<u64>>,
+    thresholds: Arc<Topic<ResourceThresholds>>,
+    warnings: Arc<Topic<Vec<ResourceWarning>>>,
+    srv_dir: String,
+}
+
+/// Log which source could not be read instead of failing the whole update
+fn read_source<T>(name: &str, res: Result<T>) -> Option<T> {
+    res.map_err(|e| warn!("Failed to read the {name} resource usage: {e}"))
+        .ok()
+}
+
+fn uptime() -> Result<u64> {
+    let uptime = read_to_string("/proc/uptime")?;
+    let uptime: f64 = uptime
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| anyhow!("Missing field in uptime"))?
+        .parse()?;
+
+    Ok(uptime as u64)
+}
+
+impl Resources {
+    /// Update the resource usage topics and warnings
+    ///
+    /// Every source is read independently, so that e.g. a failing statvfs()
+    /// on /srv does not keep the memory usage from being updated.
+    fn update(&self, cpu_times: &mut Option<CpuTimes>) {
+        let load = read_source("load", LoadAverage::get());
+        let memory = read_source("memory", MemoryUsage::get());
+        let srv = read_source("/srv disk", DiskUsage::get(&self.srv_dir));
+        let rootfs = read_source("rootfs disk", DiskUsage::get(ROOTFS_PATH));
+        let uptime = read_source("uptime", uptime());
+
+        if let Some(cpu_times_now) = read_source("cpu", CpuTimes::get()) {
+            if let Some(prev) = cpu_times.replace(cpu_times_now) {
+                self.cpu
+                    .set(Measurement::now(cpu_times_now.usage_since(&prev)));
+            }
+        }
+
+        if let Some(thresholds) = self.thresholds.try_get() {
+            let prev = self.warnings.try_get().unwrap_or_default();
+
+            let checks = [
+                (
+                    ResourceWarning::HighLoad,
+                    load.as_ref().map(|l| l.one),
+                    thresholds.load,
+                ),
+                (
+                    ResourceWarning::MemoryLow,
+                    memory.as_ref().map(|m| m.used_fraction()),
+                    thresholds.memory,
+                ),
+                (
+                    ResourceWarning::SrvFull,
+                    srv.as_ref().map(|d| d.used_fraction()),
+                    thresholds.disk,
+                ),
+                (
+                    ResourceWarning::RootfsFull,
+                    rootfs.as_ref().map(|d| d.used_fraction()),
+                    thresholds.disk,
+                ),
+            ];
+
+            let warnings: Vec<ResourceWarning> = checks
+                .iter()
+                .filter(|(warning, value, threshold)| {
+                    let active = prev.contains(warning);
+
+                    // Only clear a warning once the value is clearly below
+                    // the threshold, so that it does not flap when the value
+                    // hovers around it.
+                    // Keep the previous state if the source could not be read
+                    // this time around.
+                    match value {
+                        Some(v) if active => *v > threshold * WARNING_HYSTERESIS,
+                        Some(v) => v > threshold,
+                        None => active,
+                    }
+                })
+                .map(|(warning, _, _)| *warning)
+                .collect();
+
+            self.warnings.set_if_changed(warnings);
+        }
+
+        if let Some(load) = load {
+            self.load.set_if_changed(load);
+        }
+
+        if let Some(memory) = memory {
+            self.memory.set_if_changed(memory);
+        }
+
+        if let Some(srv) = srv {
+            self.srv.set_if_changed(srv);
+        }
+
+        if let Some(rootfs) = rootfs {
+            self.rootfs.set_if_changed(rootfs);
+        }
+
+        if let Some(uptime) = uptime {
+            self.uptime.set(uptime);
+        }
+    }
+}
+
 pub struct System {
     pub uname: Arc<Topic<Arc<Uname>>>,
     pub barebox: Arc<Topic<Arc<Barebox>>>,
     pub tacd_version: Arc<Topic<String>>,
+    pub resource_warnings: Arc<Topic<Vec<ResourceWarning>>>,
 }
 
 impl System {
-    pub fn new(bb: &mut BrokerBuilder) -> Self {
+    pub fn new(
+        bb: &mut BrokerBuilder,
+        http_config: &HttpConfig,
+        persistence_config: &PersistenceConfig,
+    ) -> Self {
         let version = env!("VERSION_STRING").to_string();
 
+        bb.topic_ro(
+            "/v1/tac/info/boot",
+            Some(Boot::get(&persistence_config.boot_count_file)),
+        );
+
+        let resources = Resources {
+            load: bb.topic_ro("/v1/tac/resources/load", None),
+            cpu: bb.topic_ro("/v1/tac/resources/cpu", None),
+            memory: bb.topic_ro("/v1/tac/resources/memory", None),
+            srv: bb.topic_ro("/v1/tac/resources/disk/srv", None),
+            rootfs: bb.topic_ro("/v1/tac/resources/disk/rootfs", None),
+            uptime: bb.topic_ro("/v1/tac/resources/uptime", None),
+            thresholds: bb.setting(
+                "/v1/tac/resources/thresholds",
+                ResourceThresholds {
+                    load: 4.0,
+                    memory: 0.9,
+                    disk: 0.9,
+                },
+                ResourceThresholds::is_valid,
+            ),
+            warnings: bb.topic_ro("/v1/tac/resources/warnings", Some(Vec::new())),
+            srv_dir: http_config.srv_dir.clone(),
+        };
+
+        let resource_warnings = resources.warnings.clone();
+
+        // Periodically update the resource usage and re-evaluate the
+        // warnings whenever the thresholds change.
+        spawn(async move {
+            let (mut thresholds_events, _) = resources.thresholds.clone().subscribe_unbounded();
+            let mut cpu_times = None;
+
+            loop {
+                resources.update(&mut cpu_times);
+
+                let _ = timeout(RESOURCES_INTERVAL, thresholds_events.next()).await;
+            }
+        });
+
         Self {
             uname: bb.topic_ro("/v1/tac/info/uname", Some(Arc::new(Uname::get()))),
             barebox: bb.topic_ro("/v1/tac/info/bootloader", Some(Arc::new(Barebox::get()))),
             tacd_version: bb.topic_ro("/v1/tac/info/tacd/version", Some(version)),
+            resource_warnings,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CpuTimes, LoadAverage, MemoryUsage, ResourceThresholds};
+
+    #[test]
+    fn parse_proc() {
+        let load = LoadAverage::parse("0.52 0.58 0.59 1/187 3456\n").unwrap();
+        assert_eq!(load.one, 0.52);
+        assert_eq!(load.fifteen, 0.59);
+
+        let prev = CpuTimes::parse("cpu  100 0 100 700 100 0 0 0 0 0\ncpu0 1 2 3 4 5\n").unwrap();
+        let now = CpuTimes::parse("cpu  150 0 150 800 100 0 0 0 0 0\n").unwrap();
+        assert_eq!(now.usage_since(&prev), 0.5);
+
+        // Time spent running guests is already accounted for in user / nice
+        let now = CpuTimes::parse("cpu  150 0 150 800 100 0 0 0 40 10\n").unwrap();
+        assert_eq!(now.usage_since(&prev), 0.5);
+
+        let meminfo = "MemTotal:         500000 kB\nMemFree:          100000 kB\nMemAvailable:     250000 kB\n";
+        let mem = MemoryUsage::parse(meminfo).unwrap();
+        assert_eq!(mem.total, 500000 * 1024);
+        assert_eq!(mem.used_fraction(), 0.5);
+
+        assert!(LoadAverage::parse("0.52").is_err());
+        assert!(CpuTimes::parse("intr 0 1 2").is_err());
+        assert!(MemoryUsage::parse("MemTotal: 1 kB").is_err());
+    }
+
+    #[test]
+    fn thresholds() {
+        let valid = |load, memory, disk| ResourceThresholds { load, memory, disk }.is_valid();
+
+        assert!(valid(4.0, 0.9, 0.9));
+        assert!(valid(0.1, 1.0, 1.0));
+        assert!(!valid(0.0, 0.9, 0.9));
+        assert!(!valid(-1.0, 0.9, 0.9));
+        assert!(!valid(f32::INFINITY, 0.9, 0.9));
+        assert!(!valid(f32::NAN, 0.9, 0.9));
+        assert!(!valid(4.0, 1.1, 0.9));
+        assert!(!valid(4.0, 0.9, 0.0));
+        assert!(!valid(4.0, 0.9, f32::NAN));
+    }
+}
@@ -15,12 +15,14 @@
 // with this program; if not, write to the Free Software Foundation, Inc.,
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 
+use std::io::Result;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::sleep;
 use std::time::Duration;
 
 use async_std::sync::Arc;
 use async_std::task::spawn_blocking;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
 use crate::broker::{BrokerBuilder, Topic};
@@ -28,26 +30,67 @@ use crate::measurement::Measurement;
 
 #[cfg(feature = "demo_mode")]
 mod hw {
-    pub trait SysClass {
-        fn input(&self) -> Result<u32, ()>;
+    use std::io::{Error, ErrorKind, Result};
+
+    pub trait SysClass: Sized {
+        fn all() -> Result<Vec<Self>>;
+        fn id(&self) -> &str;
+    }
+
+    #[derive(Clone)]
+    pub struct HwMon {
+        id: &'static str,
+        name: &'static str,
+        millicelsius: u32,
+    }
+
+    pub struct TempDecoy {
+        millicelsius: u32,
     }
 
-    pub struct HwMon;
-    pub struct TempDecoy;
+    impl SysClass for HwMon {
+        fn all() -> Result<Vec<Self>> {
+            Ok(vec![
+                Self {
+                    id: "hwmon0",
+                    name: "cpu_thermal",
+                    millicelsius: 30_000,
+                },
+                Self {
+                    id: "hwmon1",
+                    name: "tmp1075",
+                    millicelsius: 25_000,
+                },
+            ])
+        }
 
-    impl SysClass for TempDecoy {
-        fn input(&self) -> Result<u32, ()> {
-            Ok(30_000)
+        fn id(&self) -> &str {
+            self.id
         }
     }
 
     impl HwMon {
-        pub fn new(_: &'static str) -> Result<Self, ()> {
-            Ok(Self)
+        pub fn name(&self) -> Result<String> {
+            Ok(self.name.to_string())
         }
 
-        pub fn temp(&self, _: u64) -> Result<TempDecoy, ()> {
-            Ok(TempDecoy)
+        pub fn temp(&self, id: u64) -> Result<TempDecoy> {
+            match id {
+                1 => Ok(TempDecoy {
+                    millicelsius: self.millicelsius,
+                }),
+                _ => Err(Error::new(ErrorKind::NotFound, "No such sensor")),
+            }
+        }
+    }
+
+    impl TempDecoy {
+        pub fn label(&self) -> Result<String> {
+            Err(Error::new(ErrorKind::NotFound, "No label"))
+        }
+
+        pub fn input(&self) -> Result<u32> {
+            Ok(self.millicelsius)
         }
     }
 }
@@ -60,21 +103,42 @@ mod hw {
 use hw::{HwMon, SysClass};
 
 const UPDATE_INTERVAL: Duration = Duration::from_millis(500);
-const TEMPERATURE_SOC_CRITICAL: f32 = 90.0;
-const TEMPERATURE_SOC_HIGH: f32 = 70.0;
+const HISTORY_LENGTH: usize = 200;
+
+// The number of temperature inputs to probe per hwmon device
+const MAX_TEMP_INPUTS: u64 = 16;
+
+// The SoC temperature is also published under a fixed topic name and is the
+// one the warnings are based on.
+const SOC_HWMON: &str = "hwmon0";
+const SOC_TEMP_INPUT: u64 = 1;
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Thresholds {
+    pub high: f32,
+    pub critical: f32,
+}
+
+impl Thresholds {
+    fn is_valid(&self) -> bool {
+        self.high < self.critical
+    }
+}
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub enum Warning {
     Okay,
     SocHigh,
     SocCritical,
+    /// The SoC temperature can not be read, so it is unknown if it is okay
+    SocSensorError,
 }
 
 impl Warning {
-    fn from_temperatures(soc: f32) -> Self {
-        if soc > TEMPERATURE_SOC_CRITICAL {
+    fn from_temperatures(soc: f32, thresholds: &Thresholds) -> Self {
+        if soc > thresholds.critical {
             Self::SocCritical
-        } else if soc > TEMPERATURE_SOC_HIGH {
+        } else if soc > thresholds.high {
             Self::SocHigh
         } else {
             Self::Okay
@@ -82,6 +146,110 @@ impl Warning {
     }
 }
 
+/// Make a string usable as part of a topic name
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '-' | '_' => c,
+            'A'..='Z' => c.to_ascii_lowercase(),
+            _ => '_',
+        })
+        .collect()
+}
+
+struct Sensor {
+    hwmon: HwMon,
+    input: u64,
+    available: bool,
+    topic: Arc<Topic<Measurement>>,
+}
+
+impl Sensor {
+    fn name(&self) -> String {
+        let hwmon_name = self
+            .hwmon
+            .name()
+            .unwrap_or_else(|_| self.hwmon.id().to_string());
+
+        format!("{} temp{}", hwmon_name, self.input)
+    }
+
+    /// Read the current temperature in degrees Celsius
+    ///
+    /// Sensors may disappear at runtime, e.g. when the driver for a
+    /// peripheral is unbound.
+    /// Log when this happens (or when they come back) instead of failing.
+    fn read(&mut self) -> Option<f32> {
+        match self.hwmon.temp(self.input).and_then(|t| t.input()) {
+            Ok(val) => {
+                if !self.available {
+                    info!("Temperature sensor {} is available again", self.name());
+                    self.available = true;
+                }
+
+                Some(val as f32 / 1000.0)
+            }
+            Err(e) => {
+                if self.available {
+                    warn!("Failed to read temperature sensor {}: {}", self.name(), e);
+                    self.available = false;
+                }
+
+                None
+            }
+        }
+    }
+}
+
+/// Find all temperature inputs of all hwmon devices the kernel exposes
+///
+/// Every input is published on a topic named after the hwmon device and the
+/// label of the input (if it has one).
+/// If multiple hwmon devices have the same name (e.g. two sensors of the
+/// same type) the hwmon index is added to the name, e.g. tmp1075-hwmon2.
+fn enumerate_sensors(bb: &mut BrokerBuilder) -> Result<Vec<Sensor>> {
+    let mut sensors = Vec::new();
+
+    let hwmons = HwMon::all()?;
+
+    let names: Vec<String> = hwmons
+        .iter()
+        .map(|hwmon| sanitize(&hwmon.name().unwrap_or_else(|_| hwmon.id().to_string())))
+        .collect();
+
+    for (hwmon, name) in hwmons.iter().zip(names.iter()) {
+        let hwmon_name = match names.iter().filter(|n| *n == name).count() {
+            1 => name.clone(),
+            _ => format!("{name}-{}", sanitize(hwmon.id())),
+        };
+
+        for input in 1..=MAX_TEMP_INPUTS {
+            let label = match hwmon.temp(input) {
+                Ok(temp) => temp
+                    .label()
+                    .map(|l| sanitize(&l))
+                    .unwrap_or_else(|_| format!("temp{input}")),
+                Err(_) => continue,
+            };
+
+            let path = format!("/v1/tac/temperatures/sensors/{hwmon_name}/{label}");
+
+            info!("Found temperature sensor {path}");
+
+            let topic = bb.topic(&path, true, false, false, None, HISTORY_LENGTH);
+
+            sensors.push(Sensor {
+                hwmon: hwmon.clone(),
+                input,
+                available: true,
+                topic,
+            });
+        }
+    }
+
+    Ok(sensors)
+}
+
 pub struct Temperatures {
     pub soc_temperature: Arc<Topic<Measurement>>,
     pub warning: Arc<Topic<Warning>>,
@@ -91,8 +259,35 @@ pub struct Temperatures {
 impl Temperatures {
     pub fn new(bb: &mut BrokerBuilder) -> Self {
         let run = Arc::new(AtomicBool::new(true));
-        let soc_temperature = bb.topic_ro("/v1/tac/temperatures/soc", None);
+        let soc_temperature = bb.topic(
+            "/v1/tac/temperatures/soc",
+            true,
+            false,
+            false,
+            None,
+            HISTORY_LENGTH,
+        );
         let warning = bb.topic_ro("/v1/tac/temperatures/warning", None);
+        let thresholds = bb.setting(
+            "/v1/tac/temperatures/thresholds",
+            Thresholds {
+                high: 70.0,
+                critical: 90.0,
+            },
+            Thresholds::is_valid,
+        );
+
+        let mut sensors = enumerate_sensors(bb).unwrap_or_else(|e| {
+            warn!("Failed to enumerate temperature sensors: {e}");
+            Vec::new()
+        });
+
+        if !sensors
+            .iter()
+            .any(|s| s.hwmon.id() == SOC_HWMON && s.input == SOC_TEMP_INPUT)
+        {
+            warn!("Could not find the SoC temperature sensor. Temperature warnings are disabled");
+        }
 
         let run_thread = run.clone();
         let soc_temperature_thread = soc_temperature.clone();
@@ -100,23 +295,39 @@ impl Temperatures {
 
         spawn_blocking(move || {
             while run_thread.load(Ordering::Relaxed) {
-                let val = HwMon::new("hwmon0")
-                    .unwrap()
-                    .temp(1)
-                    .unwrap()
-                    .input()
-                    .unwrap();
-
-                let val = val as f32 / 1000.0;
-
-                // Provide a topic that only provides "is overheating"/"is okay"
-                // updates and not the 2Hz temperature feed.
-                // Subscribing to this topic is cheaper w.r.t. cpu/network use.
-                let warning = Warning::from_temperatures(val);
-                warning_thread.set_if_changed(warning);
-
-                let meas = Measurement::now(val);
-                soc_temperature_thread.set(meas);
+                for sensor in sensors.iter_mut() {
+                    let is_soc = sensor.hwmon.id() == SOC_HWMON && sensor.input == SOC_TEMP_INPUT;
+
+                    let val = match sensor.read() {
+                        Some(val) => val,
+                        None => {
+                            // Do not keep a stale warning (e.g. SocCritical)
+                            // around if the sensor went away.
+                            if is_soc {
+                                warning_thread.set_if_changed(Warning::SocSensorError);
+                            }
+
+                            continue;
+                        }
+                    };
+
+                    let meas = Measurement::now(val);
+                    sensor.topic.set(meas);
+
+                    if !is_soc {
+                        continue;
+                    }
+
+                    // Provide a topic that only provides "is overheating"/"is okay"
+                    // updates and not the 2Hz temperature feed.
+                    // Subscribing to this topic is cheaper w.r.t. cpu/network use.
+                    if let Some(thresholds) = thresholds.try_get() {
+                        let warning = Warning::from_temperatures(val, &thresholds);
+                        warning_thread.set_if_changed(warning);
+                    }
+
+                    soc_temperature_thread.set(meas);
+                }
 
                 sleep(UPDATE_INTERVAL);
             }
@@ -0,0 +1,462 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::time::{Duration, Instant};
+
+use async_std::prelude::*;
+use async_std::sync::Arc;
+use async_std::task::{sleep, spawn};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::broker::{BrokerBuilder, Topic};
+use crate::dut_power::OutputState;
+use crate::temperatures::Warning;
+use crate::usb_hub::UsbPort;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Which loads to switch off if the TAC stays critically hot for too long
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Settings {
+    pub dut_power: bool,
+    pub usb_ports: bool,
+    pub iobus: bool,
+    /// Seconds the temperature has to stay critical before acting
+    pub trip_delay: u64,
+    /// Seconds the temperature has to stay below critical before the
+    /// loads are switched on again
+    pub cool_down: u64,
+    /// Switch the loads back on after the cool down period
+    pub auto_reenable: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum State {
+    /// The temperature is below critical
+    Okay,
+    /// The temperature is critical, but not yet for trip_delay seconds
+    Critical,
+    /// The temperature is critical and the selected loads were switched off
+    Tripped,
+    /// The temperature is no longer critical, but not yet for cool_down
+    /// seconds
+    CoolingDown,
+}
+
+impl State {
+    /// Get the next state based on the temperature and how long the
+    /// current state was held
+    fn next(self, critical: bool, held: Duration, settings: &Settings) -> Self {
+        match (self, critical) {
+            (Self::Okay, true) => Self::Critical,
+            (Self::Critical, false) => Self::Okay,
+            (Self::Critical, true) if held >= Duration::from_secs(settings.trip_delay) => {
+                Self::Tripped
+            }
+            (Self::Tripped, false) => Self::CoolingDown,
+            (Self::CoolingDown, true) => Self::Tripped,
+            (Self::CoolingDown, false) if held >= Duration::from_secs(settings.cool_down) => {
+                Self::Okay
+            }
+            (s, _) => s,
+        }
+    }
+
+    /// Are the selected loads currently locked in the off state?
+    fn is_locked(&self) -> bool {
+        matches!(self, Self::Tripped | Self::CoolingDown)
+    }
+}
+
+/// A load that was switched off and can be switched on again later on
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Load {
+    DutPower,
+    UsbPort1,
+    UsbPort2,
+    UsbPort3,
+    IoBus,
+}
+
+/// Used to keep the DUT power and USB ports switched off while the TAC is
+/// too hot
+///
+/// The load reports that it was switched off because of the temperature
+/// (e.g. via the OverTemperature DUT power state) until it is switched on or
+/// off again.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum ThermalLock {
+    /// The load can be switched on and off as usual
+    Released,
+    /// The load is switched off and requests to switch it on are rejected
+    Locked,
+    /// Like Released, but switch the load on again if it is still off
+    /// because of the temperature
+    Restore,
+}
+
+impl From<u8> for ThermalLock {
+    fn from(val: u8) -> Self {
+        if val == (ThermalLock::Released as u8) {
+            return ThermalLock::Released;
+        }
+
+        if val == (ThermalLock::Locked as u8) {
+            return ThermalLock::Locked;
+        }
+
+        if val == (ThermalLock::Restore as u8) {
+            return ThermalLock::Restore;
+        }
+
+        panic!()
+    }
+}
+
+pub struct ThermalProtection {
+    pub switched_off: Arc<Topic<Vec<Load>>>,
+}
+
+struct Loads {
+    dut_pwr_lock: Arc<Topic<ThermalLock>>,
+    dut_pwr_state: Arc<Topic<OutputState>>,
+    usb_ports: [UsbPort; 3],
+    iobus_pwr_en: Arc<Topic<bool>>,
+}
+
+impl Loads {
+    /// Lock the loads selected in `settings` in the off state
+    ///
+    /// Returns the list of loads that were on and are now switched off.
+    fn lock(&self, settings: &Settings) -> Vec<Load> {
+        let mut switched_off = Vec::new();
+
+        if settings.dut_power {
+            if self.dut_pwr_state.try_get() == Some(OutputState::On) {
+                switched_off.push(Load::DutPower);
+            }
+
+            self.dut_pwr_lock.set(ThermalLock::Locked);
+        }
+
+        if settings.usb_ports {
+            let port_loads = [Load::UsbPort1, Load::UsbPort2, Load::UsbPort3];
+
+            for (port, load) in self.usb_ports.iter().zip(port_loads) {
+                if port.status.try_get() == Some(true) {
+                    switched_off.push(load);
+                }
+
+                port.thermal_lock.set(ThermalLock::Locked);
+            }
+        }
+
+        if settings.iobus && self.iobus_pwr_en.try_get() == Some(true) {
+            self.iobus_pwr_en.set(false);
+            switched_off.push(Load::IoBus);
+        }
+
+        switched_off
+    }
+
+    /// Release the locks taken by `lock()`
+    ///
+    /// If `restore` is set the loads that were switched off by `lock()` are
+    /// switched on again, unless they were switched on or off by someone
+    /// else in the meantime.
+    fn release(&self, switched_off: &[Load], restore: bool) {
+        let lock = match restore {
+            true => ThermalLock::Restore,
+            false => ThermalLock::Released,
+        };
+
+        self.dut_pwr_lock.set(lock);
+
+        for port in self.usb_ports.iter() {
+            port.thermal_lock.set(lock);
+        }
+
+        // The IOBus power topic does not distinguish between requests and
+        // state, so it is switched on again as long as it is still off.
+        if restore
+            && switched_off.contains(&Load::IoBus)
+            && self.iobus_pwr_en.try_get() == Some(false)
+        {
+            self.iobus_pwr_en.set(true);
+        }
+    }
+}
+
+impl ThermalProtection {
+    pub fn new(
+        bb: &mut BrokerBuilder,
+        warning: Arc<Topic<Warning>>,
+        dut_pwr_lock: Arc<Topic<ThermalLock>>,
+        dut_pwr_state: Arc<Topic<OutputState>>,
+        usb_ports: [UsbPort; 3],
+        iobus_pwr_en: Arc<Topic<bool>>,
+    ) -> Self {
+        let settings = bb.topic(
+            "/v1/tac/temperatures/protection/settings",
+            true,
+            true,
+            true,
+            Some(Settings {
+                dut_power: false,
+                usb_ports: false,
+                iobus: false,
+                trip_delay: 60,
+                cool_down: 300,
+                auto_reenable: true,
+            }),
+            1,
+        );
+        let state = bb.topic_ro("/v1/tac/temperatures/protection/state", Some(State::Okay));
+        let switched_off = bb.topic_ro(
+            "/v1/tac/temperatures/protection/switched_off",
+            Some(Vec::new()),
+        );
+
+        // The DUT power and USB ports reject requests to switch them on
+        // while they are locked.
+        // The IOBus power is a plain on/off topic instead, so it is switched
+        // off again if someone tries to switch it on.
+        let (mut iobus_events, _) = iobus_pwr_en.clone().subscribe_unbounded();
+        let iobus_pwr_en_task = iobus_pwr_en.clone();
+        let settings_task = settings.clone();
+        let state_task = state.clone();
+
+        spawn(async move {
+            while let Some(powered) = iobus_events.next().await {
+                let locked = state_task.try_get().is_some_and(|s| s.is_locked())
+                    && settings_task.try_get().is_some_and(|s| s.iobus);
+
+                if powered && locked {
+                    warn!("Not switching on the IOBus power while the TAC is too hot");
+                    iobus_pwr_en_task.set(false);
+                }
+            }
+        });
+
+        let loads = Loads {
+            dut_pwr_lock,
+            dut_pwr_state,
+            usb_ports,
+            iobus_pwr_en,
+        };
+
+        let state_task = state.clone();
+        let switched_off_task = switched_off.clone();
+
+        // Poll the temperature warning instead of waiting for changes,
+        // as the transitions below depend on how long a state was held.
+        spawn(async move {
+            let mut since = Instant::now();
+
+            loop {
+                sleep(POLL_INTERVAL).await;
+
+                let critical = warning.try_get() == Some(Warning::SocCritical);
+                let settings = match settings.try_get() {
+                    Some(s) => s,
+                    None => continue,
+                };
+
+                let prev = state_task.try_get().unwrap_or(State::Okay);
+                let next = prev.next(critical, since.elapsed(), &settings);
+
+                if next == prev {
+                    continue;
+                }
+
+                match (prev, next) {
+                    (State::Critical, State::Tripped) => {
+                        let taken = loads.lock(&settings);
+
+                        if !taken.is_empty() {
+                            warn!("TAC is critically hot. Switching off loads: {taken:?}");
+                        }
+
+                        switched_off_task.set(taken);
+                    }
+                    (State::CoolingDown, State::Okay) => {
+                        let taken = switched_off_task.try_get().unwrap_or_default();
+
+                        if settings.auto_reenable && !taken.is_empty() {
+                            info!("TAC has cooled down. Switching loads back on: {taken:?}");
+                        }
+
+                        loads.release(&taken, settings.auto_reenable);
+                        switched_off_task.set(Vec::new());
+                    }
+                    _ => {}
+                }
+
+                since = Instant::now();
+                state_task.set(next);
+            }
+        });
+
+        Self { switched_off }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Load, Loads, Settings, State, ThermalLock};
+    use crate::broker::Topic;
+    use crate::dut_power::OutputState;
+    use crate::usb_hub::UsbPort;
+
+    const SETTINGS: Settings = Settings {
+        dut_power: true,
+        usb_ports: true,
+        iobus: true,
+        trip_delay: 60,
+        cool_down: 300,
+        auto_reenable: true,
+    };
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn state_machine() {
+        // Short critical phases do not trip the protection
+        assert_eq!(State::Okay.next(false, secs(1000), &SETTINGS), State::Okay);
+        assert_eq!(State::Okay.next(true, secs(0), &SETTINGS), State::Critical);
+        assert_eq!(
+            State::Critical.next(true, secs(59), &SETTINGS),
+            State::Critical
+        );
+        assert_eq!(
+            State::Critical.next(false, secs(59), &SETTINGS),
+            State::Okay
+        );
+
+        // Staying critical for trip_delay does
+        assert_eq!(
+            State::Critical.next(true, secs(60), &SETTINGS),
+            State::Tripped
+        );
+        assert_eq!(
+            State::Tripped.next(true, secs(1000), &SETTINGS),
+            State::Tripped
+        );
+
+        // The temperature has to stay below critical for cool_down to
+        // release the loads again
+        assert_eq!(
+            State::Tripped.next(false, secs(0), &SETTINGS),
+            State::CoolingDown
+        );
+        assert_eq!(
+            State::CoolingDown.next(false, secs(299), &SETTINGS),
+            State::CoolingDown
+        );
+        assert_eq!(
+            State::CoolingDown.next(true, secs(299), &SETTINGS),
+            State::Tripped
+        );
+        assert_eq!(
+            State::CoolingDown.next(false, secs(300), &SETTINGS),
+            State::Okay
+        );
+
+        assert!(!State::Okay.is_locked());
+        assert!(!State::Critical.is_locked());
+        assert!(State::Tripped.is_locked());
+        assert!(State::CoolingDown.is_locked());
+    }
+
+    fn usb_port(powered: bool) -> UsbPort {
+        UsbPort {
+            request: Topic::anonymous(None),
+            status: Topic::anonymous(Some(powered)),
+            device: Topic::anonymous(Some(None)),
+            thermal_lock: Topic::anonymous(Some(ThermalLock::Released)),
+            overtemperature: Topic::anonymous(Some(false)),
+        }
+    }
+
+    fn test_loads() -> Loads {
+        Loads {
+            dut_pwr_lock: Topic::anonymous(Some(ThermalLock::Released)),
+            dut_pwr_state: Topic::anonymous(Some(OutputState::On)),
+            usb_ports: [usb_port(true), usb_port(false), usb_port(true)],
+            iobus_pwr_en: Topic::anonymous(Some(true)),
+        }
+    }
+
+    fn locks(loads: &Loads) -> Vec<Option<ThermalLock>> {
+        let mut locks = vec![loads.dut_pwr_lock.try_get()];
+        locks.extend(loads.usb_ports.iter().map(|p| p.thermal_lock.try_get()));
+        locks
+    }
+
+    #[test]
+    fn lock_and_release() {
+        let loads = test_loads();
+
+        // Only loads that were on are reported as switched off,
+        // but all selected loads are locked.
+        let taken = loads.lock(&SETTINGS);
+        assert_eq!(
+            taken,
+            vec![Load::DutPower, Load::UsbPort1, Load::UsbPort3, Load::IoBus]
+        );
+        assert_eq!(locks(&loads), vec![Some(ThermalLock::Locked); 4]);
+        assert_eq!(loads.iobus_pwr_en.try_get(), Some(false));
+
+        loads.release(&taken, true);
+        assert_eq!(locks(&loads), vec![Some(ThermalLock::Restore); 4]);
+        assert_eq!(loads.iobus_pwr_en.try_get(), Some(true));
+
+        // Do not switch loads on if auto_reenable is not set
+        let taken = loads.lock(&SETTINGS);
+        loads.release(&taken, false);
+        assert_eq!(locks(&loads), vec![Some(ThermalLock::Released); 4]);
+        assert_eq!(loads.iobus_pwr_en.try_get(), Some(false));
+
+        // Do not touch loads that are not selected
+        let loads = test_loads();
+        let settings = Settings {
+            dut_power: false,
+            iobus: false,
+            ..SETTINGS
+        };
+
+        let taken = loads.lock(&settings);
+        assert_eq!(taken, vec![Load::UsbPort1, Load::UsbPort3]);
+        assert_eq!(loads.dut_pwr_lock.try_get(), Some(ThermalLock::Released));
+        assert_eq!(loads.iobus_pwr_en.try_get(), Some(true));
+
+        // Do not switch the IOBus on if it was off in the first place
+        let loads = test_loads();
+        loads.iobus_pwr_en.set(false);
+
+        let taken = loads.lock(&SETTINGS);
+        assert!(!taken.contains(&Load::IoBus));
+
+        loads.release(&taken, true);
+        assert_eq!(loads.iobus_pwr_en.try_get(), Some(false));
+    }
+}
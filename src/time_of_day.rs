@@ -0,0 +1,61 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::convert::TryFrom;
+
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+
+/// A wall clock time of day that is serialized as "HH:MM"
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay(NaiveTime);
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = chrono::ParseError;
+
+    fn try_from(time: String) -> Result<Self, Self::Error> {
+        NaiveTime::parse_from_str(&time, "%H:%M").map(Self)
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        time.0.format("%H:%M").to_string()
+    }
+}
+
+impl TimeOfDay {
+    pub fn new(hour: u32, minute: u32) -> Self {
+        Self(NaiveTime::from_hms_opt(hour, minute, 0).unwrap())
+    }
+
+    pub fn time(self) -> NaiveTime {
+        self.0
+    }
+
+    /// Is `now` between `start` and `end`?
+    ///
+    /// The time span may wrap around midnight, e.g. from 22:00 to 06:00.
+    pub fn span_contains(start: Self, end: Self, now: NaiveTime) -> bool {
+        if start.0 <= end.0 {
+            start.0 <= now && now < end.0
+        } else {
+            start.0 <= now || now < end.0
+        }
+    }
+}
@@ -41,6 +41,7 @@ use screens::{splash, ActivatableScreen, AlertScreen, NormalScreen, Screen};
 pub struct UiResources {
     pub adc: crate::adc::Adc,
     pub backlight: crate::backlight::Backlight,
+    pub diagnostics: crate::diagnostics::Diagnostics,
     pub dig_io: crate::digital_io::DigitalIo,
     pub dut_pwr: crate::dut_power::DutPwrThread,
     pub iobus: crate::iobus::IoBus,
@@ -52,6 +53,7 @@ pub struct UiResources {
     pub system: crate::system::System,
     pub systemd: crate::dbus::Systemd,
     pub temperatures: crate::temperatures::Temperatures,
+    pub thermal_protection: crate::thermal_protection::ThermalProtection,
     pub usb_hub: crate::usb_hub::UsbHub,
 }
 
@@ -139,6 +141,9 @@ impl Ui {
             buttons.clone(),
         );
 
+        // Dim the backlight if no buttons were pressed for some time
+        res.backlight.dim_on_inactivity(&buttons);
+
         // Blink the status LED when locator is active
         let led_status_pattern = res.led.status.clone();
         let led_status_color = res.led.status_color.clone();
@@ -27,16 +27,21 @@ use embedded_graphics::{
 use serde::{Deserialize, Serialize};
 
 mod dig_out;
+mod display_settings;
 mod help;
 mod iobus;
 mod iobus_health;
+mod lldp;
 mod locator;
 mod overtemperature;
 mod power;
 mod power_fail;
 mod reboot;
+mod resource_warning;
 mod screensaver;
 mod setup;
+mod slot_confirm;
+mod slots;
 mod system;
 mod uart;
 mod update_available;
@@ -45,16 +50,21 @@ mod usb;
 mod usb_overload;
 
 use dig_out::DigOutScreen;
+use display_settings::DisplaySettingsScreen;
 use help::HelpScreen;
 use iobus::IoBusScreen;
 use iobus_health::IoBusHealthScreen;
+use lldp::LldpScreen;
 use locator::LocatorScreen;
 use overtemperature::OverTemperatureScreen;
 use power::PowerScreen;
 use power_fail::PowerFailScreen;
 use reboot::RebootConfirmScreen;
+use resource_warning::ResourceWarningScreen;
 use screensaver::ScreenSaverScreen;
 use setup::SetupScreen;
+use slot_confirm::SlotConfirmScreen;
+use slots::SlotsScreen;
 use system::SystemScreen;
 use uart::UartScreen;
 use update_available::UpdateAvailableScreen;
@@ -76,17 +86,22 @@ pub enum NormalScreen {
     Usb,
     DigOut,
     System,
+    Slots,
+    Lldp,
     IoBus,
     Uart,
+    DisplaySettings,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, PartialOrd, Eq, Ord, Clone, Copy, Debug)]
 pub enum AlertScreen {
     ScreenSaver,
+    ResourceWarning,
     IoBusHealth,
     PowerFail,
     Locator,
     RebootConfirm,
+    SlotConfirm,
     UpdateAvailable,
     UpdateInstallation,
     UsbOverload,
@@ -112,9 +127,12 @@ impl NormalScreen {
             Self::DutPower => Self::Usb,
             Self::Usb => Self::DigOut,
             Self::DigOut => Self::System,
-            Self::System => Self::IoBus,
+            Self::System => Self::Slots,
+            Self::Slots => Self::Lldp,
+            Self::Lldp => Self::IoBus,
             Self::IoBus => Self::Uart,
-            Self::Uart => Self::DutPower,
+            Self::Uart => Self::DisplaySettings,
+            Self::DisplaySettings => Self::DutPower,
         }
     }
 }
@@ -149,7 +167,7 @@ fn draw_border(text: &str, screen: NormalScreen, display: &Display) {
             .unwrap();
 
         let screen_idx = screen as i32;
-        let num_screens = (NormalScreen::Uart as i32) + 1;
+        let num_screens = (NormalScreen::DisplaySettings as i32) + 1;
         let x_start = screen_idx * 240 / num_screens;
         let x_end = (screen_idx + 1) * 240 / num_screens;
 
@@ -188,10 +206,16 @@ pub(super) fn init(
     reboot_message: &Arc<Topic<Option<String>>>,
     locator: &Arc<Topic<bool>>,
 ) -> Vec<Box<dyn ActivatableScreen>> {
+    // The name of the slot to boot into after confirmation
+    let boot_request = Topic::anonymous(None);
+
     vec![
         Box::new(DigOutScreen::new()),
+        Box::new(DisplaySettingsScreen::new()),
         Box::new(IoBusScreen::new()),
+        Box::new(LldpScreen::new()),
         Box::new(PowerScreen::new()),
+        Box::new(SlotsScreen::new(&boot_request)),
         Box::new(SystemScreen::new()),
         Box::new(UartScreen::new()),
         Box::new(UsbScreen::new()),
@@ -203,8 +227,13 @@ pub(super) fn init(
             reboot_message,
             &res.rauc.should_reboot,
         )),
-        Box::new(UpdateAvailableScreen::new(alerts, &res.rauc.channels)),
+        Box::new(UpdateAvailableScreen::new(
+            alerts,
+            &res.rauc.channels,
+            &res.rauc.policies,
+        )),
         Box::new(RebootConfirmScreen::new(alerts, reboot_message)),
+        Box::new(SlotConfirmScreen::new(alerts, &boot_request)),
         Box::new(ScreenSaverScreen::new(buttons, alerts)),
         Box::new(SetupScreen::new(alerts, &res.setup_mode.setup_mode)),
         Box::new(OverTemperatureScreen::new(
@@ -213,6 +242,10 @@ pub(super) fn init(
         )),
         Box::new(LocatorScreen::new(alerts, locator)),
         Box::new(UsbOverloadScreen::new(alerts, &res.usb_hub.overload)),
+        Box::new(ResourceWarningScreen::new(
+            alerts,
+            &res.system.resource_warnings,
+        )),
         Box::new(PowerFailScreen::new(alerts, &res.dut_pwr.state)),
     ]
 }
@@ -0,0 +1,216 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use async_std::sync::Arc;
+use async_trait::async_trait;
+use embedded_graphics::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::widgets::*;
+use super::{
+    draw_border, row_anchor, ActivatableScreen, ActiveScreen, Display, InputEvent, NormalScreen,
+    Screen, Ui,
+};
+use crate::backlight::NightSchedule;
+use crate::broker::Topic;
+
+const SCREEN_TYPE: NormalScreen = NormalScreen::DisplaySettings;
+const OFFSET_VALUE: Point = Point::new(150, 0);
+const OFFSET_INDICATOR: Point = Point::new(170, -10);
+
+// The values to cycle through when changing a setting on the local UI.
+// Other values can be set via the API.
+const BRIGHTNESS_STEPS: &[f32] = &[0.1, 0.25, 0.5, 0.75, 1.0];
+const DIM_TIMEOUT_STEPS: &[u64] = &[0, 60, 300, 600, 1800, 3600];
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+enum Setting {
+    Brightness,
+    DimTimeout,
+    NightSchedule,
+}
+
+impl Setting {
+    fn next(&self) -> Self {
+        match self {
+            Self::Brightness => Self::DimTimeout,
+            Self::DimTimeout => Self::NightSchedule,
+            Self::NightSchedule => Self::Brightness,
+        }
+    }
+}
+
+/// Get the next larger value from steps or wrap around to the first one
+fn next_step<T: PartialOrd + Copy>(steps: &[T], current: T) -> T {
+    steps
+        .iter()
+        .find(|step| **step > current)
+        .unwrap_or(&steps[0])
+        .to_owned()
+}
+
+pub struct DisplaySettingsScreen {
+    highlighted: Arc<Topic<Setting>>,
+}
+
+impl DisplaySettingsScreen {
+    pub fn new() -> Self {
+        Self {
+            highlighted: Topic::anonymous(Some(Setting::Brightness)),
+        }
+    }
+}
+
+struct Active {
+    widgets: WidgetContainer,
+    highlighted: Arc<Topic<Setting>>,
+    default_brightness: Arc<Topic<f32>>,
+    dim_timeout: Arc<Topic<u64>>,
+    night_schedule: Arc<Topic<NightSchedule>>,
+}
+
+impl ActivatableScreen for DisplaySettingsScreen {
+    fn my_type(&self) -> Screen {
+        Screen::Normal(SCREEN_TYPE)
+    }
+
+    fn activate(&mut self, ui: &Ui, display: Display) -> Box<dyn ActiveScreen> {
+        draw_border("Display", SCREEN_TYPE, &display);
+
+        let mut widgets = WidgetContainer::new(display);
+        let backlight = &ui.res.backlight;
+
+        let labels = [
+            (0, Setting::Brightness, "Brightness:"),
+            (2, Setting::DimTimeout, "Dim after:"),
+            (4, Setting::NightSchedule, "Night mode:"),
+        ];
+
+        for (row, setting, label) in labels {
+            widgets.push(|display| {
+                DynamicWidget::text(
+                    self.highlighted.clone(),
+                    display,
+                    row_anchor(row),
+                    Box::new(move |highlight| {
+                        if *highlight == setting {
+                            format!("> {label}")
+                        } else {
+                            format!("  {label}")
+                        }
+                    }),
+                )
+            });
+        }
+
+        widgets.push(|display| {
+            DynamicWidget::text(
+                backlight.default_brightness.clone(),
+                display,
+                row_anchor(0) + OFFSET_VALUE,
+                Box::new(|brightness: &f32| format!("{:>4.0}%", brightness * 100.0)),
+            )
+        });
+
+        widgets.push(|display| {
+            DynamicWidget::text(
+                backlight.dim_timeout.clone(),
+                display,
+                row_anchor(2) + OFFSET_VALUE,
+                Box::new(|secs: &u64| match secs {
+                    0 => "Never".into(),
+                    s => format!("{}min", s / 60),
+                }),
+            )
+        });
+
+        widgets.push(|display| {
+            DynamicWidget::indicator(
+                backlight.night_schedule.clone(),
+                display,
+                row_anchor(4) + OFFSET_INDICATOR,
+                Box::new(|schedule: &NightSchedule| match schedule.enabled {
+                    true => IndicatorState::On,
+                    false => IndicatorState::Off,
+                }),
+            )
+        });
+
+        widgets.push(|display| {
+            DynamicWidget::text(
+                backlight.night_schedule.clone(),
+                display,
+                row_anchor(5),
+                Box::new(|schedule: &NightSchedule| {
+                    let start: String = schedule.start.into();
+                    let end: String = schedule.end.into();
+
+                    format!("  {start} - {end}")
+                }),
+            )
+        });
+
+        let highlighted = self.highlighted.clone();
+        let default_brightness = backlight.default_brightness.clone();
+        let dim_timeout = backlight.dim_timeout.clone();
+        let night_schedule = backlight.night_schedule.clone();
+
+        let active = Active {
+            widgets,
+            highlighted,
+            default_brightness,
+            dim_timeout,
+            night_schedule,
+        };
+
+        Box::new(active)
+    }
+}
+
+#[async_trait]
+impl ActiveScreen for Active {
+    fn my_type(&self) -> Screen {
+        Screen::Normal(SCREEN_TYPE)
+    }
+
+    async fn deactivate(mut self: Box<Self>) -> Display {
+        self.widgets.destroy().await
+    }
+
+    fn input(&mut self, ev: InputEvent) {
+        let highlighted = self.highlighted.try_get().unwrap_or(Setting::Brightness);
+
+        match ev {
+            InputEvent::NextScreen => {}
+            InputEvent::ToggleAction(_) => self.highlighted.set(highlighted.next()),
+            InputEvent::PerformAction(_) => match highlighted {
+                Setting::Brightness => self
+                    .default_brightness
+                    .modify(|prev| Some(next_step(BRIGHTNESS_STEPS, prev.unwrap_or(1.0)))),
+                Setting::DimTimeout => self
+                    .dim_timeout
+                    .modify(|prev| Some(next_step(DIM_TIMEOUT_STEPS, prev.unwrap_or(0)))),
+                Setting::NightSchedule => self.night_schedule.modify(|prev| {
+                    prev.map(|mut schedule| {
+                        schedule.enabled = !schedule.enabled;
+                        schedule
+                    })
+                }),
+            },
+        }
+    }
+}
@@ -0,0 +1,127 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use async_trait::async_trait;
+use embedded_graphics::{
+    mono_font::MonoTextStyle, pixelcolor::BinaryColor, prelude::*, text::Text,
+};
+
+use super::widgets::*;
+use super::{
+    draw_border, row_anchor, ActivatableScreen, ActiveScreen, Display, InputEvent, NormalScreen,
+    Screen, Ui,
+};
+use crate::dbus::networkmanager::LldpNeighbor;
+
+const SCREEN_TYPE: NormalScreen = NormalScreen::Lldp;
+
+pub struct LldpScreen;
+
+impl LldpScreen {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+struct Active {
+    widgets: WidgetContainer,
+}
+
+/// Describe the switch port an interface is plugged into in three lines
+fn describe(neighbors: &[LldpNeighbor]) -> String {
+    let neighbor = match neighbors.first() {
+        Some(n) => n,
+        None => return "  No neighbor".to_string(),
+    };
+
+    let system = neighbor
+        .system_name
+        .as_deref()
+        .or(neighbor.chassis_id.as_deref())
+        .unwrap_or("-");
+
+    let port = neighbor
+        .port_description
+        .as_deref()
+        .or(neighbor.port_id.as_deref())
+        .unwrap_or("-");
+
+    let vlan = neighbor
+        .vlan_id
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "-".to_string());
+
+    format!("  {system}\n  Port: {port}\n  VLAN: {vlan}")
+}
+
+impl ActivatableScreen for LldpScreen {
+    fn my_type(&self) -> Screen {
+        Screen::Normal(SCREEN_TYPE)
+    }
+
+    fn activate(&mut self, ui: &Ui, display: Display) -> Box<dyn ActiveScreen> {
+        draw_border("Switch Ports", SCREEN_TYPE, &display);
+
+        let ui_text_style: MonoTextStyle<BinaryColor> =
+            MonoTextStyle::new(&UI_TEXT_FONT, BinaryColor::On);
+
+        display.with_lock(|target| {
+            Text::new("Uplink:", row_anchor(0), ui_text_style)
+                .draw(target)
+                .unwrap();
+
+            Text::new("DUT:", row_anchor(5), ui_text_style)
+                .draw(target)
+                .unwrap();
+        });
+
+        let mut widgets = WidgetContainer::new(display);
+
+        widgets.push(|display| {
+            DynamicWidget::text(
+                ui.res.network.uplink_lldp.clone(),
+                display,
+                row_anchor(1),
+                Box::new(|neighbors: &Vec<LldpNeighbor>| describe(neighbors)),
+            )
+        });
+
+        widgets.push(|display| {
+            DynamicWidget::text(
+                ui.res.network.dut_lldp.clone(),
+                display,
+                row_anchor(6),
+                Box::new(|neighbors: &Vec<LldpNeighbor>| describe(neighbors)),
+            )
+        });
+
+        Box::new(Active { widgets })
+    }
+}
+
+#[async_trait]
+impl ActiveScreen for Active {
+    fn my_type(&self) -> Screen {
+        Screen::Normal(SCREEN_TYPE)
+    }
+
+    async fn deactivate(mut self: Box<Self>) -> Display {
+        self.widgets.destroy().await
+    }
+
+    fn input(&mut self, _ev: InputEvent) {}
+}
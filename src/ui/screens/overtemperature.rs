@@ -31,6 +31,7 @@ use super::{
 use crate::broker::Topic;
 use crate::measurement::Measurement;
 use crate::temperatures::Warning;
+use crate::thermal_protection::Load;
 
 const SCREEN_TYPE: AlertScreen = AlertScreen::OverTemperature;
 
@@ -48,7 +49,9 @@ impl OverTemperatureScreen {
         spawn(async move {
             while let Some(warning) = warning_events.next().await {
                 match warning {
-                    Warning::Okay => alerts.deassert(SCREEN_TYPE),
+                    // The alert asks the user to provide more airflow,
+                    // which does not help if the sensor is broken.
+                    Warning::Okay | Warning::SocSensorError => alerts.deassert(SCREEN_TYPE),
                     Warning::SocHigh | Warning::SocCritical => alerts.assert(SCREEN_TYPE),
                 }
             }
@@ -87,6 +90,34 @@ impl ActivatableScreen for OverTemperatureScreen {
 
         let mut widgets = WidgetContainer::new(display);
 
+        widgets.push(|display| {
+            DynamicWidget::text(
+                ui.res.thermal_protection.switched_off.clone(),
+                display,
+                row_anchor(5),
+                Box::new(|switched_off: &Vec<Load>| {
+                    let mut loads: Vec<&str> = Vec::new();
+
+                    for load in switched_off {
+                        let load = match load {
+                            Load::DutPower => "DUT",
+                            Load::UsbPort1 | Load::UsbPort2 | Load::UsbPort3 => "USB",
+                            Load::IoBus => "IOBus",
+                        };
+
+                        if !loads.contains(&load) {
+                            loads.push(load);
+                        }
+                    }
+
+                    match loads.is_empty() {
+                        true => String::new(),
+                        false => format!("Off: {}", loads.join(", ")),
+                    }
+                }),
+            )
+        });
+
         widgets.push(|display| {
             DynamicWidget::text_center(
                 ui.res.temperatures.soc_temperature.clone(),
@@ -114,6 +114,7 @@ impl ActivatableScreen for PowerScreen {
                     OutputState::OverCurrent => "> Ov. Curr.".into(),
                     OutputState::OverVoltage => "> Ov. Volt.".into(),
                     OutputState::RealtimeViolation => "> Rt Err.".into(),
+                    OutputState::OverTemperature => "> Ov. Temp.".into(),
                 }),
             )
         });
@@ -72,7 +72,8 @@ impl PowerFailScreen {
                     OutputState::InvertedPolarity
                     | OutputState::OverCurrent
                     | OutputState::OverVoltage
-                    | OutputState::RealtimeViolation => alerts.assert(SCREEN_TYPE),
+                    | OutputState::RealtimeViolation
+                    | OutputState::OverTemperature => alerts.assert(SCREEN_TYPE),
                     OutputState::Changing => {}
                 }
             }
@@ -121,6 +122,7 @@ impl ActivatableScreen for PowerFailScreen {
                         OutputState::RealtimeViolation => {
                             "Output disabled due to\na realtime violation."
                         }
+                        OutputState::OverTemperature => "DUT powered off as the\nTAC is too hot.",
                         OutputState::Changing => "",
                     };
 
@@ -63,7 +63,7 @@ impl RebootConfirmScreen {
     }
 }
 
-fn rly(text: &str, display: &Display) {
+pub(super) fn rly(text: &str, display: &Display) {
     let text_style: MonoTextStyle<BinaryColor> = MonoTextStyle::new(&UI_TEXT_FONT, BinaryColor::On);
 
     display.with_lock(|target| {
@@ -73,7 +73,7 @@ fn rly(text: &str, display: &Display) {
     });
 }
 
-fn brb(display: &Display) {
+pub(super) fn brb(display: &Display) {
     let text_style: MonoTextStyle<BinaryColor> = MonoTextStyle::new(&UI_TEXT_FONT, BinaryColor::On);
 
     display.clear();
@@ -0,0 +1,150 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use async_std::prelude::*;
+use async_std::sync::Arc;
+use async_std::task::spawn;
+use async_trait::async_trait;
+use embedded_graphics::{
+    mono_font::MonoTextStyle, pixelcolor::BinaryColor, prelude::*, text::Text,
+};
+
+use super::widgets::*;
+use super::{
+    row_anchor, ActivatableScreen, ActiveScreen, AlertList, AlertScreen, Alerter, Display,
+    InputEvent, Screen, Ui,
+};
+use crate::broker::Topic;
+use crate::system::ResourceWarning;
+
+const SCREEN_TYPE: AlertScreen = AlertScreen::ResourceWarning;
+
+pub struct ResourceWarningScreen;
+
+struct Active {
+    widgets: WidgetContainer,
+    alerts: Arc<Topic<AlertList>>,
+}
+
+impl ResourceWarningScreen {
+    pub fn new(
+        alerts: &Arc<Topic<AlertList>>,
+        warnings: &Arc<Topic<Vec<ResourceWarning>>>,
+    ) -> Self {
+        let (mut warnings_events, _) = warnings.clone().subscribe_unbounded();
+        let alerts = alerts.clone();
+
+        // The alert is asserted again if a new kind of warning appears,
+        // even if it was dismissed before.
+        // Warnings that go away and come back while others are still active
+        // do not re-assert it.
+        spawn(async move {
+            let mut shown = Vec::new();
+
+            while let Some(warnings) = warnings_events.next().await {
+                if warnings.is_empty() {
+                    alerts.deassert(SCREEN_TYPE);
+                    shown.clear();
+                } else {
+                    let new: Vec<ResourceWarning> = warnings
+                        .into_iter()
+                        .filter(|w| !shown.contains(w))
+                        .collect();
+
+                    if !new.is_empty() {
+                        alerts.assert(SCREEN_TYPE);
+                        shown.extend(new);
+                    }
+                }
+            }
+        });
+
+        Self
+    }
+}
+
+impl ActivatableScreen for ResourceWarningScreen {
+    fn my_type(&self) -> Screen {
+        Screen::Alert(SCREEN_TYPE)
+    }
+
+    fn activate(&mut self, ui: &Ui, display: Display) -> Box<dyn ActiveScreen> {
+        let ui_text_style: MonoTextStyle<BinaryColor> =
+            MonoTextStyle::new(&UI_TEXT_FONT, BinaryColor::On);
+
+        display.with_lock(|target| {
+            Text::new(
+                "System resources low",
+                row_anchor(0) - (row_anchor(1) - row_anchor(0)),
+                ui_text_style,
+            )
+            .draw(target)
+            .unwrap();
+
+            Text::new("> Dismiss", row_anchor(8), ui_text_style)
+                .draw(target)
+                .unwrap();
+        });
+
+        let mut widgets = WidgetContainer::new(display);
+
+        widgets.push(|display| {
+            DynamicWidget::text(
+                ui.res.system.resource_warnings.clone(),
+                display,
+                row_anchor(1),
+                Box::new(|warnings: &Vec<ResourceWarning>| {
+                    let lines: Vec<&str> = warnings
+                        .iter()
+                        .map(|w| match w {
+                            ResourceWarning::HighLoad => "- High CPU load",
+                            ResourceWarning::MemoryLow => "- Memory is low",
+                            ResourceWarning::SrvFull => "- /srv is almost full",
+                            ResourceWarning::RootfsFull => "- Rootfs almost full",
+                        })
+                        .collect();
+
+                    lines.join("\n")
+                }),
+            )
+        });
+
+        let alerts = ui.alerts.clone();
+
+        Box::new(Active { widgets, alerts })
+    }
+}
+
+#[async_trait]
+impl ActiveScreen for Active {
+    fn my_type(&self) -> Screen {
+        Screen::Alert(SCREEN_TYPE)
+    }
+
+    async fn deactivate(mut self: Box<Self>) -> Display {
+        self.widgets.destroy().await
+    }
+
+    fn input(&mut self, ev: InputEvent) {
+        match ev {
+            InputEvent::NextScreen | InputEvent::ToggleAction(_) => {}
+            InputEvent::PerformAction(_) => {
+                self.alerts.deassert(SCREEN_TYPE);
+            }
+        }
+    }
+}
@@ -119,7 +119,7 @@ struct Active {
     widgets: WidgetContainer,
     locator: Arc<Topic<bool>>,
     alerts: Arc<Topic<AlertList>>,
-    brightness: Arc<Topic<f32>>,
+    screensaver: Arc<Topic<bool>>,
 }
 
 impl ActivatableScreen for ScreenSaverScreen {
@@ -160,16 +160,16 @@ impl ActivatableScreen for ScreenSaverScreen {
 
         let locator = ui.locator.clone();
         let alerts = ui.alerts.clone();
-        let brightness = ui.res.backlight.brightness.clone();
+        let screensaver = ui.res.backlight.screensaver.clone();
 
-        // Dim to 10% brightness in screensaver mode
-        brightness.set(0.1);
+        // Dim the backlight in screensaver mode
+        screensaver.set(true);
 
         let active = Active {
             widgets,
             locator,
             alerts,
-            brightness,
+            screensaver,
         };
 
         Box::new(active)
@@ -183,8 +183,8 @@ impl ActiveScreen for Active {
     }
 
     async fn deactivate(mut self: Box<Self>) -> Display {
-        // Restore full backlight brightness
-        self.brightness.set(1.0);
+        // Restore the backlight brightness
+        self.screensaver.set(false);
         self.widgets.destroy().await
     }
 
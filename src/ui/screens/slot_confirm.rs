@@ -0,0 +1,151 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::time::Duration;
+
+use async_std::future::timeout;
+use async_std::prelude::*;
+use async_std::sync::Arc;
+use async_std::task::spawn;
+use async_trait::async_trait;
+use log::warn;
+
+use super::reboot::{brb, rly};
+use super::{
+    ActivatableScreen, ActiveScreen, AlertList, AlertScreen, Alerter, Display, InputEvent, Screen,
+    Ui,
+};
+use crate::broker::Topic;
+use crate::dbus::rauc::{MarkRequest, SlotMark};
+
+const SCREEN_TYPE: AlertScreen = AlertScreen::SlotConfirm;
+
+// How long to wait for RAUC to make the requested slot the primary one
+const MARK_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct SlotConfirmScreen {
+    boot_request: Arc<Topic<Option<String>>>,
+}
+
+impl SlotConfirmScreen {
+    pub fn new(alerts: &Arc<Topic<AlertList>>, boot_request: &Arc<Topic<Option<String>>>) -> Self {
+        // Receive the name of the slot to boot into and activate this screen
+        let (mut boot_request_events, _) = boot_request.clone().subscribe_unbounded();
+        let boot_request = boot_request.clone();
+        let alerts = alerts.clone();
+
+        spawn(async move {
+            while let Some(boot_request) = boot_request_events.next().await {
+                if boot_request.is_some() {
+                    alerts.assert(SCREEN_TYPE);
+                } else {
+                    alerts.deassert(SCREEN_TYPE);
+                }
+            }
+        });
+
+        Self { boot_request }
+    }
+}
+
+struct Active {
+    display: Display,
+    slot: String,
+    mark: Arc<Topic<MarkRequest>>,
+    primary: Arc<Topic<String>>,
+    reboot: Arc<Topic<bool>>,
+    boot_request: Arc<Topic<Option<String>>>,
+}
+
+impl ActivatableScreen for SlotConfirmScreen {
+    fn my_type(&self) -> Screen {
+        Screen::Alert(SCREEN_TYPE)
+    }
+
+    fn activate(&mut self, ui: &Ui, display: Display) -> Box<dyn ActiveScreen> {
+        let slot = self.boot_request.try_get().unwrap().unwrap();
+
+        rly(
+            &format!("Boot into slot\n{slot}?\nLong press lower\nbutton to confirm."),
+            &display,
+        );
+
+        let active = Active {
+            display,
+            slot,
+            mark: ui.res.rauc.mark.clone(),
+            primary: ui.res.rauc.primary.clone(),
+            reboot: ui.res.systemd.reboot.clone(),
+            boot_request: self.boot_request.clone(),
+        };
+
+        Box::new(active)
+    }
+}
+
+#[async_trait]
+impl ActiveScreen for Active {
+    fn my_type(&self) -> Screen {
+        Screen::Alert(SCREEN_TYPE)
+    }
+
+    async fn deactivate(mut self: Box<Self>) -> Display {
+        self.display
+    }
+
+    fn input(&mut self, ev: InputEvent) {
+        match ev {
+            InputEvent::NextScreen | InputEvent::ToggleAction(_) => self.boot_request.set(None),
+            InputEvent::PerformAction(_) => {
+                brb(&self.display);
+
+                let slot = self.slot.clone();
+                let primary = self.primary.clone();
+                let reboot = self.reboot.clone();
+                let boot_request = self.boot_request.clone();
+
+                let (mut primary_events, _) = primary.subscribe_unbounded();
+
+                self.mark.set(MarkRequest {
+                    slot: slot.clone(),
+                    state: SlotMark::Active,
+                });
+
+                // Only reboot once RAUC has made the slot the primary one,
+                // otherwise we would just boot into the same slot again.
+                spawn(async move {
+                    let marked = timeout(MARK_TIMEOUT, async {
+                        while let Some(primary) = primary_events.next().await {
+                            if primary == slot {
+                                return;
+                            }
+                        }
+                    })
+                    .await;
+
+                    match marked {
+                        Ok(()) => reboot.set(true),
+                        Err(_) => {
+                            warn!("Slot {slot} did not become the primary slot. Not rebooting");
+                            boot_request.set(None);
+                        }
+                    }
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use async_std::sync::Arc;
+use async_trait::async_trait;
+use embedded_graphics::{
+    mono_font::MonoTextStyle, pixelcolor::BinaryColor, prelude::*, text::Text,
+};
+
+use super::buttons::Source;
+use super::widgets::*;
+use super::{
+    draw_border, row_anchor, ActivatableScreen, ActiveScreen, Display, InputEvent, NormalScreen,
+    Screen, Ui,
+};
+use crate::broker::Topic;
+use crate::dbus::rauc::{other_rootfs, SlotBootAttempts, SlotStatus};
+
+const SCREEN_TYPE: NormalScreen = NormalScreen::Slots;
+const SLOTS: [&str; 2] = ["rootfs.0", "rootfs.1"];
+
+pub struct SlotsScreen {
+    boot_request: Arc<Topic<Option<String>>>,
+}
+
+impl SlotsScreen {
+    pub fn new(boot_request: &Arc<Topic<Option<String>>>) -> Self {
+        Self {
+            boot_request: boot_request.clone(),
+        }
+    }
+}
+
+struct Active {
+    widgets: WidgetContainer,
+    slot_status: Arc<Topic<Arc<SlotStatus>>>,
+    boot_request: Arc<Topic<Option<String>>>,
+}
+
+fn describe_slot(slot_status: &SlotStatus, name: &str) -> String {
+    let info = match slot_status.get(&name.replace('.', "_")) {
+        Some(info) => info,
+        None => return format!("{name}: -"),
+    };
+
+    let state = info.get("state").map(|s| s.as_str()).unwrap_or("-");
+    let boot_status = info.get("boot_status").map(|s| s.as_str()).unwrap_or("-");
+
+    format!("{name}: {state} {boot_status}")
+}
+
+fn describe_attempts(attempts: &SlotBootAttempts, name: &str) -> String {
+    match attempts.get(name) {
+        Some(a) => format!("  prio {}, {} tries", a.priority, a.remaining_attempts),
+        None => "  -".to_string(),
+    }
+}
+
+impl ActivatableScreen for SlotsScreen {
+    fn my_type(&self) -> Screen {
+        Screen::Normal(SCREEN_TYPE)
+    }
+
+    fn activate(&mut self, ui: &Ui, display: Display) -> Box<dyn ActiveScreen> {
+        draw_border("Boot Slots", SCREEN_TYPE, &display);
+
+        let ui_text_style: MonoTextStyle<BinaryColor> =
+            MonoTextStyle::new(&UI_TEXT_FONT, BinaryColor::On);
+
+        display.with_lock(|target| {
+            Text::new("> Boot other slot", row_anchor(8), ui_text_style)
+                .draw(target)
+                .unwrap();
+        });
+
+        let mut widgets = WidgetContainer::new(display);
+
+        for (i, name) in SLOTS.iter().copied().enumerate() {
+            widgets.push(|display| {
+                DynamicWidget::text(
+                    ui.res.rauc.slot_status.clone(),
+                    display,
+                    row_anchor(2 * i as u8),
+                    Box::new(move |slot_status: &Arc<SlotStatus>| describe_slot(slot_status, name)),
+                )
+            });
+
+            widgets.push(|display| {
+                DynamicWidget::text(
+                    ui.res.rauc.boot_attempts.clone(),
+                    display,
+                    row_anchor(2 * i as u8 + 1),
+                    Box::new(move |attempts: &SlotBootAttempts| describe_attempts(attempts, name)),
+                )
+            });
+        }
+
+        widgets.push(|display| {
+            DynamicWidget::text(
+                ui.res.rauc.primary.clone(),
+                display,
+                row_anchor(5),
+                Box::new(|primary: &String| format!("Primary: {primary}")),
+            )
+        });
+
+        let slot_status = ui.res.rauc.slot_status.clone();
+        let boot_request = self.boot_request.clone();
+
+        Box::new(Active {
+            widgets,
+            slot_status,
+            boot_request,
+        })
+    }
+}
+
+#[async_trait]
+impl ActiveScreen for Active {
+    fn my_type(&self) -> Screen {
+        Screen::Normal(SCREEN_TYPE)
+    }
+
+    async fn deactivate(mut self: Box<Self>) -> Display {
+        self.widgets.destroy().await
+    }
+
+    fn input(&mut self, ev: InputEvent) {
+        // Only allow switching slots from the front panel
+        if let InputEvent::PerformAction(Source::Local) = ev {
+            let other = self
+                .slot_status
+                .try_get()
+                .and_then(|slot_status| other_rootfs(&slot_status));
+
+            if let Some(other) = other {
+                self.boot_request.set(Some(other));
+            }
+        }
+    }
+}
@@ -17,6 +17,7 @@
 
 use async_std::sync::Arc;
 use async_trait::async_trait;
+use embedded_graphics::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use super::buttons::Source;
@@ -26,17 +27,21 @@ use super::{
     Display, InputEvent, NormalScreen, Screen, Ui,
 };
 use crate::broker::Topic;
-use crate::dbus::networkmanager::LinkInfo;
+use crate::dbus::networkmanager::{IpInfo, LinkInfo};
 use crate::measurement::Measurement;
 
 const SCREEN_TYPE: NormalScreen = NormalScreen::System;
 
+// The diagnostics entry shares the last row with the updates entry
+const OFFSET_DIAGNOSTICS: Point = Point::new(100, 0);
+
 #[derive(Serialize, Deserialize, Clone, Copy)]
 enum Action {
     Reboot,
     Help,
     SetupMode,
     Updates,
+    Diagnostics,
 }
 
 impl Action {
@@ -45,7 +50,8 @@ impl Action {
             Self::Reboot => Self::Help,
             Self::Help => Self::SetupMode,
             Self::SetupMode => Self::Updates,
-            Self::Updates => Self::Reboot,
+            Self::Updates => Self::Diagnostics,
+            Self::Diagnostics => Self::Reboot,
         }
     }
 }
@@ -65,6 +71,7 @@ struct Active {
     reboot_message: Arc<Topic<Option<String>>>,
     show_help: Arc<Topic<bool>>,
     alerts: Arc<Topic<AlertList>>,
+    save_diagnostics: Arc<Topic<bool>>,
 }
 
 impl ActivatableScreen for SystemScreen {
@@ -123,6 +130,20 @@ impl ActivatableScreen for SystemScreen {
             )
         });
 
+        widgets.push(|display| {
+            DynamicWidget::text(
+                ui.res.network.bridge_ip.clone(),
+                display,
+                row_anchor(4),
+                Box::new(|info: &IpInfo| match info.preferred_ipv6() {
+                    // Long addresses do not fit next to the label
+                    Some(ip) if ip.len() > 15 => ip.to_string(),
+                    Some(ip) => format!("IPv6:   {}", ip),
+                    None => "IPv6:   -".to_string(),
+                }),
+            )
+        });
+
         widgets.push(|display| {
             DynamicWidget::text(
                 highlighted.clone(),
@@ -171,10 +192,23 @@ impl ActivatableScreen for SystemScreen {
             )
         });
 
+        widgets.push(|display| {
+            DynamicWidget::text(
+                highlighted.clone(),
+                display,
+                row_anchor(8) + OFFSET_DIAGNOSTICS,
+                Box::new(|action| match action {
+                    Action::Diagnostics => "> Diagnostics".into(),
+                    _ => "  Diagnostics".into(),
+                }),
+            )
+        });
+
         let reboot_message = ui.reboot_message.clone();
         let setup_mode = ui.res.setup_mode.setup_mode.clone();
         let show_help = ui.res.setup_mode.show_help.clone();
         let alerts = ui.alerts.clone();
+        let save_diagnostics = ui.res.diagnostics.save.clone();
 
         let active = Active {
             widgets,
@@ -183,6 +217,7 @@ impl ActivatableScreen for SystemScreen {
             setup_mode,
             show_help,
             alerts,
+            save_diagnostics,
         };
 
         Box::new(active)
@@ -215,6 +250,7 @@ impl ActiveScreen for Active {
                 Action::Help => self.show_help.set(true),
                 Action::SetupMode => self.setup_mode.set(true),
                 Action::Updates => self.alerts.assert(AlertScreen::UpdateAvailable),
+                Action::Diagnostics => self.save_diagnostics.set(true),
             },
             _ => {}
         }
@@ -30,10 +30,13 @@ use super::{
     InputEvent, Screen, Ui,
 };
 use crate::broker::Topic;
-use crate::dbus::rauc::Channel;
+use crate::dbus::rauc::{mode_of, AutoUpdateMode, AutoUpdatePolicies, Channel};
 
 const SCREEN_TYPE: AlertScreen = AlertScreen::UpdateAvailable;
 
+// The number of characters that fit into a line on the screen
+const MAX_LINE_LEN: usize = 24;
+
 #[derive(Serialize, Deserialize, PartialEq, Clone)]
 enum Highlight {
     Channel(usize),
@@ -72,9 +75,14 @@ impl Selection {
         !self.channels.is_empty()
     }
 
-    fn update_channels(&self, channels: Vec<Channel>) -> Option<Self> {
+    fn update_channels(
+        &self,
+        channels: Vec<Channel>,
+        policies: Option<&AutoUpdatePolicies>,
+    ) -> Option<Self> {
         let channels: Vec<Channel> = channels
             .into_iter()
+            .filter(|ch| mode_of(policies, &ch.name) != AutoUpdateMode::Never)
             .filter(|ch| {
                 ch.bundle
                     .as_ref()
@@ -139,15 +147,32 @@ struct Active {
 }
 
 impl UpdateAvailableScreen {
-    pub fn new(alerts: &Arc<Topic<AlertList>>, channels: &Arc<Topic<Vec<Channel>>>) -> Self {
-        let (mut channels_events, _) = channels.clone().subscribe_unbounded();
+    pub fn new(
+        alerts: &Arc<Topic<AlertList>>,
+        channels: &Arc<Topic<Vec<Channel>>>,
+        policies: &Arc<Topic<AutoUpdatePolicies>>,
+    ) -> Self {
+        let (channels_events, _) = channels.clone().subscribe_unbounded();
+        let (policies_events, _) = policies.clone().subscribe_unbounded();
         let alerts = alerts.clone();
+        let channels = channels.clone();
+        let policies = policies.clone();
         let selection = Topic::anonymous(Some(Selection::new()));
         let selection_task = selection.clone();
 
+        // Channels with an auto update policy of "Never" do not notify
+        // about new bundles.
+        let mut changes = channels_events
+            .map(|_| ())
+            .merge(policies_events.map(|_| ()));
+
         spawn(async move {
-            while let Some(channels) = channels_events.next().await {
-                selection_task.modify(|sel| sel.unwrap().update_channels(channels));
+            while changes.next().await.is_some() {
+                let channels = channels.try_get().unwrap_or_default();
+                let policies = policies.try_get();
+
+                selection_task
+                    .modify(|sel| sel.unwrap().update_channels(channels, policies.as_ref()));
 
                 if selection_task.try_get().unwrap().have_update() {
                     alerts.assert(SCREEN_TYPE);
@@ -215,6 +240,33 @@ impl ActivatableScreen for UpdateAvailableScreen {
                         .draw(target)
                         .unwrap();
 
+                    // Show what the highlighted bundle would bring, if there
+                    // is enough space left on the screen.
+                    let bundle = match sel.highlight {
+                        Highlight::Channel(idx) if num_updates <= 2 => {
+                            sel.channels[idx].bundle.as_ref()
+                        }
+                        _ => None,
+                    };
+
+                    if let Some(bundle) = bundle {
+                        let details = bundle
+                            .release_notes
+                            .as_ref()
+                            .and_then(|notes| notes.lines().next())
+                            .map(|line| line.chars().take(MAX_LINE_LEN).collect())
+                            .or_else(|| bundle.size.map(|s| format!("{} MB", s / 1_000_000)))
+                            .unwrap_or_default();
+
+                        Text::new(&bundle.version, row_anchor(7), ui_text_style)
+                            .draw(target)
+                            .unwrap();
+
+                        Text::new(&details, row_anchor(8), ui_text_style)
+                            .draw(target)
+                            .unwrap();
+                    }
+
                     // Don't bother tracking the actual bounding box and instead
                     // clear the whole screen on update.
                     Some(target.bounding_box())
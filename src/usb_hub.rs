@@ -21,10 +21,13 @@ use std::time::Duration;
 use async_std::prelude::*;
 use async_std::sync::Arc;
 use async_std::task::{sleep, spawn};
+use log::warn;
 use serde::{Deserialize, Serialize};
 
 use crate::adc::CalibratedChannel;
 use crate::broker::{BrokerBuilder, Topic};
+use crate::config::UsbHubConfig;
+use crate::thermal_protection::ThermalLock;
 
 #[cfg(feature = "demo_mode")]
 mod rw {
@@ -119,23 +122,6 @@ mod rw {
 
 use rw::{read_to_string, write};
 
-const POLL_INTERVAL: Duration = Duration::from_secs(1);
-
-const PORTS: &[(&str, &str)] = &[
-    (
-        "port1",
-        "/sys/devices/platform/soc/5800d000.usb/usb1/1-1/1-1:1.0/1-1-port1",
-    ),
-    (
-        "port2",
-        "/sys/devices/platform/soc/5800d000.usb/usb1/1-1/1-1:1.0/1-1-port2",
-    ),
-    (
-        "port3",
-        "/sys/devices/platform/soc/5800d000.usb/usb1/1-1/1-1:1.0/1-1-port3",
-    ),
-];
-
 // The total current for all ports is limited to 700mA, the per-port current is
 // limited to 500mA.
 // The measurement is not _that_ exact so start warning at 90% utilization.
@@ -190,6 +176,8 @@ pub struct UsbPort {
     pub request: Arc<Topic<bool>>,
     pub status: Arc<Topic<bool>>,
     pub device: Arc<Topic<Option<UsbDevice>>>,
+    pub thermal_lock: Arc<Topic<ThermalLock>>,
+    pub overtemperature: Arc<Topic<bool>>,
 }
 
 pub struct UsbHub {
@@ -199,32 +187,96 @@ pub struct UsbHub {
     pub port3: UsbPort,
 }
 
-fn handle_port(bb: &mut BrokerBuilder, name: &'static str, base: &'static str) -> UsbPort {
+/// Turn USB port power on or off
+///
+/// Also clears the device info upon power off so it does not contain stale
+/// information until the next poll.
+fn set_powered(
+    disable_path: &Path,
+    status: &Topic<bool>,
+    device: &Topic<Option<UsbDevice>>,
+    powered: bool,
+) {
+    write(disable_path, if powered { b"0" } else { b"1" }).unwrap();
+
+    if !powered {
+        device.set(None);
+    }
+
+    status.set(powered);
+}
+
+fn handle_port(
+    bb: &mut BrokerBuilder,
+    name: &'static str,
+    base: &str,
+    poll_interval: Duration,
+) -> UsbPort {
     let port = UsbPort {
         request: bb.topic_wo(format!("/v1/usb/host/{name}/powered").as_str(), None),
         status: bb.topic_ro(format!("/v1/usb/host/{name}/powered").as_str(), None),
         device: bb.topic_ro(format!("/v1/usb/host/{name}/device").as_str(), Some(None)),
+        thermal_lock: Topic::anonymous(Some(ThermalLock::Released)),
+        overtemperature: bb.topic_ro(
+            format!("/v1/usb/host/{name}/overtemperature").as_str(),
+            Some(false),
+        ),
     };
 
     let request = port.request.clone();
     let status = port.status.clone();
     let device = port.device.clone();
+    let thermal_lock = port.thermal_lock.clone();
+    let overtemperature = port.overtemperature.clone();
     let disable_path = Path::new(base).join("disable");
 
     // Spawn a task that turns USB port power on or off upon request.
-    // Also clears the device info upon power off so it does not contain stale
-    // information until the next poll.
+    // Requests to turn the port on are rejected while the thermal protection
+    // keeps it switched off.
     spawn(async move {
         let (mut src, _) = request.subscribe_unbounded();
 
         while let Some(ev) = src.next().await {
-            write(&disable_path, if ev { b"0" } else { b"1" }).unwrap();
-
-            if !ev {
-                device.set(None);
+            if ev && thermal_lock.try_get() == Some(ThermalLock::Locked) {
+                warn!("Not switching on USB {name} while the TAC is too hot");
+                overtemperature.set(true);
+                status.set(false);
+                continue;
             }
 
-            status.set(ev);
+            set_powered(&disable_path, &status, &device, ev);
+            overtemperature.set_if_changed(false);
+        }
+    });
+
+    let status = port.status.clone();
+    let device = port.device.clone();
+    let thermal_lock = port.thermal_lock.clone();
+    let overtemperature = port.overtemperature.clone();
+    let disable_path = Path::new(base).join("disable");
+
+    // Spawn a task that switches the port off when the thermal protection
+    // trips and on again once the TAC has cooled down, unless it was switched
+    // on or off by someone else in the meantime.
+    spawn(async move {
+        let (mut locks, _) = thermal_lock.subscribe_unbounded();
+
+        while let Some(lock) = locks.next().await {
+            match lock {
+                ThermalLock::Locked => {
+                    if status.try_get() == Some(true) {
+                        set_powered(&disable_path, &status, &device, false);
+                        overtemperature.set(true);
+                    }
+                }
+                ThermalLock::Released => overtemperature.set_if_changed(false),
+                ThermalLock::Restore => {
+                    if overtemperature.try_get() == Some(true) {
+                        set_powered(&disable_path, &status, &device, true);
+                        overtemperature.set(false);
+                    }
+                }
+            }
         }
     });
 
@@ -272,7 +324,7 @@ fn handle_port(bb: &mut BrokerBuilder, name: &'static str, base: &'static str) -
 
             device.set_if_changed(dev_info);
 
-            sleep(POLL_INTERVAL).await;
+            sleep(poll_interval).await;
         }
     });
 
@@ -281,6 +333,7 @@ fn handle_port(bb: &mut BrokerBuilder, name: &'static str, base: &'static str) -
 
 fn handle_overloads(
     bb: &mut BrokerBuilder,
+    poll_interval: Duration,
     total: CalibratedChannel,
     port1: CalibratedChannel,
     port2: CalibratedChannel,
@@ -301,7 +354,7 @@ fn handle_overloads(
 
             overload_task.set_if_changed(overloaded_port);
 
-            sleep(POLL_INTERVAL).await;
+            sleep(poll_interval).await;
         }
     });
 
@@ -311,20 +364,20 @@ fn handle_overloads(
 impl UsbHub {
     pub fn new(
         bb: &mut BrokerBuilder,
+        config: &UsbHubConfig,
         total: CalibratedChannel,
         port1: CalibratedChannel,
         port2: CalibratedChannel,
         port3: CalibratedChannel,
     ) -> Self {
-        let overload = handle_overloads(bb, total, port1, port2, port3);
-
-        let mut ports = PORTS.iter().map(|(name, base)| handle_port(bb, name, base));
+        let poll_interval = config.poll_interval();
+        let overload = handle_overloads(bb, poll_interval, total, port1, port2, port3);
 
         Self {
             overload,
-            port1: ports.next().unwrap(),
-            port2: ports.next().unwrap(),
-            port3: ports.next().unwrap(),
+            port1: handle_port(bb, "port1", &config.port1, poll_interval),
+            port2: handle_port(bb, "port2", &config.port2, poll_interval),
+            port3: handle_port(bb, "port3", &config.port3, poll_interval),
         }
     }
 }
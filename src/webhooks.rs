@@ -0,0 +1,488 @@
+// This file is part of tacd, the LXA TAC system daemon
+// Copyright (C) 2023 Pengutronix e.K.
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use async_std::channel::{unbounded, Receiver, Sender};
+use async_std::future::timeout;
+use async_std::prelude::*;
+use async_std::sync::Arc;
+use async_std::task::{sleep, spawn, JoinHandle};
+use futures_lite::future::race;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::broker::{AnyTopic, BrokerBuilder, Topic};
+use crate::config::{RetryConfig, WebhookConfig};
+use crate::measurement::Timestamp;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+// Drop the oldest events if the receiver can not keep up,
+// e.g. because it is down for a longer time.
+const MAX_QUEUED: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WebhookStatus {
+    pub name: String,
+    pub delivered: u64,
+    /// Events that could not be delivered even after retrying
+    pub failed: u64,
+    /// Events that were dropped because too many were queued
+    pub dropped: u64,
+    /// Events waiting to be delivered
+    pub pending: u64,
+    pub last_delivery: Option<Timestamp>,
+    pub last_error: Option<String>,
+}
+
+/// POST a JSON body to an URL once
+async fn post(url: &str, body: &Value) -> Result<(), String> {
+    let req = surf::post(url)
+        .body_json(body)
+        .map_err(|e| format!("Failed to encode request: {e}"))?;
+
+    match timeout(REQUEST_TIMEOUT, req).await {
+        Ok(Ok(res)) if res.status().is_success() => Ok(()),
+        Ok(Ok(res)) => Err(format!("{url} returned {}", res.status())),
+        Ok(Err(e)) => Err(format!("Request to {url} failed: {e}")),
+        Err(_) => Err(format!("Request to {url} timed out")),
+    }
+}
+
+/// POST a JSON body to an URL, retrying with an exponential backoff
+///
+/// Returns the error of the last attempt if all of them failed.
+pub async fn deliver(url: &str, body: &Value, retry: &RetryConfig) -> Result<(), String> {
+    let mut delay = retry.delay();
+    let mut attempt = 0;
+
+    loop {
+        let err = match post(url, body).await {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+
+        if attempt >= retry.retries {
+            return Err(err);
+        }
+
+        attempt += 1;
+
+        warn!("{err}. Retrying in {}ms", delay.as_millis());
+
+        sleep(delay).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+    }
+}
+
+struct Event {
+    topic: String,
+    value: Value,
+    timestamp: Timestamp,
+}
+
+struct Webhook {
+    index: usize,
+    config: WebhookConfig,
+    topics: Vec<Arc<dyn AnyTopic>>,
+}
+
+impl Webhook {
+    /// Subscribe to the topics and start delivering their changes
+    ///
+    /// The subscriptions are set up before this returns, so that no
+    /// changes are missed.
+    fn run(
+        self,
+        hostname: Arc<Topic<String>>,
+        status: Arc<Topic<Vec<WebhookStatus>>>,
+        shutdown: Receiver<()>,
+    ) -> JoinHandle<()> {
+        let (tx, mut rx) = unbounded();
+        let (events_tx, events_rx) = unbounded();
+
+        // Only changes are reported, not the values the topics have
+        // when the tacd starts.
+        let handles: Vec<_> = self
+            .topics
+            .iter()
+            .map(|t| t.clone().subscribe_as_bytes(tx.clone(), false))
+            .collect();
+
+        // Take the timestamp when the event happens and not when it is
+        // delivered, which may be a lot later if retries are needed.
+        // Stop taking new events on shutdown, so that the queue drains and
+        // the delivery loop below finishes.
+        spawn(async move {
+            let _handles = handles;
+
+            let shutdown = async {
+                let _ = shutdown.recv().await;
+                None
+            };
+
+            let mut shutdown = Box::pin(shutdown);
+
+            while let Some((topic, msg)) = race(rx.next(), &mut shutdown).await {
+                let value = match serde_json::from_slice(&msg) {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                };
+
+                let event = Event {
+                    topic: topic.to_string(),
+                    value,
+                    timestamp: Timestamp::now(),
+                };
+
+                if events_tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        spawn(self.deliver(hostname, status, events_rx))
+    }
+
+    async fn deliver(
+        self,
+        hostname: Arc<Topic<String>>,
+        status: Arc<Topic<Vec<WebhookStatus>>>,
+        events_rx: Receiver<Event>,
+    ) {
+        let update_status = |cb: &dyn Fn(&mut WebhookStatus)| {
+            status.modify(|prev| {
+                let mut list = prev?;
+                cb(list.get_mut(self.index)?);
+                Some(list)
+            })
+        };
+
+        let mut queue = VecDeque::new();
+
+        loop {
+            if queue.is_empty() {
+                match events_rx.recv().await {
+                    Ok(event) => queue.push_back(event),
+                    Err(_) => break,
+                }
+            }
+
+            while let Ok(event) = events_rx.try_recv() {
+                queue.push_back(event);
+            }
+
+            let dropped = queue.len().saturating_sub(MAX_QUEUED);
+            queue.drain(..dropped);
+
+            let event = match queue.pop_front() {
+                Some(event) => event,
+                None => continue,
+            };
+
+            let pending = queue.len() as u64;
+
+            update_status(&|s| {
+                s.dropped += dropped as u64;
+                s.pending = pending + 1;
+            });
+
+            let body = json!({
+                "topic": event.topic,
+                "value": event.value,
+                "timestamp": event.timestamp,
+                "hostname": hostname.try_get().unwrap_or_default(),
+            });
+
+            let res = deliver(&self.config.url, &body, &self.config.retry).await;
+
+            if let Err(e) = &res {
+                warn!("Failed to deliver webhook {}: {e}", self.config.name);
+            }
+
+            update_status(&|s| {
+                s.pending = pending;
+
+                match &res {
+                    Ok(()) => {
+                        s.delivered += 1;
+                        s.last_delivery = Some(Timestamp::now());
+                    }
+                    Err(e) => {
+                        s.failed += 1;
+                        s.last_error = Some(e.clone());
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// The running delivery tasks of the webhooks
+///
+/// Dropping this stops taking new events, just like calling shutdown().
+#[must_use]
+pub struct Deliveries {
+    shutdown: Sender<()>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Deliveries {
+    /// Stop taking new events and wait until the queued ones are delivered
+    pub async fn shutdown(self) {
+        // Closing the channel wakes up all webhooks at once
+        self.shutdown.close();
+
+        for task in self.tasks {
+            task.await;
+        }
+    }
+}
+
+/// Notify other systems (like a CI) about changes of topics via HTTP POST
+pub struct Webhooks {
+    status: Arc<Topic<Vec<WebhookStatus>>>,
+    hostname: Arc<Topic<String>>,
+    webhooks: Vec<WebhookConfig>,
+}
+
+impl Webhooks {
+    pub fn new(
+        bb: &mut BrokerBuilder,
+        webhooks: &[WebhookConfig],
+        hostname: Arc<Topic<String>>,
+    ) -> Self {
+        let initial = webhooks
+            .iter()
+            .map(|webhook| WebhookStatus {
+                name: webhook.name.clone(),
+                delivered: 0,
+                failed: 0,
+                dropped: 0,
+                pending: 0,
+                last_delivery: None,
+                last_error: None,
+            })
+            .collect();
+
+        Self {
+            status: bb.topic_ro("/v1/tac/webhooks", Some(initial)),
+            hostname,
+            webhooks: webhooks.to_vec(),
+        }
+    }
+
+    /// Start delivering events
+    ///
+    /// This has to happen after the broker is built, as webhooks can refer
+    /// to any readable topic.
+    pub fn run(self, topics: &[Arc<dyn AnyTopic>]) -> Deliveries {
+        let (shutdown, shutdown_rx) = unbounded();
+        let mut tasks = Vec::new();
+
+        for (index, config) in self.webhooks.iter().enumerate() {
+            let matching: Vec<_> = topics
+                .iter()
+                .filter(|t| {
+                    let path: &str = t.path();
+                    t.web_readable() && config.topics.iter().any(|p| p == path)
+                })
+                .cloned()
+                .collect();
+
+            let missing: Vec<_> = config
+                .topics
+                .iter()
+                .filter(|p| {
+                    !matching.iter().any(|t| {
+                        let path: &str = t.path();
+                        path == p.as_str()
+                    })
+                })
+                .cloned()
+                .collect();
+
+            // Deliver events for the topics that do exist anyways,
+            // but make it visible that the config contains a mistake.
+            if !missing.is_empty() {
+                let msg = format!("There are no readable topics {}", missing.join(", "));
+
+                warn!("Webhook {}: {msg}", config.name);
+
+                self.status.modify(|prev| {
+                    let mut list = prev?;
+                    list.get_mut(index)?.last_error = Some(msg);
+                    Some(list)
+                });
+            }
+
+            let webhook = Webhook {
+                index,
+                config: config.clone(),
+                topics: matching,
+            };
+
+            tasks.push(webhook.run(
+                self.hostname.clone(),
+                self.status.clone(),
+                shutdown_rx.clone(),
+            ));
+        }
+
+        Deliveries { shutdown, tasks }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    use async_std::future::timeout;
+    use async_std::prelude::*;
+    use async_std::sync::{Arc, Mutex};
+    use async_std::task::{block_on, spawn};
+    use serde_json::Value;
+
+    use super::Webhooks;
+    use crate::broker::{AnyTopic, BrokerBuilder};
+    use crate::config::{RetryConfig, WebhookConfig};
+
+    /// Start a local HTTP server that fails the first `failures` requests
+    ///
+    /// Returns the URL to POST to and the bodies of the accepted requests.
+    fn stand_in(failures: usize) -> (String, Arc<Mutex<Vec<Value>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let attempts = Arc::new(Mutex::new(0));
+
+        let mut server = tide::new();
+        let server_received = received.clone();
+
+        server.at("/hook").post(move |mut req: tide::Request<()>| {
+            let received = server_received.clone();
+            let attempts = attempts.clone();
+
+            async move {
+                let mut attempts = attempts.lock().await;
+                *attempts += 1;
+
+                if *attempts <= failures {
+                    return Ok(tide::Response::new(500));
+                }
+
+                received.lock().await.push(req.body_json().await?);
+
+                Ok(tide::Response::new(204))
+            }
+        });
+
+        spawn(server.listen(listener));
+
+        (url, received)
+    }
+
+    fn webhook(name: &str, url: &str, retries: u32) -> WebhookConfig {
+        WebhookConfig {
+            name: name.to_string(),
+            url: url.to_string(),
+            topics: vec!["/v1/watched".to_string(), "/v1/missing".to_string()],
+            retry: RetryConfig {
+                retries,
+                delay_ms: 10,
+            },
+        }
+    }
+
+    #[test]
+    fn delivery() {
+        let (url, received) = stand_in(2);
+
+        // Nothing listens on this port once the listener is dropped
+        let unreachable = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/hook", listener.local_addr().unwrap())
+        };
+
+        let mut bb = BrokerBuilder::new();
+
+        let watched = bb.topic_ro("/v1/watched", Some(1u32));
+        let hostname = bb.topic_ro("/v1/hostname", Some("lxatac-00001".to_string()));
+
+        let webhooks = Webhooks::new(
+            &mut bb,
+            &[
+                webhook("ci", &url, 3),
+                webhook("unreachable", &unreachable, 1),
+            ],
+            hostname,
+        );
+        let (mut status_events, _) = webhooks.status.clone().subscribe_unbounded();
+
+        let topics: Vec<Arc<dyn AnyTopic>> = vec![watched.clone()];
+        let deliveries = webhooks.run(&topics);
+
+        watched.set(2);
+
+        // The first two attempts fail and are retried
+        let status = block_on(timeout(Duration::from_secs(5), async {
+            loop {
+                let status = status_events.next().await.unwrap();
+
+                if status[0].delivered == 1 && status[1].failed == 1 {
+                    break status;
+                }
+            }
+        }))
+        .expect("Timed out waiting for the deliveries");
+
+        // The initial value is not reported, only changes are
+        let received = block_on(received.lock()).clone();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["topic"], "/v1/watched");
+        assert_eq!(received[0]["value"], 2);
+        assert_eq!(received[0]["hostname"], "lxatac-00001");
+        assert!(received[0]["timestamp"].is_f64());
+
+        assert_eq!(status[0].delivered, 1);
+        assert_eq!(status[0].failed, 0);
+        assert_eq!(status[0].pending, 0);
+        assert!(status[0].last_delivery.is_some());
+        assert_eq!(
+            status[0].last_error.as_deref(),
+            Some("There are no readable topics /v1/missing")
+        );
+
+        assert_eq!(status[1].delivered, 0);
+        assert_eq!(status[1].failed, 1);
+        assert!(status[1]
+            .last_error
+            .as_deref()
+            .unwrap()
+            .starts_with("Request to http://127.0.0.1"));
+
+        // Nothing is queued anymore, so shutting down does not take long
+        block_on(timeout(Duration::from_secs(5), deliveries.shutdown()))
+            .expect("Timed out waiting for the shutdown");
+    }
+}
@@ -34,6 +34,19 @@ type Measurement = {
 
 type IpList = Array<string>;
 
+type IpAddress = {
+  address: string;
+  prefix: number;
+};
+
+type IpInfo = {
+  ipv4: Array<IpAddress>;
+  ipv6: Array<IpAddress>;
+  gateway4: string | null;
+  gateway6: string | null;
+  dns: Array<string>;
+};
+
 type LinkStatus = {
   speed: number;
   carrier: boolean;
@@ -240,6 +253,38 @@ export default function DashboardTac() {
               }}
             />
           </Box>
+          <Box>
+            <Box variant="awsui-key-label">IPv6 Addresses</Box>
+            <MqttBox
+              topic="/v1/tac/network/ip/tac-bridge"
+              format={(obj: IpInfo) => {
+                return obj.ipv6.length < 1
+                  ? "-"
+                  : obj.ipv6
+                      .map((a) => `${a.address}/${a.prefix}`)
+                      .join(", ");
+              }}
+            />
+          </Box>
+          <Box>
+            <Box variant="awsui-key-label">Default Gateway</Box>
+            <MqttBox
+              topic="/v1/tac/network/ip/tac-bridge"
+              format={(obj: IpInfo) => {
+                const gws = [obj.gateway4, obj.gateway6].filter((g) => g);
+                return gws.length < 1 ? "-" : gws.join(", ");
+              }}
+            />
+          </Box>
+          <Box>
+            <Box variant="awsui-key-label">DNS Servers</Box>
+            <MqttBox
+              topic="/v1/tac/network/ip/tac-bridge"
+              format={(obj: IpInfo) => {
+                return obj.dns.length < 1 ? "-" : obj.dns.join(", ");
+              }}
+            />
+          </Box>
         </ColumnLayout>
       </Container>
     </SpaceBetween>
@@ -105,6 +105,7 @@ enum OutputState {
   OverCurrent = "OverCurrent",
   OverVoltage = "OverVoltage",
   RealtimeViolation = "RealtimeViolation",
+  OverTemperature = "OverTemperature",
 }
 
 type Duration = {
@@ -593,6 +594,9 @@ export function PowerFailNotification() {
     case OutputState.RealtimeViolation:
       reason = "a realtime violation";
       break;
+    case OutputState.OverTemperature:
+      reason = "the TAC being too hot";
+      break;
   }
 
   return (